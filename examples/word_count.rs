@@ -1,120 +1,49 @@
+use std::convert::Infallible;
 use std::path::PathBuf;
-use std::fs::{ File, OpenOptions };
-use std::io::{ BufReader, BufRead, Write };
+use std::io;
 use std::sync::Arc;
-use std::collections::HashMap;
 
 extern crate mapreduce;
+use mapreduce::functions::{ Mapper, Reducer, Combiner, Emitter };
 use mapreduce::master::Master;
+use mapreduce::input::Record;
 
-fn split_input_file(working_directory: &PathBuf, input_file: &PathBuf) -> Vec<PathBuf> {
-    let f = OpenOptions::new()
-                        .read(true)
-                        .open(&input_file)
-                        .unwrap();
+struct WordCount;
 
-    let max_lines = 500;
-    let mut count = 0;
-    let mut input = vec![];
-    let mut input_number = 1;
-    for line in BufReader::new(f).lines().map(|l| l.unwrap_or("".to_string())) {
-        input.push(line);
-        count += 1;
-        if count == max_lines {
-            write_input_file(&working_directory, input_number, &mut input);
-            input_number += 1;
-            count = 0;
-        }
-    }
-
-    let mut split = working_directory.clone();
-    split.push(format!("input_{}", input_number));
-    write_input_file(&working_directory, input_number, &mut input);
-
-    (1..(input_number + 1)).map(|i| {
-        let mut path = working_directory.clone();
-        path.push(format!("input_{}", i));
-        path
-    })
-    .collect()
-}
+impl Mapper for WordCount {
+    type Key = String;
+    type Value = u64;
+    type Error = io::Error;
 
-fn write_input_file(working_directory: &PathBuf, index: usize, contents: &mut Vec<String>) {
-    let mut path = working_directory.clone();
-    path.push(format!("input_{}", index));
-    let mut input_file = OpenOptions::new()
-                                     .write(true)
-                                     .create(true)
-                                     .open(&path)
-                                     .unwrap();
-    for line in contents {
-        let _ = input_file.write_all(line.as_bytes());
-    }
-}
-
-fn map_fn(input: BufReader<File>) -> Vec<String> {
-    let mut word_counts: HashMap<String, i32> = HashMap::new();
-    for line in input.lines().map(|l| l.unwrap_or("".to_string())) {
-        let words = line.split(" ");
-        for word in words {
-            *word_counts.entry(word.to_lowercase()).or_insert(0) += 1;
+    fn map(&self, records: &mut dyn Iterator<Item = Record>, output: &mut Emitter<String, u64>) -> io::Result<()> {
+        for record in records {
+            let line = String::from_utf8(record.bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            for word in line.split_whitespace() {
+                output.emit(word.to_lowercase(), 1);
+            }
         }
+        Ok(())
     }
+}
 
-    let mut words = word_counts.keys().collect::<Vec<&String>>();
-    words.sort();
-
-    let mut a_to_e = vec![];
-    let mut f_to_k = vec![];
-    let mut l_to_q = vec![];
-    let mut r_to_t = vec![];
-    let mut u_to_z = vec![];
+impl Reducer for WordCount {
+    type Key = String;
+    type Value = u64;
+    type Output = u64;
+    type Error = Infallible;
 
-    for word in words {
-        if let Some(first_letter) = word.chars().nth(0) {
-            let line = format!("{}={}", word.clone(), word_counts.get(word).unwrap());
-            match first_letter {
-                'a' ... 'e' => a_to_e.push(line),
-                'f' ... 'k' => f_to_k.push(line),
-                'l' ... 'q' => l_to_q.push(line),
-                'r' ... 't' => r_to_t.push(line),
-                'u' ... 'z' => u_to_z.push(line),
-                _ => {}
-            }
-        }
+    fn reduce(&self, _word: &String, counts: &mut dyn Iterator<Item = u64>) -> Result<u64, Infallible> {
+        Ok(counts.sum())
     }
-
-    vec![a_to_e, f_to_k, l_to_q, r_to_t, u_to_z]
-        .iter()
-        .map(|group| {
-            group.iter()
-                 .fold("".to_string(), |mut contents, line| {
-                     contents.push_str(&line);
-                     contents.push_str("\n");
-                     contents.clone()
-                 })
-                 .to_string()
-        })
-        .collect()
 }
 
-fn reduce_fn(input: Vec<BufReader<File>>) -> String {
-    let mut word_counts: HashMap<String, i32> = HashMap::new();
-    for file in input {
-        for line in file.lines().map(|l| l.unwrap_or("".to_string())) {
-            let mut word_count = line.split("=");
-            let word = word_count.next().unwrap();
-            let count = i32::from_str_radix(word_count.next().unwrap(), 10).unwrap();
-            word_counts.insert(word.to_string(), count);
-        }
-    }
+impl Combiner for WordCount {
+    type Key = String;
+    type Value = u64;
 
-    let mut words = word_counts.keys().collect::<Vec<&String>>();
-    words.sort();
-    words.iter().fold("".to_string(), |mut results, &word| {
-        results.push_str(&format!("{}={}\n", word, word_counts.get(word).unwrap()));
-        results
-    })
+    fn combine(&self, _word: &String, counts: &mut dyn Iterator<Item = u64>) -> u64 {
+        counts.sum()
+    }
 }
 
 fn main() {
@@ -122,14 +51,17 @@ fn main() {
     let mut book = working_directory.clone();
     book.push("war_of_the_worlds.txt");
 
-    let input_files = split_input_file(&working_directory, &book);
-    let map = Arc::new(map_fn);
-    let reduce = Arc::new(reduce_fn);
-
-    let master = Master::new(working_directory.clone(),
-                             input_files,
-                             map,
-                             reduce
-                            );
-    let result_files = master.run(4);
+    let word_count = Arc::new(WordCount);
+
+    let master = Master::with_functions(working_directory.clone(),
+                                        vec![book],
+                                        word_count.clone(),
+                                        word_count.clone(),
+                                        5
+                                       )
+        .with_combiner(word_count)
+        .with_split_size(64 * 1024);
+    if let Err(e) = master.run(4) {
+        println!("word count failed: {}", e);
+    }
 }
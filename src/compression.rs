@@ -0,0 +1,205 @@
+use std::fs::File;
+use std::io;
+use std::io::{ BufRead, BufReader, Write };
+use std::path::Path;
+
+#[cfg(feature = "gzip")]
+use std::io::Read;
+
+#[cfg(feature = "gzip")]
+use flate2::Compression;
+#[cfg(feature = "gzip")]
+use flate2::read::{ GzDecoder, MultiGzDecoder };
+#[cfg(feature = "gzip")]
+use flate2::write::GzEncoder;
+
+/// How intermediate runs or result files are compressed. Codecs other than
+/// `None` are behind the `gzip` and `zstd` cargo features.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Codec {
+    #[default]
+    None,
+    #[cfg(feature = "gzip")]
+    Gzip,
+    #[cfg(feature = "zstd")]
+    Zstd
+}
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+impl Codec {
+    /// The codec's id in a sorted run's header.
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Codec::None => 0,
+            #[cfg(feature = "gzip")]
+            Codec::Gzip => 1,
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => 2
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> io::Result<Codec> {
+        match id {
+            0 => Ok(Codec::None),
+            #[cfg(feature = "gzip")]
+            1 => Ok(Codec::Gzip),
+            #[cfg(feature = "zstd")]
+            2 => Ok(Codec::Zstd),
+            #[cfg(not(feature = "gzip"))]
+            1 => Err(unsupported("gzip")),
+            #[cfg(not(feature = "zstd"))]
+            2 => Err(unsupported("zstd")),
+            id => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown codec {}", id)))
+        }
+    }
+
+    /// The suffix added to the names of result files written with this codec.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Codec::None => "",
+            #[cfg(feature = "gzip")]
+            Codec::Gzip => ".gz",
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => ".zst"
+        }
+    }
+
+    pub(crate) fn compress(self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut encoder = self.encoder(vec![])?;
+        encoder.write_all(bytes)?;
+        encoder.finish()
+    }
+
+    pub(crate) fn decompress(self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decompressed = vec![];
+        match self {
+            Codec::None => decompressed.extend_from_slice(bytes),
+            #[cfg(feature = "gzip")]
+            Codec::Gzip => {
+                GzDecoder::new(bytes).read_to_end(&mut decompressed)?;
+            }
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => {
+                decompressed = ::zstd::decode_all(bytes)?;
+            }
+        }
+        Ok(decompressed)
+    }
+
+    /// Wraps `writer` so that everything written to it is compressed.
+    pub(crate) fn encoder<W: Write>(self, writer: W) -> io::Result<Encoder<W>> {
+        Ok(match self {
+            Codec::None => Encoder::Plain(writer),
+            #[cfg(feature = "gzip")]
+            Codec::Gzip => Encoder::Gzip(GzEncoder::new(writer, Compression::default())),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => Encoder::Zstd(::zstd::Encoder::new(writer, 0)?)
+        })
+    }
+}
+
+/// A writer compressing with one of the codecs. `finish` must be called to
+/// complete the compressed stream.
+pub(crate) enum Encoder<W: Write> {
+    Plain(W),
+    #[cfg(feature = "gzip")]
+    Gzip(GzEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(::zstd::Encoder<'static, W>)
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::Plain(writer) => Ok(writer),
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder.finish(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.finish()
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        match *self {
+            Encoder::Plain(ref mut writer) => writer.write(bytes),
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(ref mut encoder) => encoder.write(bytes),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(ref mut encoder) => encoder.write(bytes)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Encoder::Plain(ref mut writer) => writer.flush(),
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(ref mut encoder) => encoder.flush(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(ref mut encoder) => encoder.flush()
+        }
+    }
+}
+
+/// Opens a result file for reading, decompressing it if it starts with the
+/// gzip or zstd magic number.
+pub fn open_result(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    decoder(BufReader::new(File::open(path)?))
+}
+
+/// Detects the codec of `reader` from its first bytes and decompresses it.
+pub fn decoder<R: BufRead + 'static>(mut reader: R) -> io::Result<Box<dyn BufRead>> {
+    let head = reader.fill_buf()?;
+    let (gzip, zstd) = (head.starts_with(GZIP_MAGIC), head.starts_with(ZSTD_MAGIC));
+    if gzip {
+        #[cfg(feature = "gzip")]
+        return Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))));
+        #[cfg(not(feature = "gzip"))]
+        return Err(unsupported("gzip"));
+    }
+    if zstd {
+        #[cfg(feature = "zstd")]
+        return Ok(Box::new(BufReader::new(::zstd::Decoder::with_buffer(reader)?)));
+        #[cfg(not(feature = "zstd"))]
+        return Err(unsupported("zstd"));
+    }
+    Ok(Box::new(reader))
+}
+
+#[cfg(not(all(feature = "gzip", feature = "zstd")))]
+fn unsupported(codec: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported,
+                   format!("data is compressed with {}, which this build does not support", codec))
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{ Cursor, Read };
+    use super::{ Codec, decoder };
+
+    fn codecs() -> Vec<Codec> {
+        vec![Codec::None,
+             #[cfg(feature = "gzip")]
+             Codec::Gzip,
+             #[cfg(feature = "zstd")]
+             Codec::Zstd]
+    }
+
+    #[test]
+    fn every_codec_round_trips_and_is_detected() {
+        let text = "the martians landed at horsell common\n".repeat(100);
+        for codec in codecs() {
+            let compressed = codec.compress(text.as_bytes()).unwrap();
+            assert_eq!(codec.decompress(&compressed).unwrap(), text.as_bytes(), "{:?}", codec);
+            assert_eq!(Codec::from_id(codec.id()).unwrap(), codec);
+
+            let mut detected = String::new();
+            decoder(Cursor::new(compressed)).unwrap().read_to_string(&mut detected).unwrap();
+            assert_eq!(detected, text, "{:?}", codec);
+        }
+        assert!(Codec::from_id(9).is_err());
+    }
+}
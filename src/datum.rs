@@ -0,0 +1,204 @@
+use std::str;
+
+/// A key or value the framework can write to and read back from
+/// intermediate files.
+pub trait Datum: Sized + Send + 'static {
+    fn encode(&self, buffer: &mut Vec<u8>);
+    fn decode(bytes: &[u8]) -> Option<Self>;
+
+    /// The length of the encoded datum, used to account for map output held
+    /// in memory. Defaults to encoding it.
+    fn encoded_len(&self) -> usize {
+        let mut buffer = vec![];
+        self.encode(&mut buffer);
+        buffer.len()
+    }
+
+    /// Writes the datum as text, for the text output formats. Defaults to the
+    /// encoded bytes.
+    fn write_text(&self, buffer: &mut Vec<u8>) {
+        self.encode(buffer)
+    }
+
+    /// Writes the datum as a JSON value. Defaults to its text as a JSON string.
+    fn write_json(&self, buffer: &mut Vec<u8>) {
+        let mut text = vec![];
+        self.write_text(&mut text);
+        write_json_string(&String::from_utf8_lossy(&text), buffer)
+    }
+}
+
+/// Writes `text` as a quoted, escaped JSON string.
+fn write_json_string(text: &str, buffer: &mut Vec<u8>) {
+    buffer.push(b'"');
+    for c in text.chars() {
+        match c {
+            '"' => buffer.extend_from_slice(b"\\\""),
+            '\\' => buffer.extend_from_slice(b"\\\\"),
+            '\n' => buffer.extend_from_slice(b"\\n"),
+            '\r' => buffer.extend_from_slice(b"\\r"),
+            '\t' => buffer.extend_from_slice(b"\\t"),
+            c if (c as u32) < 0x20 => buffer.extend_from_slice(format!("\\u{:04x}", c as u32).as_bytes()),
+            c => buffer.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes())
+        }
+    }
+    buffer.push(b'"');
+}
+
+impl Datum for String {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(self.as_bytes());
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.len()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        str::from_utf8(bytes).ok().map(|s| s.to_string())
+    }
+}
+
+impl Datum for Vec<u8> {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(self);
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.len()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        Some(bytes.to_vec())
+    }
+}
+
+impl Datum for bool {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        buffer.push(*self as u8);
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            [0] => Some(false),
+            [1] => Some(true),
+            _ => None
+        }
+    }
+
+    fn write_text(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(self.to_string().as_bytes());
+    }
+
+    fn write_json(&self, buffer: &mut Vec<u8>) {
+        self.write_text(buffer)
+    }
+}
+
+macro_rules! integer_datum {
+    ($($t:ty),*) => {
+        $(
+            impl Datum for $t {
+                fn encode(&self, buffer: &mut Vec<u8>) {
+                    buffer.extend_from_slice(&self.to_be_bytes());
+                }
+
+                fn decode(bytes: &[u8]) -> Option<Self> {
+                    let mut raw = [0; ::std::mem::size_of::<$t>()];
+                    if bytes.len() != raw.len() {
+                        return None;
+                    }
+                    raw.copy_from_slice(bytes);
+                    Some(<$t>::from_be_bytes(raw))
+                }
+
+                fn encoded_len(&self) -> usize {
+                    ::std::mem::size_of::<$t>()
+                }
+
+                fn write_text(&self, buffer: &mut Vec<u8>) {
+                    buffer.extend_from_slice(self.to_string().as_bytes());
+                }
+
+                fn write_json(&self, buffer: &mut Vec<u8>) {
+                    self.write_text(buffer)
+                }
+            }
+        )*
+    }
+}
+
+integer_datum!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+impl Datum for usize {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        (*self as u64).encode(buffer)
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        u64::decode(bytes).map(|n| n as usize)
+    }
+
+    fn encoded_len(&self) -> usize {
+        8
+    }
+
+    fn write_text(&self, buffer: &mut Vec<u8>) {
+        (*self as u64).write_text(buffer)
+    }
+
+    fn write_json(&self, buffer: &mut Vec<u8>) {
+        (*self as u64).write_json(buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Datum;
+
+    fn round_trip<T: Datum>(value: &T) -> Option<T> {
+        let mut buffer = vec![];
+        value.encode(&mut buffer);
+        assert_eq!(value.encoded_len(), buffer.len());
+        T::decode(&buffer)
+    }
+
+    #[test]
+    fn datums_round_trip() {
+        assert_eq!(round_trip(&"war of the worlds".to_string()), Some("war of the worlds".to_string()));
+        assert_eq!(round_trip(&vec![0u8, 255, 7]), Some(vec![0u8, 255, 7]));
+        assert_eq!(round_trip(&true), Some(true));
+        assert_eq!(round_trip(&-42i32), Some(-42i32));
+        assert_eq!(round_trip(&u64::MAX), Some(u64::MAX));
+        assert_eq!(round_trip(&17usize), Some(17usize));
+    }
+
+    fn text<T: Datum>(value: &T) -> String {
+        let mut buffer = vec![];
+        value.write_text(&mut buffer);
+        String::from_utf8(buffer).unwrap()
+    }
+
+    fn json<T: Datum>(value: &T) -> String {
+        let mut buffer = vec![];
+        value.write_json(&mut buffer);
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn datums_render_as_text_and_json() {
+        assert_eq!(text(&"tripod".to_string()), "tripod");
+        assert_eq!(text(&-42i32), "-42");
+        assert_eq!(text(&true), "true");
+        assert_eq!(json(&"the \"heat\" ray\n".to_string()), "\"the \\\"heat\\\" ray\\n\"");
+        assert_eq!(json(&17usize), "17");
+        assert_eq!(json(&false), "false");
+    }
+
+    #[test]
+    fn decoding_rejects_malformed_bytes() {
+        assert_eq!(u32::decode(&[1, 2, 3]), None);
+        assert_eq!(bool::decode(&[2]), None);
+        assert_eq!(String::decode(&[0xff, 0xfe]), None);
+    }
+}
@@ -0,0 +1,97 @@
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use task::Task;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MapReduceError {
+    /// An input file, or a map output read by a reduce task, could not be
+    /// opened or read.
+    ReadInput { task: Task, attempt: u32, path: PathBuf, reason: String },
+    /// A map output read by a reduce task failed its checksums or did not
+    /// match its index.
+    CorruptIntermediate { task: Task, attempt: u32, path: PathBuf, reason: String },
+    /// A task's output could not be written or committed.
+    WriteOutput { task: Task, attempt: u32, path: PathBuf, reason: String },
+    /// The map or reduce function returned an error.
+    FunctionFailed { task: Task, attempt: u32, message: String },
+    /// A user-supplied function panicked while running the task.
+    Panicked { task: Task, attempt: u32, message: String },
+    /// An attempt ran longer than the task timeout.
+    TimedOut { task: Task, attempt: u32, timeout: Duration },
+    /// The working directory does not exist or is not a directory.
+    InvalidWorkingDirectory { path: PathBuf, reason: String },
+    /// A task failed on every one of its allowed attempts; `cause` is the
+    /// failure of the last one.
+    RetriesExhausted { task: Task, attempts: u32, cause: Box<MapReduceError> },
+    /// A worker could not be started.
+    WorkerFailed { worker: usize, reason: String },
+    /// The worker running an attempt died before reporting its result.
+    WorkerLost { task: Task, attempt: u32, worker: usize, reason: String },
+    /// A function or format named by a master could not be built from a
+    /// `Registry`.
+    InvalidFunction { name: String, reason: String },
+    /// The master's settings cannot run a job, such as zero reduce
+    /// partitions.
+    InvalidConfiguration { reason: String },
+    /// A worker's connection to its master failed, or the master did not
+    /// follow the protocol.
+    ConnectionFailed { reason: String }
+}
+
+impl fmt::Display for MapReduceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MapReduceError::ReadInput { ref task, attempt, ref path, ref reason } => {
+                write!(f, "{} (attempt {}) could not read {}: {}", task, attempt, path.display(), reason)
+            }
+            MapReduceError::CorruptIntermediate { ref task, attempt, ref path, ref reason } => {
+                write!(f, "{} (attempt {}) found corrupt map output {}: {}", task, attempt, path.display(), reason)
+            }
+            MapReduceError::WriteOutput { ref task, attempt, ref path, ref reason } => {
+                write!(f, "{} (attempt {}) could not write {}: {}", task, attempt, path.display(), reason)
+            }
+            MapReduceError::FunctionFailed { ref task, attempt, ref message } => {
+                write!(f, "{} (attempt {}) failed: {}", task, attempt, message)
+            }
+            MapReduceError::Panicked { ref task, attempt, ref message } => {
+                write!(f, "{} (attempt {}) panicked: {}", task, attempt, message)
+            }
+            MapReduceError::TimedOut { ref task, attempt, timeout } => {
+                write!(f, "{} (attempt {}) timed out after {:?}", task, attempt, timeout)
+            }
+            MapReduceError::InvalidWorkingDirectory { ref path, ref reason } => {
+                write!(f, "invalid working directory {}: {}", path.display(), reason)
+            }
+            MapReduceError::RetriesExhausted { ref task, attempts, ref cause } => {
+                write!(f, "{} failed after {} attempts: {}", task, attempts, cause)
+            }
+            MapReduceError::WorkerFailed { worker, ref reason } => {
+                write!(f, "worker {} could not be started: {}", worker, reason)
+            }
+            MapReduceError::WorkerLost { ref task, attempt, worker, ref reason } => {
+                write!(f, "{} (attempt {}) lost worker {}: {}", task, attempt, worker, reason)
+            }
+            MapReduceError::InvalidFunction { ref name, ref reason } => {
+                write!(f, "cannot use function {}: {}", name, reason)
+            }
+            MapReduceError::InvalidConfiguration { ref reason } => {
+                write!(f, "invalid configuration: {}", reason)
+            }
+            MapReduceError::ConnectionFailed { ref reason } => {
+                write!(f, "connection to the master failed: {}", reason)
+            }
+        }
+    }
+}
+
+impl Error for MapReduceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            MapReduceError::RetriesExhausted { ref cause, .. } => Some(&**cause),
+            _ => None
+        }
+    }
+}
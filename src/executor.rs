@@ -0,0 +1,335 @@
+use std::env;
+#[cfg(unix)]
+use std::ffi::OsString;
+use std::fs::{ remove_file, rename };
+use std::io;
+#[cfg(unix)]
+use std::io::BufReader;
+#[cfg(unix)]
+use std::os::fd::{ AsFd, OwnedFd };
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+#[cfg(unix)]
+use std::path::PathBuf;
+#[cfg(unix)]
+use std::process;
+#[cfg(unix)]
+use std::process::{ Child, Stdio };
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+#[cfg(unix)]
+use chan;
+use chan::{ Sender, Receiver };
+
+use error::MapReduceError;
+#[cfg(unix)]
+use protocol::{ Command, send, receive };
+use registry::JobSpec;
+pub use worker::{ Job, JobResult };
+
+/// Set in the environment of a worker process to its worker id.
+pub const WORKER_ID: &str = "MAPREDUCE_WORKER_ID";
+
+/// How often each worker sends a `JobResult::Heartbeat` while it is alive.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Runs worker `id`, taking jobs from the receiver and reporting on the
+/// sender until the job channel is closed.
+pub type RunWorker = Arc<dyn Fn(usize, Receiver<Job>, Sender<JobResult>) + Send + Sync>;
+
+/// Runs a master's workers. `start` returns once the workers are running;
+/// they keep taking jobs until the job channel is closed. Workers outside
+/// the master's process cannot be handed `run_worker`; they build their
+/// functions from `spec`, which masters built from a `Registry` provide.
+pub trait Executor: Send + Sync {
+    fn start(&self,
+             n_workers: usize,
+             working_directory: &Path,
+             run_worker: RunWorker,
+             spec: Option<&JobSpec>,
+             jobs: Receiver<Job>,
+             results: Sender<JobResult>
+             ) -> Result<(), MapReduceError>;
+
+    /// Commits a map attempt's output for one partition. Defaults to renaming
+    /// it in the working directory; executors whose workers keep their map
+    /// output elsewhere record the move instead.
+    fn commit_map_output(&self, from: &Path, to: &Path) -> io::Result<()> {
+        rename(from, to)
+    }
+
+    /// Removes a map attempt's output that will not be committed.
+    fn discard_map_output(&self, path: &Path) {
+        let _ = remove_file(path);
+    }
+
+    /// Brings a reduce or quarantine file an attempt reported into the working
+    /// directory before it is committed. Defaults to doing nothing, as workers
+    /// write these files there.
+    fn collect_output(&self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Removes a reduce or quarantine file an attempt wrote that will not be
+    /// committed.
+    fn discard_output(&self, path: &Path) {
+        let _ = remove_file(path);
+    }
+
+    /// Whether committed map output is kept by the worker that wrote it, and
+    /// so is lost with it. Defaults to `false`.
+    fn map_output_on_workers(&self) -> bool {
+        false
+    }
+}
+
+/// Runs each worker on a thread of the master's process. This is the
+/// default.
+pub struct ThreadExecutor;
+
+impl Executor for ThreadExecutor {
+    fn start(&self,
+             n_workers: usize,
+             _working_directory: &Path,
+             run_worker: RunWorker,
+             _spec: Option<&JobSpec>,
+             jobs: Receiver<Job>,
+             results: Sender<JobResult>
+             ) -> Result<(), MapReduceError>
+    {
+        for id in 0..n_workers {
+            let run_worker = run_worker.clone();
+            let jobs = jobs.clone();
+            let worker_results = results.clone();
+            let worker = thread::spawn(move || run_worker(id, jobs, worker_results));
+            let results = results.clone();
+            thread::spawn(move || {
+                while !worker.is_finished() {
+                    results.send(JobResult::Heartbeat(id));
+                    thread::sleep(HEARTBEAT_INTERVAL);
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Runs each worker in its own process, so that a worker that crashes or
+/// runs out of memory only loses the attempt it was running. The master
+/// launches copies of its own binary with `WORKER_ID` set and a Unix socket
+/// as standard input; when such a copy calls `Master::run`, or
+/// `Registry::serve_if_worker` for a master built from a registry, it serves
+/// jobs over the socket instead of running the job itself. A worker process that
+/// exits has its attempt reported as `WorkerLost` and is replaced.
+#[cfg(unix)]
+pub struct ProcessExecutor {
+    program: PathBuf,
+    args: Vec<OsString>
+}
+
+#[cfg(unix)]
+impl ProcessExecutor {
+    /// Launches workers as `current_exe` with the master's own arguments.
+    pub fn new() -> io::Result<Self> {
+        Ok(ProcessExecutor { program: env::current_exe()?, args: env::args_os().skip(1).collect() })
+    }
+
+    /// Launches workers with `args` instead of the master's arguments. The
+    /// arguments must bring the worker to the same `Master::run` call, with
+    /// the same functions, as the master, or to `Registry::serve_if_worker`.
+    pub fn with_args<I: IntoIterator<Item = S>, S: Into<OsString>>(mut self, args: I) -> Self {
+        self.args = args.into_iter().map(Into::into).collect();
+        self
+    }
+}
+
+#[cfg(unix)]
+impl Executor for ProcessExecutor {
+    fn start(&self,
+             n_workers: usize,
+             _working_directory: &Path,
+             _run_worker: RunWorker,
+             spec: Option<&JobSpec>,
+             jobs: Receiver<Job>,
+             results: Sender<JobResult>
+             ) -> Result<(), MapReduceError>
+    {
+        for id in 0..n_workers {
+            let launcher = Launcher { program: self.program.clone(), args: self.args.clone(), spec: spec.cloned(), id };
+            let process = launcher.launch(&results)
+                                  .map_err(|e| MapReduceError::WorkerFailed { worker: id, reason: e.to_string() })?;
+            let jobs = jobs.clone();
+            let results = results.clone();
+            thread::spawn(move || launcher.forward(process, jobs, results));
+        }
+        Ok(())
+    }
+}
+
+/// A running worker process and the master's end of its connection. A
+/// thread passes on everything the process reports, signalling `finished`
+/// each time an attempt ends and `lost` once the connection is gone.
+#[cfg(unix)]
+struct Process {
+    child: Child,
+    stream: UnixStream,
+    finished: Receiver<()>,
+    lost: Receiver<io::Error>
+}
+
+#[cfg(unix)]
+struct Launcher {
+    program: PathBuf,
+    args: Vec<OsString>,
+    spec: Option<JobSpec>,
+    id: usize
+}
+
+#[cfg(unix)]
+impl Launcher {
+    /// Starts a worker process with the other end of its connection as its
+    /// standard input, and sends it the spec, if there is one.
+    fn launch(&self, results: &Sender<JobResult>) -> io::Result<Process> {
+        let (mut stream, worker_stream) = UnixStream::pair()?;
+        let child = process::Command::new(&self.program).args(&self.args)
+                                                        .env(WORKER_ID, self.id.to_string())
+                                                        .stdin(Stdio::from(OwnedFd::from(worker_stream)))
+                                                        .stdout(Stdio::null())
+                                                        .spawn()?;
+        if let Some(ref spec) = self.spec {
+            send(&mut stream, &Command::Configure(Box::new(spec.clone())))?;
+        }
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let (finished_send, finished) = chan::async();
+        let (lost_send, lost) = chan::async();
+        let results = results.clone();
+        thread::spawn(move || {
+            let error = loop {
+                match receive(&mut reader) {
+                    Ok(Some(result)) => {
+                        let ends_attempt = !matches!(result, JobResult::Started(..) | JobResult::Heartbeat(_));
+                        results.send(result);
+                        if ends_attempt {
+                            finished_send.send(());
+                        }
+                    }
+                    Ok(None) => break io::Error::new(io::ErrorKind::UnexpectedEof, "worker process closed its connection"),
+                    Err(e) => break e
+                }
+            };
+            lost_send.send(error);
+        });
+        Ok(Process { child, stream, finished, lost })
+    }
+
+    /// Sends jobs to the worker process one at a time, replacing the process
+    /// if it is lost.
+    fn forward(&self, mut process: Process, jobs: Receiver<Job>, results: Sender<JobResult>) {
+        while let Err((job, e)) = supervise(&mut process, &jobs) {
+            let _ = process.child.kill();
+            let reason = match process.child.wait() {
+                Ok(status) => format!("{} (worker process {})", e, status),
+                Err(_) => e.to_string()
+            };
+            if let Some(job) = job {
+                let (task, attempt) = (job.task(), job.attempt());
+                results.send(JobResult::Failed(task, attempt, MapReduceError::WorkerLost { task, attempt, worker: self.id, reason }));
+            }
+            process = match self.launch(&results) {
+                Ok(process) => process,
+                Err(_) => return
+            };
+        }
+        let _ = send(&mut process.stream, &Command::Shutdown);
+        drop(process.stream);
+        let _ = process.child.wait();
+    }
+}
+
+/// Hands a worker process one job at a time until the job channel is closed.
+/// If the process is lost, returns the job it was running, if any.
+#[cfg(unix)]
+fn supervise(process: &mut Process, jobs: &Receiver<Job>) -> Result<(), (Option<Job>, io::Error)> {
+    let lost = process.lost.clone();
+    let finished = process.finished.clone();
+    let closed = || io::Error::new(io::ErrorKind::UnexpectedEof, "worker process closed its connection");
+    loop {
+        let next;
+        chan_select! {
+            jobs.recv() -> job => next = job,
+            lost.recv() -> e => return Err((None, e.unwrap_or_else(closed)))
+        }
+        let job = match next {
+            Some(job) => job,
+            None => return Ok(())
+        };
+        if let Err(e) = send(&mut process.stream, &Command::Assign(job.clone())) {
+            return Err((Some(job), e));
+        }
+        // `finished` is also closed once the process is lost, so a closed
+        // channel means the attempt did not finish.
+        let done;
+        chan_select! {
+            finished.recv() -> signal => done = signal.is_some(),
+            lost.recv() -> e => return Err((Some(job), e.unwrap_or_else(closed)))
+        }
+        if !done {
+            return Err((Some(job), lost.recv().unwrap_or_else(closed)));
+        }
+    }
+}
+
+/// The worker id set by a `ProcessExecutor`, if this process is one of its
+/// workers.
+pub fn worker_id() -> Option<usize> {
+    env::var(WORKER_ID).ok().and_then(|id| id.parse().ok())
+}
+
+/// Runs worker `id` in this process on jobs read from the connection to the
+/// master on standard input, until the master shuts it down or closes the
+/// connection. The worker is built by `build`, from the spec the master sent
+/// if it sent one.
+#[cfg(unix)]
+pub fn serve<F>(id: usize, build: F) -> io::Result<()>
+    where F: FnOnce(Option<JobSpec>) -> Result<RunWorker, MapReduceError>
+{
+    let stream = UnixStream::from(io::stdin().as_fd().try_clone_to_owned()?);
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let (spec, mut next) = match receive(&mut reader)? {
+        Some(Command::Configure(spec)) => (Some(*spec), None),
+        command => (None, command)
+    };
+    let run_worker = build(spec).map_err(|e| io::Error::other(e.to_string()))?;
+    let (job_send, job_recv) = chan::async();
+    let (result_send, result_recv) = chan::async();
+    let heartbeats = result_send.clone();
+    thread::spawn(move || run_worker(id, job_recv, result_send));
+    thread::spawn(move || loop {
+        heartbeats.send(JobResult::Heartbeat(id));
+        thread::sleep(HEARTBEAT_INTERVAL);
+    });
+
+    let mut writer = stream.try_clone()?;
+    thread::spawn(move || {
+        for result in result_recv.iter() {
+            if send(&mut writer, &result).is_err() {
+                break;
+            }
+        }
+    });
+
+    if next.is_none() {
+        next = receive(&mut reader)?;
+    }
+    while let Some(command) = next {
+        match command {
+            Command::Assign(job) => job_send.send(job),
+            Command::Configure(_) => {}
+            Command::Shutdown => break
+        }
+        next = receive(&mut reader)?;
+    }
+    Ok(())
+}
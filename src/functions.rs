@@ -0,0 +1,197 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::{ File, remove_file };
+use std::hash::Hash;
+use std::io::{ self, BufReader, BufWriter, Write };
+use std::path::{ Path, PathBuf };
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+
+use datum::Datum;
+use input::Record;
+
+/// Turns the records of one input split into key/value pairs, passing each
+/// pair to `output` as soon as it is made. The framework serializes the
+/// pairs, partitions them and groups them by key for the reduce phase. An
+/// `Err` rejects the input; the master's `FailurePolicy` decides what happens
+/// to the task. Mappers that cannot fail can use `Infallible` as the error.
+pub trait Mapper: Send + Sync {
+    type Key: Datum + Ord + Hash;
+    type Value: Datum;
+    type Error: Error + Send + 'static;
+
+    fn map(&self,
+           records: &mut dyn Iterator<Item = Record>,
+           output: &mut Emitter<Self::Key, Self::Value>
+           ) -> Result<(), Self::Error>;
+}
+
+/// Where a map call emits its key/value pairs. In a map task the pairs go
+/// straight into the task's output buffer, which spills to disk when full, so
+/// a mapper never has to hold its output in memory.
+pub struct Emitter<'a, K, V> {
+    emit: &'a mut dyn FnMut(K, V)
+}
+
+impl<'a, K, V> Emitter<'a, K, V> {
+    /// An emitter handing every pair to `emit`.
+    pub fn new(emit: &'a mut dyn FnMut(K, V)) -> Self {
+        Emitter { emit }
+    }
+
+    pub fn emit(&mut self, key: K, value: V) {
+        (self.emit)(key, value)
+    }
+}
+
+/// Called once per key, in key order, with every value emitted for that key.
+/// The values are read from the map outputs as the iterator is advanced, so
+/// a key can have more values than fit in memory; values left unread are
+/// skipped. The returned output is written to the partition's result file
+/// with the key, in the master's `OutputFormat`; an `Err` fails the task like
+/// a mapper's.
+pub trait Reducer: Send + Sync {
+    type Key: Datum + Ord;
+    type Value: Datum;
+    type Output: Datum;
+    type Error: Error + Send + 'static;
+
+    fn reduce(&self, key: &Self::Key, values: &mut dyn Iterator<Item = Self::Value>) -> Result<Self::Output, Self::Error>;
+}
+
+/// Pre-aggregates a map task's output: called once per key within each sorted
+/// partition before it is written, and must return a value the reducer would
+/// treat the same as the values it replaces.
+pub trait Combiner: Send + Sync {
+    type Key;
+    type Value;
+
+    fn combine(&self, key: &Self::Key, values: &mut dyn Iterator<Item = Self::Value>) -> Self::Value;
+}
+
+pub type BoxError = Box<dyn Error + Send + Sync>;
+pub type MapFn = Arc<dyn Fn(&mut dyn Iterator<Item = Record>, &mut Emitter<usize, String>) -> Result<(), BoxError> + Send + Sync>;
+pub type ReduceFn = Arc<dyn Fn(Vec<BufReader<File>>) -> Result<String, BoxError> + Send + Sync>;
+
+/// The error returned by a map or reduce closure, wrapped so that it can be
+/// used as the adapters' `Error` type.
+#[derive(Debug)]
+pub struct ClosureError(pub BoxError);
+
+impl fmt::Display for ClosureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Error for ClosureError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&*self.0)
+    }
+}
+
+/// Adapts a map closure emitting `(bucket, contents)` pairs: the bucket index
+/// is the key and the contents the value. Pair it with a `BucketPartitioner`
+/// so that bucket `i` lands in partition `i`.
+pub struct ClosureMapper {
+    map: MapFn
+}
+
+impl ClosureMapper {
+    pub fn new(map: MapFn) -> Self {
+        ClosureMapper { map }
+    }
+}
+
+impl Mapper for ClosureMapper {
+    type Key = usize;
+    type Value = String;
+    type Error = ClosureError;
+
+    fn map(&self, records: &mut dyn Iterator<Item = Record>, output: &mut Emitter<usize, String>) -> Result<(), ClosureError> {
+        (self.map)(records, output).map_err(ClosureError)
+    }
+}
+
+/// Adapts a reduce closure over raw files: the contents of each bucket
+/// written by a `ClosureMapper` are handed back to the closure as one file,
+/// kept in the scratch directory while the closure runs.
+pub struct ClosureReducer {
+    reduce: ReduceFn,
+    scratch_directory: PathBuf
+}
+
+impl ClosureReducer {
+    pub fn new(reduce: ReduceFn, scratch_directory: PathBuf) -> Self {
+        ClosureReducer { reduce, scratch_directory }
+    }
+
+    /// Streams the bucket's contents, in the order the merge yields them,
+    /// into the scratch file at `path` and hands it to the closure.
+    fn reduce_file(&self, contents: &mut dyn Iterator<Item = String>, path: &Path) -> Result<String, ClosureError> {
+        let error = |e: io::Error| ClosureError(e.into());
+        let mut file = BufWriter::new(File::create(path).map_err(error)?);
+        for content in contents {
+            file.write_all(content.as_bytes()).map_err(error)?;
+        }
+        file.flush().map_err(error)?;
+        let file = File::open(path).map_err(error)?;
+        (self.reduce)(vec![BufReader::new(file)]).map_err(ClosureError)
+    }
+
+    fn scratch_path(&self, bucket: usize) -> PathBuf {
+        self.scratch_directory.join(format!("bucket.{}.{}.{}.tmp",
+                                            bucket,
+                                            process::id(),
+                                            SCRATCH_FILES.fetch_add(1, Ordering::SeqCst)))
+    }
+}
+
+impl Reducer for ClosureReducer {
+    type Key = usize;
+    type Value = String;
+    type Output = String;
+    type Error = ClosureError;
+
+    fn reduce(&self, bucket: &usize, contents: &mut dyn Iterator<Item = String>) -> Result<String, ClosureError> {
+        let path = self.scratch_path(*bucket);
+        let result = self.reduce_file(contents, &path);
+        let _ = remove_file(path);
+        result
+    }
+}
+
+static SCRATCH_FILES: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(test)]
+mod test {
+    use std::fs::{ create_dir_all, read_dir, remove_dir_all };
+    use std::io::Read;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use super::{ Reducer, ClosureReducer };
+
+    #[test]
+    fn closure_reducer_hands_a_bucket_over_as_one_scratch_file() {
+        let directory = PathBuf::from("./test-data/closure_reducer_scratch_file");
+        create_dir_all(&directory).unwrap();
+        let scratch_directory = directory.clone();
+        let reducer = ClosureReducer::new(Arc::new(move |files| {
+            assert_eq!(read_dir(&scratch_directory).unwrap().count(), 1);
+            let mut contents = String::new();
+            for mut file in files {
+                file.read_to_string(&mut contents)?;
+            }
+            Ok(contents)
+        }), directory.clone());
+
+        let mut values = (0..1000).map(|n| format!("{}\n", n));
+        let output = reducer.reduce(&3, &mut values).unwrap();
+
+        assert_eq!(output, (0..1000).map(|n| format!("{}\n", n)).collect::<String>());
+        assert_eq!(read_dir(&directory).unwrap().count(), 0);
+        remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::{ BufRead, BufReader, Read, Seek, SeekFrom, Take };
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use split::InputSplit;
+
+/// One record of an input, with the byte offset in its file where it starts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub offset: u64,
+    pub bytes: Vec<u8>
+}
+
+pub type Records = Box<dyn Iterator<Item = io::Result<Record>>>;
+
+/// The input format chosen for each input file; inputs without one are read
+/// as `TextLines`.
+pub type InputFormats = HashMap<PathBuf, Arc<dyn InputFormat>>;
+
+/// Turns an input split into the records the mapper is handed. A split owns
+/// every record that starts inside its byte range, so a record straddling
+/// the end of a split is read to its end by that split and skipped by the
+/// next one.
+pub trait InputFormat: Send + Sync {
+    fn records(&self, split: &InputSplit) -> io::Result<Records>;
+
+    /// Whether inputs in this format can be cut into several splits.
+    fn splittable(&self) -> bool {
+        true
+    }
+}
+
+/// Newline-terminated lines, without the line ending.
+pub struct TextLines;
+
+impl InputFormat for TextLines {
+    fn records(&self, split: &InputSplit) -> io::Result<Records> {
+        let lines = Delimited::new(b"\n").records(split)?;
+        Ok(Box::new(lines.map(|line| {
+            line.map(|mut line| {
+                if line.bytes.last() == Some(&b'\r') {
+                    line.bytes.pop();
+                }
+                line
+            })
+        })))
+    }
+}
+
+/// Records terminated by an arbitrary delimiter, without the delimiter.
+pub struct Delimited {
+    delimiter: Vec<u8>
+}
+
+impl Delimited {
+    pub fn new(delimiter: &[u8]) -> Self {
+        assert!(!delimiter.is_empty(), "record delimiter must not be empty");
+        Delimited { delimiter: delimiter.to_vec() }
+    }
+
+    /// The offset of the first record starting at or after `position`.
+    fn record_start(&self, file: &mut File, position: u64) -> io::Result<u64> {
+        if position == 0 {
+            return Ok(0);
+        }
+        let from = position.saturating_sub(self.delimiter.len() as u64);
+        file.seek(SeekFrom::Start(from))?;
+        let skipped = read_record(&mut BufReader::new(&mut *file), &self.delimiter, &mut vec![])?;
+        Ok(from + skipped as u64)
+    }
+}
+
+impl InputFormat for Delimited {
+    fn records(&self, split: &InputSplit) -> io::Result<Records> {
+        let mut file = File::open(&split.path)?;
+        let size = file.metadata()?.len();
+        let start = self.record_start(&mut file, split.offset.min(size))?;
+        let end = self.record_start(&mut file, split.end().min(size))?;
+        file.seek(SeekFrom::Start(start))?;
+        Ok(Box::new(DelimitedRecords {
+            reader: BufReader::new(file.take(end.saturating_sub(start))),
+            delimiter: self.delimiter.clone(),
+            offset: start
+        }))
+    }
+}
+
+struct DelimitedRecords {
+    reader: BufReader<Take<File>>,
+    delimiter: Vec<u8>,
+    offset: u64
+}
+
+impl Iterator for DelimitedRecords {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<io::Result<Record>> {
+        let mut bytes = vec![];
+        match read_record(&mut self.reader, &self.delimiter, &mut bytes) {
+            Ok(0) => None,
+            Ok(n) => {
+                let offset = self.offset;
+                self.offset += n as u64;
+                if bytes.ends_with(&self.delimiter) {
+                    bytes.truncate(bytes.len() - self.delimiter.len());
+                }
+                Some(Ok(Record { offset, bytes }))
+            }
+            Err(e) => Some(Err(e))
+        }
+    }
+}
+
+/// Reads up to and including the next `delimiter`, or to the end of the input,
+/// returning the number of bytes read.
+fn read_record<R: BufRead>(reader: &mut R, delimiter: &[u8], buffer: &mut Vec<u8>) -> io::Result<usize> {
+    let last = delimiter[delimiter.len() - 1];
+    let mut read = 0;
+    loop {
+        let n = reader.read_until(last, buffer)?;
+        read += n;
+        if n == 0 || buffer.ends_with(delimiter) {
+            return Ok(read);
+        }
+    }
+}
+
+/// Binary records of exactly `length` bytes each. A file whose size is not a
+/// multiple of the record length is reported as invalid data.
+pub struct FixedLength {
+    length: u64
+}
+
+impl FixedLength {
+    pub fn new(length: u64) -> Self {
+        assert!(length > 0, "record length must not be zero");
+        FixedLength { length }
+    }
+
+    fn record_start(&self, position: u64) -> u64 {
+        position.div_ceil(self.length) * self.length
+    }
+}
+
+impl InputFormat for FixedLength {
+    fn records(&self, split: &InputSplit) -> io::Result<Records> {
+        let mut file = File::open(&split.path)?;
+        let size = file.metadata()?.len();
+        let start = self.record_start(split.offset.min(size)).min(size);
+        let end = self.record_start(split.end().min(size)).min(size);
+        file.seek(SeekFrom::Start(start))?;
+        let mut reader = BufReader::new(file.take(end.saturating_sub(start)));
+        let length = self.length;
+        let mut offset = start;
+        Ok(Box::new((0..(end.saturating_sub(start)).div_ceil(length)).map(move |_| {
+            let mut bytes = vec![0; length.min(end - offset) as usize];
+            reader.read_exact(&mut bytes)?;
+            if (bytes.len() as u64) < length {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated fixed-length record"));
+            }
+            let record = Record { offset, bytes };
+            offset += length;
+            Ok(record)
+        })))
+    }
+}
+
+/// The whole file as a single record. Inputs in this format are never split.
+pub struct WholeFile;
+
+impl InputFormat for WholeFile {
+    fn records(&self, split: &InputSplit) -> io::Result<Records> {
+        if split.offset > 0 {
+            return Ok(Box::new(None.into_iter()));
+        }
+        let mut bytes = vec![];
+        File::open(&split.path)?.read_to_end(&mut bytes)?;
+        Ok(Box::new(Some(Ok(Record { offset: 0, bytes })).into_iter()))
+    }
+
+    fn splittable(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{ create_dir_all, remove_dir_all, write };
+    use std::path::{ Path, PathBuf };
+    use split::InputSplit;
+    use super::{ InputFormat, Record, TextLines, Delimited, FixedLength, WholeFile };
+
+    fn read_all(format: &dyn InputFormat, input: &Path, split_size: u64) -> Vec<Vec<Record>> {
+        InputSplit::split(input, split_size).iter()
+                                            .map(|split| {
+                                                format.records(split)
+                                                      .unwrap()
+                                                      .map(|record| record.unwrap())
+                                                      .collect::<Vec<Record>>()
+                                            })
+                                            .collect()
+    }
+
+    fn record(offset: u64, bytes: &[u8]) -> Record {
+        Record { offset, bytes: bytes.to_vec() }
+    }
+
+    #[test]
+    fn lines_are_read_exactly_once_across_splits() {
+        let working_directory = PathBuf::from("./test-data/input_reads_lines_once");
+        create_dir_all(&working_directory).unwrap();
+        let input = working_directory.join("input");
+        write(&input, "the martians\r\nlanded\n\nat horsell common\nthe heat ray").unwrap();
+        let expected = vec![record(0, b"the martians"),
+                            record(14, b"landed"),
+                            record(21, b""),
+                            record(22, b"at horsell common"),
+                            record(40, b"the heat ray")];
+
+        for split_size in 1..60 {
+            let records = read_all(&TextLines, &input, split_size).into_iter()
+                                                                  .flatten()
+                                                                  .collect::<Vec<Record>>();
+            assert_eq!(records, expected, "split size {}", split_size);
+        }
+        remove_dir_all(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn straddling_lines_belong_to_the_split_they_start_in() {
+        let working_directory = PathBuf::from("./test-data/input_straddling_lines");
+        create_dir_all(&working_directory).unwrap();
+        let input = working_directory.join("input");
+        write(&input, "the heat ray\nthe tripods\n").unwrap();
+
+        assert_eq!(read_all(&TextLines, &input, 10), vec![vec![record(0, b"the heat ray")],
+                                                          vec![record(13, b"the tripods")],
+                                                          vec![]]);
+        remove_dir_all(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn delimited_records_span_split_boundaries() {
+        let working_directory = PathBuf::from("./test-data/input_delimited_records");
+        create_dir_all(&working_directory).unwrap();
+        let input = working_directory.join("input");
+        write(&input, "martians||tripods||heat ray||").unwrap();
+        let expected = vec![record(0, b"martians"), record(10, b"tripods"), record(19, b"heat ray")];
+
+        for split_size in 1..32 {
+            let records = read_all(&Delimited::new(b"||"), &input, split_size).into_iter()
+                                                                             .flatten()
+                                                                             .collect::<Vec<Record>>();
+            assert_eq!(records, expected, "split size {}", split_size);
+        }
+        remove_dir_all(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn fixed_length_records_are_aligned_to_their_length() {
+        let working_directory = PathBuf::from("./test-data/input_fixed_length_records");
+        create_dir_all(&working_directory).unwrap();
+        let input = working_directory.join("input");
+        write(&input, [1u8, 2, 3, 4, 5, 6, 7, 8, 9]).unwrap();
+
+        assert_eq!(read_all(&FixedLength::new(3), &input, 4),
+                   vec![vec![record(0, &[1, 2, 3]), record(3, &[4, 5, 6])],
+                        vec![record(6, &[7, 8, 9])],
+                        vec![]]);
+
+        write(&input, [1u8, 2, 3, 4]).unwrap();
+        let split = InputSplit::whole(input.clone());
+        let records = FixedLength::new(3).records(&split).unwrap().collect::<Vec<_>>();
+        assert!(records[1].is_err());
+        remove_dir_all(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn whole_files_are_a_single_record() {
+        let working_directory = PathBuf::from("./test-data/input_whole_files");
+        create_dir_all(&working_directory).unwrap();
+        let input = working_directory.join("input");
+        write(&input, "the heat ray\nthe tripods\n").unwrap();
+
+        assert!(!WholeFile.splittable());
+        assert_eq!(read_all(&WholeFile, &input, 10),
+                   vec![vec![record(0, b"the heat ray\nthe tripods\n")], vec![], vec![]]);
+        remove_dir_all(&working_directory).unwrap();
+    }
+}
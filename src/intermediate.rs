@@ -0,0 +1,442 @@
+use std::io;
+use std::io::{ Read, Seek, SeekFrom, Write };
+use std::vec;
+
+use compression::Codec;
+use datum::Datum;
+
+/// Marks the start and end of a sorted run.
+const RUN_MAGIC: &[u8; 4] = b"MRRN";
+
+/// The header's size: magic and codec id.
+const HEADER_SIZE: usize = 4 + 1;
+
+/// Records are cut into a new block once a block's uncompressed payload
+/// reaches this size.
+const BLOCK_SIZE: usize = 64 * 1024;
+
+/// The footer's size: index offset, block count, index checksum and magic.
+const FOOTER_SIZE: usize = 8 + 4 + 4 + 4;
+
+/// Writes a sorted run of `pairs`, the format of the intermediate `map.X.reduce.Y`
+/// files. A header of `RUN_MAGIC` and the id of `codec` is followed by the
+/// pairs, written as records in blocks. Each block is a big-endian `u32`
+/// length, a CRC32 and the payload compressed with `codec`; the length and
+/// checksum are of the compressed bytes. An index follows with each block's
+/// offset, record count and first key, then a footer holding the index's
+/// offset, the block count, the index's CRC32 and `RUN_MAGIC`.
+pub fn write_run<K, V, W, I>(writer: W, pairs: I, codec: Codec) -> io::Result<W>
+    where K: Datum, V: Datum, W: Write, I: IntoIterator<Item = (K, V)>
+{
+    let mut run = RunWriter::new(writer, codec)?;
+    for (key, value) in pairs {
+        run.push(&key, &value)?;
+    }
+    run.finish()
+}
+
+/// Writes a sorted run one pair at a time, holding no more than a block of
+/// it in memory. The pairs must be pushed in key order.
+pub struct RunWriter<W: Write> {
+    writer: W,
+    codec: Codec,
+    offset: u64,
+    payload: Vec<u8>,
+    n_records: u32,
+    index: Vec<u8>,
+    n_blocks: u32
+}
+
+impl<W: Write> RunWriter<W> {
+    pub fn new(mut writer: W, codec: Codec) -> io::Result<Self> {
+        writer.write_all(RUN_MAGIC)?;
+        writer.write_all(&[codec.id()])?;
+        Ok(RunWriter {
+            writer,
+            codec,
+            offset: HEADER_SIZE as u64,
+            payload: vec![],
+            n_records: 0,
+            index: vec![],
+            n_blocks: 0
+        })
+    }
+
+    pub fn push<K: Datum, V: Datum>(&mut self, key: &K, value: &V) -> io::Result<()> {
+        if self.payload.len() >= BLOCK_SIZE {
+            self.write_block()?;
+        }
+        if self.n_records == 0 {
+            self.index.extend_from_slice(&self.offset.to_be_bytes());
+            write_field(&mut self.index, key);
+        }
+        write_field(&mut self.payload, key);
+        write_field(&mut self.payload, value);
+        self.n_records += 1;
+        Ok(())
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        if self.n_records == 0 {
+            return Ok(());
+        }
+        self.index.extend_from_slice(&self.n_records.to_be_bytes());
+        let stored = self.codec.compress(&self.payload)?;
+        self.writer.write_all(&(stored.len() as u32).to_be_bytes())?;
+        self.writer.write_all(&crc32(&stored).to_be_bytes())?;
+        self.writer.write_all(&stored)?;
+        self.offset += 8 + stored.len() as u64;
+        self.n_blocks += 1;
+        self.payload.clear();
+        self.n_records = 0;
+        Ok(())
+    }
+
+    /// Writes the last block, the index and the footer, and hands back the
+    /// writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_block()?;
+        let mut footer = self.offset.to_be_bytes().to_vec();
+        footer.extend_from_slice(&self.n_blocks.to_be_bytes());
+        footer.extend_from_slice(&crc32(&self.index).to_be_bytes());
+        footer.extend_from_slice(RUN_MAGIC);
+        self.writer.write_all(&self.index)?;
+        self.writer.write_all(&footer)?;
+        Ok(self.writer)
+    }
+}
+
+/// Reads back a whole sorted run written by `write_run` with any codec. See
+/// `RunReader` for the checks made.
+#[cfg(test)]
+pub fn read_run<K, V, R>(reader: &mut R) -> io::Result<Vec<(K, V)>>
+    where K: Datum, V: Datum, R: Read + Seek
+{
+    RunReader::new(reader)?.collect()
+}
+
+struct BlockEntry {
+    offset: u64,
+    first_key: Option<Vec<u8>>,
+    n_records: u32
+}
+
+/// Reads a sorted run written by `write_run` with any codec, one block at a
+/// time. The index is checked against its checksum when the run is opened,
+/// and each block against its checksum and the index when the reader gets to
+/// it. Any mismatch is reported as `InvalidData` (see `is_corrupt`), and
+/// iteration ends after the first error.
+pub struct RunReader<K, V, R> {
+    reader: R,
+    codec: Codec,
+    index_offset: u64,
+    blocks: vec::IntoIter<BlockEntry>,
+    block: vec::IntoIter<(K, V)>,
+    next_offset: u64,
+    block_number: u32,
+    done: bool
+}
+
+impl<K: Datum, V: Datum, R: Read + Seek> RunReader<K, V, R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let length = reader.seek(SeekFrom::End(0))?;
+        let mut header = [0; HEADER_SIZE];
+        let mut footer = [0; FOOTER_SIZE];
+        if length >= (HEADER_SIZE + FOOTER_SIZE) as u64 {
+            reader.seek(SeekFrom::Start(0))?;
+            reader.read_exact(&mut header)?;
+            reader.seek(SeekFrom::End(-(FOOTER_SIZE as i64)))?;
+            reader.read_exact(&mut footer)?;
+        }
+        if !header.starts_with(RUN_MAGIC) || !footer.ends_with(RUN_MAGIC) {
+            return Err(invalid_data("sorted run is missing its header or footer"));
+        }
+        let codec = Codec::from_id(header[4])?;
+        let mut footer = &footer[..];
+        let index_offset = read_u64(&mut footer)?;
+        let n_blocks = read_u32(&mut footer)?;
+        let index_crc = read_u32(&mut footer)?;
+        let index_end = length - FOOTER_SIZE as u64;
+        if index_offset < HEADER_SIZE as u64 || index_offset > index_end {
+            return Err(invalid_data("sorted run index is out of bounds"));
+        }
+        let mut index = vec![0; (index_end - index_offset) as usize];
+        reader.seek(SeekFrom::Start(index_offset))?;
+        reader.read_exact(&mut index)?;
+        if crc32(&index) != index_crc {
+            return Err(invalid_data("sorted run index failed its checksum"));
+        }
+
+        let mut entries = &index[..];
+        let mut blocks = vec![];
+        for _ in 0..n_blocks {
+            let offset = read_u64(&mut entries)?;
+            let first_key = read_field::<Vec<u8>, _>(&mut entries)?;
+            let n_records = read_u32(&mut entries)?;
+            blocks.push(BlockEntry { offset, first_key, n_records });
+        }
+        if !entries.is_empty() {
+            return Err(invalid_data("sorted run blocks do not match its index"));
+        }
+        reader.seek(SeekFrom::Start(HEADER_SIZE as u64))?;
+        Ok(RunReader {
+            reader,
+            codec,
+            index_offset,
+            blocks: blocks.into_iter(),
+            block: vec![].into_iter(),
+            next_offset: HEADER_SIZE as u64,
+            block_number: 0,
+            done: false
+        })
+    }
+
+    /// Reads and checks the next block, returning `false` once there are
+    /// none left.
+    fn read_block(&mut self) -> io::Result<bool> {
+        let entry = match self.blocks.next() {
+            Some(entry) => entry,
+            None if self.next_offset == self.index_offset => return Ok(false),
+            None => return Err(invalid_data("sorted run blocks do not match its index"))
+        };
+        let block = self.block_number;
+        if entry.offset != self.next_offset {
+            return Err(invalid_data(&format!("block {} is not where the index says", block)));
+        }
+        let mut header = [0; 8];
+        self.reader.read_exact(&mut header)?;
+        let mut header = &header[..];
+        let length = read_u32(&mut header)? as u64;
+        let crc = read_u32(&mut header)?;
+        if entry.offset + 8 + length > self.index_offset {
+            return Err(invalid_data(&format!("block {} is truncated", block)));
+        }
+        let mut stored = vec![0; length as usize];
+        self.reader.read_exact(&mut stored)?;
+        if crc32(&stored) != crc {
+            return Err(invalid_data(&format!("block {} failed its checksum", block)));
+        }
+        let payload = self.codec.decompress(&stored)
+                                .map_err(|_| invalid_data(&format!("block {} could not be decompressed", block)))?;
+        if entry.first_key.is_some_and(|key| !payload.get(4..).is_some_and(|records| records.starts_with(&key))) {
+            return Err(invalid_data(&format!("block {} does not start with its indexed key", block)));
+        }
+        let records = read_pairs::<K, V, _>(&mut &payload[..])?;
+        if records.len() != entry.n_records as usize {
+            return Err(invalid_data(&format!("block {} has the wrong number of records", block)));
+        }
+        self.block = records.into_iter();
+        self.next_offset = entry.offset + 8 + length;
+        self.block_number += 1;
+        Ok(true)
+    }
+}
+
+impl<K: Datum, V: Datum, R: Read + Seek> Iterator for RunReader<K, V, R> {
+    type Item = io::Result<(K, V)>;
+
+    fn next(&mut self) -> Option<io::Result<(K, V)>> {
+        loop {
+            if let Some(pair) = self.block.next() {
+                return Some(Ok(pair));
+            }
+            if self.done {
+                return None;
+            }
+            match self.read_block() {
+                Ok(true) => {}
+                Ok(false) => {
+                    self.done = true;
+                    return None;
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Whether an error from a `RunReader` means the run is corrupt, rather than
+/// that it could not be read at all.
+pub fn is_corrupt(error: &io::Error) -> bool {
+    matches!(error.kind(), io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof)
+}
+
+/// Writes a key/value record: a big-endian `u32` key length, the encoded key,
+/// a `u32` value length and the encoded value. Records make up a sorted run's
+/// blocks and the `LengthPrefixed` output format.
+pub fn write_pair<K, V, W>(writer: &mut W, key: &K, value: &V) -> io::Result<()>
+    where K: Datum, V: Datum, W: Write + ?Sized
+{
+    let mut buffer = vec![];
+    write_field(&mut buffer, key);
+    write_field(&mut buffer, value);
+    writer.write_all(&buffer)
+}
+
+pub fn read_pairs<K, V, R>(reader: &mut R) -> io::Result<Vec<(K, V)>>
+    where K: Datum, V: Datum, R: Read
+{
+    let mut pairs = vec![];
+    while let Some(key) = read_field(reader)? {
+        match read_field(reader)? {
+            Some(value) => pairs.push((key, value)),
+            None => return Err(invalid_data("record is missing its value"))
+        }
+    }
+    Ok(pairs)
+}
+
+fn write_field<T: Datum>(buffer: &mut Vec<u8>, datum: &T) {
+    let start = buffer.len();
+    buffer.extend_from_slice(&[0; 4]);
+    datum.encode(buffer);
+    let length = (buffer.len() - start - 4) as u32;
+    buffer[start..start + 4].copy_from_slice(&length.to_be_bytes());
+}
+
+fn read_field<T: Datum, R: Read>(reader: &mut R) -> io::Result<Option<T>> {
+    let mut length = [0; 4];
+    let mut filled = 0;
+    while filled < length.len() {
+        match reader.read(&mut length[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(invalid_data("truncated field length")),
+            Ok(n) => filled += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e)
+        }
+    }
+    let mut bytes = vec![0; u32::from_be_bytes(length) as usize];
+    reader.read_exact(&mut bytes)?;
+    T::decode(&bytes).map(Some)
+                     .ok_or_else(|| invalid_data("field could not be decoded"))
+}
+
+fn read_u32(bytes: &mut &[u8]) -> io::Result<u32> {
+    let mut raw = [0; 4];
+    bytes.read_exact(&mut raw)?;
+    Ok(u32::from_be_bytes(raw))
+}
+
+fn read_u64(bytes: &mut &[u8]) -> io::Result<u64> {
+    let mut raw = [0; 8];
+    bytes.read_exact(&mut raw)?;
+    Ok(u64::from_be_bytes(raw))
+}
+
+/// The CRC-32 (IEEE) lookup table.
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, &byte| (crc >> 8) ^ CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize])
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use compression::Codec;
+    use super::{ write_pair, read_pairs, write_run, read_run, is_corrupt, crc32, HEADER_SIZE };
+
+    fn codecs() -> Vec<Codec> {
+        vec![Codec::None,
+             #[cfg(feature = "gzip")]
+             Codec::Gzip,
+             #[cfg(feature = "zstd")]
+             Codec::Zstd]
+    }
+
+    fn many_pairs() -> Vec<(String, u64)> {
+        (0..20_000u64).map(|i| (format!("martian-{:06}", i), i)).collect()
+    }
+
+    #[test]
+    fn pairs_round_trip() {
+        let pairs = vec![("martian".to_string(), 3u64),
+                         ("".to_string(), 0),
+                         ("tripod".to_string(), 12)];
+        let mut buffer = vec![];
+        for (key, value) in pairs.iter() {
+            write_pair(&mut buffer, key, value).unwrap();
+        }
+
+        let read = read_pairs::<String, u64, _>(&mut Cursor::new(buffer)).unwrap();
+        assert_eq!(read, pairs);
+    }
+
+    #[test]
+    fn truncated_record_is_an_error() {
+        let mut buffer = vec![];
+        write_pair(&mut buffer, &"heat-ray".to_string(), &1u64).unwrap();
+        buffer.pop();
+
+        assert!(read_pairs::<String, u64, _>(&mut Cursor::new(buffer)).is_err());
+    }
+
+    #[test]
+    fn runs_round_trip_across_blocks() {
+        let pairs = many_pairs();
+        for codec in codecs() {
+            let mut buffer = vec![];
+            write_run(&mut buffer, pairs.clone(), codec).unwrap();
+            assert_eq!(read_run::<String, u64, _>(&mut Cursor::new(buffer)).unwrap(), pairs, "{:?}", codec);
+
+            let mut empty = vec![];
+            write_run::<String, u64, _, _>(&mut empty, vec![], codec).unwrap();
+            assert_eq!(read_run::<String, u64, _>(&mut Cursor::new(empty)).unwrap(), vec![]);
+        }
+    }
+
+    #[test]
+    fn corrupt_runs_are_detected() {
+        let mut buffer = vec![];
+        write_run(&mut buffer, many_pairs(), Codec::None).unwrap();
+
+        for position in [10, buffer.len() / 2, buffer.len() - 30, buffer.len() - 1] {
+            let mut corrupt = buffer.clone();
+            corrupt[position] ^= 0x20;
+            let error = read_run::<String, u64, _>(&mut Cursor::new(corrupt)).unwrap_err();
+            assert!(is_corrupt(&error), "flipped byte {}: {}", position, error);
+        }
+        let truncated = buffer[..buffer.len() - 100].to_vec();
+        assert!(is_corrupt(&read_run::<String, u64, _>(&mut Cursor::new(truncated)).unwrap_err()));
+    }
+
+    #[test]
+    fn blocks_running_past_the_index_are_truncated() {
+        let mut buffer = vec![];
+        write_run(&mut buffer, many_pairs(), Codec::None).unwrap();
+        buffer[HEADER_SIZE..HEADER_SIZE + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+
+        let error = read_run::<String, u64, _>(&mut Cursor::new(buffer)).unwrap_err();
+        assert!(is_corrupt(&error));
+        assert!(error.to_string().contains("block 0 is truncated"), "{}", error);
+    }
+
+    #[test]
+    fn crc32_matches_the_ieee_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+}
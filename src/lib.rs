@@ -1,5 +1,25 @@
 #[macro_use]
 extern crate chan;
+#[cfg(feature = "gzip")]
+extern crate flate2;
+#[cfg(feature = "zstd")]
+extern crate zstd;
 
 mod worker;
+mod intermediate;
+mod shuffle;
+mod spill;
+pub mod compression;
+pub mod datum;
+pub mod error;
+pub mod executor;
+pub mod functions;
+pub mod input;
 pub mod master;
+pub mod network;
+pub mod output;
+pub mod partitioner;
+pub mod protocol;
+pub mod registry;
+pub mod split;
+pub mod task;
@@ -1,165 +1,1680 @@
 use std::path::PathBuf;
-use std::io::BufReader;
-use std::fs::{ File, read_dir };
-use std::str::FromStr;
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::hash::Hash;
+use std::net::ToSocketAddrs;
+use std::fs::{ File, OpenOptions, metadata, read_dir, remove_file, rename };
+use std::io;
+use std::io::{ BufReader, Write };
+use std::path::Path;
+use std::collections::{ BTreeMap, BTreeSet, HashMap };
+use std::sync::{ Arc, Mutex };
+use std::sync::atomic::{ AtomicBool, Ordering };
+#[cfg(unix)]
+use std::process;
+use std::slice;
 use std::thread;
+use std::time::{ Duration, Instant };
 use chan;
 use chan::{ Sender, Receiver };
-use worker::{ Job, JobResult, Worker };
+use functions::{ Mapper, Reducer, Combiner, MapFn, ReduceFn, ClosureMapper, ClosureReducer };
+use partitioner::{ Partitioner, HashPartitioner, BucketPartitioner };
+use compression::Codec;
+use datum::Datum;
+use error::MapReduceError;
+#[cfg(unix)]
+use executor;
+use executor::{ Executor, RunWorker, ThreadExecutor };
+use input::{ InputFormat, InputFormats, TextLines };
+use network;
+use output::{ OutputFormat, KeyValueText, PlainText };
+use protocol::{ Message, send, receive, read_tag, unknown_tag };
+use registry::{ Registry, Functions, Named, JobSpec, RegisteredMapper, RegisteredReducer };
+use split::InputSplit;
+use task::Task;
+use worker::{ Job,
+              JobResult,
+              Worker,
+              map_attempt_name,
+              map_output_name,
+              quarantine_attempt_name,
+              quarantine_name,
+              reduce_attempt_name };
 
-pub struct Master {
+/// How often the master checks running tasks for timeouts and stragglers.
+const TICK_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How much map output a map task buffers before spilling it to disk.
+const MAP_BUFFER_SIZE: usize = 64 * 1024 * 1024;
+
+/// How long a worker can go without a heartbeat before it is declared dead,
+/// unless set with `Master::with_worker_timeout`.
+pub const WORKER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many times slower than the median a task must be to get a backup.
+const SLOWDOWN: u32 = 2;
+
+/// The file in the working directory the job manifest is saved to.
+pub const MANIFEST: &str = "job.manifest";
+
+/// A task that has not finished yet. `running` holds the attempts in flight
+/// and when each was picked up by a worker, or `None` while still queued.
+/// `waiting_for` holds the map tasks being re-run because the task found their
+/// output corrupt or could not fetch it; no new attempt is launched until they
+/// have committed.
+struct TaskState {
+    job: Job,
+    launched: u32,
+    failures: u32,
+    running: HashMap<u32, Option<Instant>>,
+    speculated: bool,
+    record_failures: HashMap<u64, u32>,
+    waiting_for: BTreeSet<i32>
+}
+
+impl TaskState {
+    fn new(job: Job) -> Self {
+        let mut running = HashMap::new();
+        running.insert(job.attempt(), None);
+        TaskState {
+            launched: job.attempt(),
+            failures: 0,
+            running,
+            speculated: false,
+            record_failures: HashMap::new(),
+            waiting_for: BTreeSet::new(),
+            job
+        }
+    }
+}
+
+/// Bookkeeping for one phase: the tasks still outstanding, how long finished
+/// tasks took, and which worker has each attempt.
+struct Phase {
+    outstanding: HashMap<Task, TaskState>,
+    durations: Vec<Duration>,
+    assigned: HashMap<(Task, u32), usize>,
+    n_workers: usize
+}
+
+/// The files committed by each finished task: every map task's output for each
+/// partition, and each reduce task's result, keyed by task id. Skipped tasks
+/// commit nothing. The job of every committed map task, and the worker that
+/// ran it, are kept so that it can be re-run if its output turns out to be
+/// corrupt or is lost with its worker. `launched` holds the last attempt
+/// launched of every task, and `unsaved` the tasks launched since the job
+/// manifest was last written to.
+#[derive(Default)]
+struct TaskRegistry {
+    launched: BTreeMap<Task, u32>,
+    unsaved: BTreeSet<Task>,
+    map_jobs: BTreeMap<i32, Job>,
+    map_workers: BTreeMap<i32, usize>,
+    map_outputs: BTreeMap<i32, Vec<PathBuf>>,
+    reduce_outputs: BTreeMap<i32, PathBuf>,
+    quarantine_files: BTreeMap<i32, PathBuf>,
+    skipped: BTreeSet<Task>
+}
+
+/// A record of the job manifest, which is a log of records in frames of their
+/// own. It starts with `Partitions`; each time a task commits or is skipped,
+/// the attempts launched since the last records are appended, then the
+/// commit. Committed files are not recorded, as their names follow from the
+/// task ids.
+#[derive(Debug, PartialEq, Eq)]
+enum ManifestRecord {
+    /// The job's number of reduce partitions.
+    Partitions(usize),
+    /// The last attempt launched of a task.
+    Launched(Task, u32),
+    /// A map task committed, with its job at the committed attempt.
+    MapCommitted(Job),
+    /// The map task committed a quarantine file.
+    Quarantined(i32),
+    ReduceCommitted(i32),
+    Skipped(Task)
+}
+
+impl Message for ManifestRecord {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        match *self {
+            ManifestRecord::Partitions(n_reduce) => {
+                buffer.push(0);
+                n_reduce.write(buffer);
+            }
+            ManifestRecord::Launched(task, attempt) => {
+                buffer.push(1);
+                task.write(buffer);
+                attempt.write(buffer);
+            }
+            ManifestRecord::MapCommitted(ref job) => {
+                buffer.push(2);
+                job.write(buffer);
+            }
+            ManifestRecord::Quarantined(job_id) => {
+                buffer.push(3);
+                job_id.write(buffer);
+            }
+            ManifestRecord::ReduceCommitted(job_id) => {
+                buffer.push(4);
+                job_id.write(buffer);
+            }
+            ManifestRecord::Skipped(task) => {
+                buffer.push(5);
+                task.write(buffer);
+            }
+        }
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        match read_tag(bytes)? {
+            0 => Ok(ManifestRecord::Partitions(usize::read(bytes)?)),
+            1 => Ok(ManifestRecord::Launched(Task::read(bytes)?, u32::read(bytes)?)),
+            2 => Ok(ManifestRecord::MapCommitted(Job::read(bytes)?)),
+            3 => Ok(ManifestRecord::Quarantined(i32::read(bytes)?)),
+            4 => Ok(ManifestRecord::ReduceCommitted(i32::read(bytes)?)),
+            5 => Ok(ManifestRecord::Skipped(Task::read(bytes)?)),
+            tag => unknown_tag("manifest record", tag)
+        }
+    }
+}
+
+/// Sends on `ticks` every interval until dropped, so the master wakes up to
+/// check running tasks even when no results are arriving.
+struct Ticker {
+    ticks: Receiver<()>,
+    stopped: Arc<AtomicBool>
+}
+
+impl Ticker {
+    fn new(interval: Duration) -> Self {
+        let (send, ticks) = chan::async();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stop = stopped.clone();
+        thread::spawn(move || {
+            while !stop.load(Ordering::SeqCst) {
+                thread::sleep(interval);
+                send.send(());
+            }
+        });
+        Ticker { ticks, stopped }
+    }
+}
+
+impl Drop for Ticker {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+/// What a successful run produced: the result file of every reduce
+/// partition that was not skipped, in partition order, the quarantine file of
+/// every map task that skipped bad records, and the tasks skipped under
+/// `FailurePolicy::Skip`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobOutput {
+    pub result_files: Vec<PathBuf>,
+    pub quarantine_files: Vec<PathBuf>,
+    pub skipped: Vec<Task>
+}
+
+/// What the master does when a map or reduce function returns an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailurePolicy {
+    /// Retry the task like any other failure, up to the attempt limit.
+    Retry,
+    /// Give up on the task and carry on without its output.
+    Skip,
+    /// Fail the whole run straight away.
+    Abort
+}
+
+pub struct Master<M: Mapper, R: Reducer> {
     input_files: Vec<PathBuf>,
+    input_formats: InputFormats,
     working_directory: PathBuf,
-    map: Arc<Fn(BufReader<File>) -> Vec<String> + Send + Sync>,
-    reduce: Arc<Fn(Vec<BufReader<File>>) -> String + Send + Sync>,
+    mapper: Arc<M>,
+    reducer: Arc<R>,
+    partitioner: Arc<dyn Partitioner<M::Key>>,
+    combiner: Option<Arc<dyn Combiner<Key = M::Key, Value = M::Value>>>,
+    output_format: Arc<dyn OutputFormat<M::Key, R::Output>>,
+    intermediate_codec: Codec,
+    output_codec: Codec,
+    n_reduce: usize,
+    max_attempts: u32,
+    task_timeout: Option<Duration>,
+    speculative_execution: bool,
+    failure_policy: FailurePolicy,
+    max_record_failures: Option<u32>,
+    split_size: Option<u64>,
+    map_buffer_size: usize,
+    executor: Arc<dyn Executor>,
+    worker_timeout: Duration,
+    names: Option<Functions>,
+    registry: Mutex<TaskRegistry>,
+    heartbeats: Arc<Mutex<HashMap<usize, Instant>>>,
     job_queue: Sender<Job>,
     results_queue: Receiver<JobResult>,
     worker_job_queue: Receiver<Job>,
     worker_results_queue: Sender<JobResult>
 }
 
-impl Master {
+impl Master<ClosureMapper, ClosureReducer> {
     pub fn new(working_directory: PathBuf,
            input_files: Vec<PathBuf>,
-           map: Arc<Fn(BufReader<File>) -> Vec<String> + Send + Sync>,
-           reduce: Arc<Fn(Vec<BufReader<File>>) -> String + Send + Sync>
+           map: MapFn,
+           reduce: ReduceFn,
+           n_reduce: usize
            ) -> Self
+    {
+        let reducer = ClosureReducer::new(reduce, working_directory.clone());
+        Master::with_functions(working_directory,
+                               input_files,
+                               Arc::new(ClosureMapper::new(map)),
+                               Arc::new(reducer),
+                               n_reduce)
+            .with_partitioner(Arc::new(BucketPartitioner))
+            .with_output_format(Arc::new(PlainText))
+    }
+}
+
+/// A master whose functions were looked up in a `Registry`.
+pub type RegisteredMaster<K, V, O> = Master<RegisteredMapper<K, V>, RegisteredReducer<K, V, O>>;
+
+impl<K, V, O> RegisteredMaster<K, V, O>
+    where K: Datum + Ord + Hash,
+          V: Datum,
+          O: Datum
+{
+    /// Builds a master from the functions and formats `functions` names in
+    /// `registry`. Workers in other processes are sent the names, with the
+    /// settings they need, and look them up in their own registry. Setting a
+    /// partitioner, combiner, output format or input by value afterwards
+    /// drops the names, so that workers build the whole master themselves.
+    pub fn from_registry(registry: &Registry,
+                         working_directory: PathBuf,
+                         input_files: Vec<PathBuf>,
+                         functions: Functions,
+                         n_reduce: usize
+                         ) -> Result<Self, MapReduceError>
+    {
+        let mapper = registry.find_mapper(&functions.mapper)?;
+        let reducer = registry.find_reducer(&functions.reducer)?;
+        let partitioner = registry.find_partitioner(functions.partitioner.as_ref().unwrap_or(&Named::new("hash")))?;
+        let output_format = registry.find_output_format(functions.output_format.as_ref().unwrap_or(&Named::new("key_value_text")))?;
+        let mut master = Master::with_functions(working_directory, input_files, Arc::new(mapper), Arc::new(reducer), n_reduce)
+            .with_partitioner(partitioner)
+            .with_output_format(output_format);
+        if let Some(ref combiner) = functions.combiner {
+            master = master.with_combiner(registry.find_combiner(combiner)?);
+        }
+        for (input_file, format) in functions.inputs.iter() {
+            master = master.with_input(input_file.clone(), registry.find_input_format(format)?);
+        }
+        master.names = Some(functions);
+        master.check_configuration()?;
+        Ok(master)
+    }
+
+    /// Builds the master a worker runs for `spec`, without any input files.
+    pub(crate) fn from_spec(registry: &Registry, spec: &JobSpec) -> Result<Self, MapReduceError> {
+        Ok(Master::from_registry(registry, spec.working_directory.clone(), vec![], spec.functions.clone(), spec.n_reduce)?
+            .with_map_buffer_size(spec.map_buffer_size)
+            .with_intermediate_compression(spec.intermediate_codec)
+            .with_output_compression(spec.output_codec))
+    }
+}
+
+impl<M, R> Master<M, R>
+    where M: Mapper + 'static,
+          R: Reducer<Key = M::Key, Value = M::Value> + 'static
+{
+    pub fn with_functions(working_directory: PathBuf,
+                          input_files: Vec<PathBuf>,
+                          mapper: Arc<M>,
+                          reducer: Arc<R>,
+                          n_reduce: usize
+                          ) -> Self
     {
         let (work_send, work_recv) = chan::async();
+        let (report_send, report_recv) = chan::async();
         let (result_send, result_recv) = chan::async();
+        let heartbeats = Arc::new(Mutex::new(HashMap::new()));
+        forward_results(report_recv, result_send, heartbeats.clone());
 
         Master {
-            input_files: input_files,
-            working_directory: working_directory,
-            map: map,
-            reduce: reduce,
+            input_files,
+            input_formats: InputFormats::new(),
+            working_directory,
+            mapper,
+            reducer,
+            partitioner: Arc::new(HashPartitioner),
+            combiner: None,
+            output_format: Arc::new(KeyValueText),
+            intermediate_codec: Codec::None,
+            output_codec: Codec::None,
+            n_reduce,
+            max_attempts: 4,
+            task_timeout: None,
+            speculative_execution: false,
+            failure_policy: FailurePolicy::Retry,
+            max_record_failures: None,
+            split_size: None,
+            map_buffer_size: MAP_BUFFER_SIZE,
+            executor: Arc::new(ThreadExecutor),
+            worker_timeout: WORKER_TIMEOUT,
+            names: None,
+            registry: Mutex::new(TaskRegistry::default()),
+            heartbeats,
             job_queue: work_send,
             results_queue: result_recv,
             worker_job_queue: work_recv,
-            worker_results_queue: result_send
+            worker_results_queue: report_send
+        }
+    }
+
+    pub fn with_partitioner(mut self, partitioner: Arc<dyn Partitioner<M::Key>>) -> Self {
+        self.partitioner = partitioner;
+        self.names = None;
+        self
+    }
+
+    pub fn with_combiner(mut self, combiner: Arc<dyn Combiner<Key = M::Key, Value = M::Value>>) -> Self {
+        self.combiner = Some(combiner);
+        self.names = None;
+        self
+    }
+
+    /// Sets how reduce results are named, encoded and committed. Defaults to
+    /// `KeyValueText`.
+    pub fn with_output_format(mut self, output_format: Arc<dyn OutputFormat<M::Key, R::Output>>) -> Self {
+        self.output_format = output_format;
+        self.names = None;
+        self
+    }
+
+    /// Compresses the blocks of the intermediate `map.X.reduce.Y` files with
+    /// `codec`. Reduce tasks read any codec the build supports, whatever this
+    /// is set to.
+    pub fn with_intermediate_compression(mut self, codec: Codec) -> Self {
+        self.intermediate_codec = codec;
+        self
+    }
+
+    /// Compresses result files with `codec`, adding its extension to their
+    /// names. Read them back with `compression::open_result`.
+    pub fn with_output_compression(mut self, codec: Codec) -> Self {
+        self.output_codec = codec;
+        self
+    }
+
+    /// Number of times a task is attempted before `run` gives up on it.
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Treats an attempt that has been running for longer than `timeout` as
+    /// failed and launches a fresh one. The abandoned attempt can still win if
+    /// it finishes first.
+    pub fn with_task_timeout(mut self, timeout: Duration) -> Self {
+        self.task_timeout = Some(timeout);
+        self
+    }
+
+    /// Near the end of a phase, launches a backup attempt of any task that has
+    /// been running for more than `SLOWDOWN` times the median task duration,
+    /// as long as a worker is idle. The first attempt to finish wins.
+    pub fn with_speculative_execution(mut self) -> Self {
+        self.speculative_execution = true;
+        self
+    }
+
+    /// Sets what happens to a task whose map or reduce function returns an
+    /// error. Defaults to `FailurePolicy::Retry`.
+    pub fn with_failure_policy(mut self, failure_policy: FailurePolicy) -> Self {
+        self.failure_policy = failure_policy;
+        self
+    }
+
+    /// Turns on bad-record skipping: map tasks are run one record at a time,
+    /// and once a task has panicked `max_record_failures` times on the same
+    /// record it is re-run with that record skipped. Skipped records are
+    /// written to a quarantine file per map task.
+    pub fn with_bad_record_skipping(mut self, max_record_failures: u32) -> Self {
+        self.max_record_failures = Some(max_record_failures.max(1));
+        self
+    }
+
+    /// Cuts every input file into splits of about `split_size` bytes, each
+    /// mapped by its own task. Without it each file is a single split.
+    pub fn with_split_size(mut self, split_size: u64) -> Self {
+        self.split_size = Some(split_size);
+        self
+    }
+
+    /// Sets how many bytes of output a map task holds in memory. Past that,
+    /// the buffered pairs are sorted, combined and spilled to disk, and the
+    /// spills are merged into the task's output when it finishes. Defaults
+    /// to `MAP_BUFFER_SIZE`.
+    pub fn with_map_buffer_size(mut self, map_buffer_size: usize) -> Self {
+        self.map_buffer_size = map_buffer_size;
+        self
+    }
+
+    /// Sets how workers are run. Defaults to `ThreadExecutor`.
+    pub fn with_executor(mut self, executor: Arc<dyn Executor>) -> Self {
+        self.executor = executor;
+        self
+    }
+
+    /// Declares a worker dead once it has gone `timeout` without a heartbeat.
+    /// Its attempts in flight fail with `MapReduceError::WorkerLost` and are
+    /// retried, and if the executor keeps map output on the workers, its
+    /// committed map tasks are re-run. Defaults to `WORKER_TIMEOUT`, 10
+    /// seconds; workers send a heartbeat every `executor::HEARTBEAT_INTERVAL`.
+    pub fn with_worker_timeout(mut self, timeout: Duration) -> Self {
+        self.worker_timeout = timeout;
+        self
+    }
+
+    /// Adds an input read with `format`. Inputs passed to the constructor are
+    /// read as `TextLines`.
+    pub fn with_input(mut self, input_file: PathBuf, format: Arc<dyn InputFormat>) -> Self {
+        self.input_formats.insert(input_file.clone(), format);
+        self.input_files.push(input_file);
+        self.names = None;
+        self
+    }
+
+    /// The map task of every input split, each at its first attempt.
+    fn map_jobs(&self) -> Vec<Job> {
+        let skipped = self.max_record_failures.map(|_| vec![]);
+        self.input_files.iter()
+                                   .flat_map(|input| {
+                                       let splittable = self.input_formats.get(input)
+                                                                          .map_or(TextLines.splittable(), |f| f.splittable());
+                                       match self.split_size {
+                                           Some(split_size) if splittable => InputSplit::split(input, split_size),
+                                           _ => vec![InputSplit::whole(input.clone())]
+                                       }
+                                   })
+                                   .enumerate()
+                                   .map(|(index, split)| Job::Map(((index + 1) as i32, 1, split, skipped.clone())))
+                                   .collect()
+    }
+
+    /// Launches every map task not yet committed or skipped.
+    fn do_map(&self) -> Result<Vec<Job>, MapReduceError> {
+        let jobs = self.map_jobs();
+        self.launch_remaining(jobs)
+    }
+
+    /// Launches one reduce job per partition not yet committed or skipped,
+    /// from the map outputs in the registry, ordered by map task.
+    fn do_reduce(&self) -> Result<Vec<Job>, MapReduceError> {
+        let jobs = {
+            let registry = self.registry.lock().unwrap();
+            (0..self.n_reduce).map(|partition| {
+                                  let files = registry.map_outputs
+                                                      .values()
+                                                      .map(|outputs| outputs[partition].clone())
+                                                      .collect::<Vec<PathBuf>>();
+                                  Job::Reduce(((partition + 1) as i32, 1, files))
+                              })
+                              .collect::<Vec<Job>>()
+        };
+        self.launch_remaining(jobs)
+    }
+
+    /// Launches the jobs whose tasks have neither committed nor been skipped,
+    /// each at the attempt after the last one launched.
+    fn launch_remaining(&self, jobs: Vec<Job>) -> Result<Vec<Job>, MapReduceError> {
+        let jobs = {
+            let registry = self.registry.lock().unwrap();
+            jobs.into_iter()
+                .filter(|job| {
+                    let committed = match job.task() {
+                        Task::Map(job_id) => registry.map_outputs.contains_key(&job_id),
+                        Task::Reduce(job_id) => registry.reduce_outputs.contains_key(&job_id)
+                    };
+                    !committed && !registry.skipped.contains(&job.task())
+                })
+                .map(|job| job.with_attempt(registry.launched.get(&job.task()).map_or(1, |attempt| attempt + 1)))
+                .collect::<Vec<Job>>()
+        };
+        self.send_jobs(&jobs)?;
+        Ok(jobs)
+    }
+
+    /// Records the attempts as launched and hands them to the workers.
+    fn send_jobs(&self, jobs: &[Job]) -> Result<(), MapReduceError> {
+        {
+            let mut registry = self.registry.lock().unwrap();
+            for job in jobs {
+                let launched = registry.launched.entry(job.task()).or_insert(0);
+                *launched = (*launched).max(job.attempt());
+                registry.unsaved.insert(job.task());
+            }
+        }
+        for job in jobs {
+            self.job_queue.send(job.clone());
+        }
+        Ok(())
+    }
+
+    /// Writes a new job manifest holding what `registry` records, replacing
+    /// the last one only once it has been written in full.
+    fn start_manifest(&self, registry: &mut TaskRegistry) -> Result<(), MapReduceError> {
+        let mut records = vec![ManifestRecord::Partitions(self.n_reduce)];
+        records.extend(registry.launched.iter().map(|(&task, &attempt)| ManifestRecord::Launched(task, attempt)));
+        records.extend(registry.map_jobs.values().map(|job| ManifestRecord::MapCommitted(job.clone())));
+        records.extend(registry.quarantine_files.keys().map(|&job_id| ManifestRecord::Quarantined(job_id)));
+        records.extend(registry.reduce_outputs.keys().map(|&job_id| ManifestRecord::ReduceCommitted(job_id)));
+        records.extend(registry.skipped.iter().map(|&task| ManifestRecord::Skipped(task)));
+        registry.unsaved.clear();
+        let manifest = self.working_directory.join(MANIFEST);
+        let temporary = self.working_directory.join(format!("{}.tmp", MANIFEST));
+        self.write_manifest(&temporary, false, &records)
+            .and_then(|()| rename(&temporary, &manifest).map_err(|e| self.unsaved_manifest(e)))
+    }
+
+    /// Appends the attempts launched since the manifest was last written to,
+    /// and then the commit or skip of `task`, to the job manifest.
+    fn save_manifest(&self, registry: &mut TaskRegistry, task: Task) -> Result<(), MapReduceError> {
+        let mut records = registry.unsaved
+                                  .iter()
+                                  .map(|task| ManifestRecord::Launched(*task, registry.launched[task]))
+                                  .collect::<Vec<ManifestRecord>>();
+        if registry.skipped.contains(&task) {
+            records.push(ManifestRecord::Skipped(task));
+        } else if let Task::Map(job_id) = task {
+            records.push(ManifestRecord::MapCommitted(registry.map_jobs[&job_id].clone()));
+            if registry.quarantine_files.contains_key(&job_id) {
+                records.push(ManifestRecord::Quarantined(job_id));
+            }
+        } else if let Task::Reduce(job_id) = task {
+            records.push(ManifestRecord::ReduceCommitted(job_id));
         }
+        registry.unsaved.clear();
+        self.write_manifest(&self.working_directory.join(MANIFEST), true, &records)
     }
 
-    fn do_map(&self) -> i32 {
-        for (index, input) in self.input_files.iter().enumerate() {
-            self.job_queue.send(Job::Map(((index + 1) as i32, input.clone())));
+    /// Writes `records` to the manifest at `path` in a single write, after
+    /// what it holds already if `append`.
+    fn write_manifest(&self, path: &Path, append: bool, records: &[ManifestRecord]) -> Result<(), MapReduceError> {
+        let mut frames = vec![];
+        for record in records {
+            send(&mut frames, record).map_err(|e| self.unsaved_manifest(e))?;
         }
+        OpenOptions::new().create(true)
+                          .write(true)
+                          .append(append)
+                          .truncate(!append)
+                          .open(path)
+                          .and_then(|mut file| file.write_all(&frames))
+                          .map_err(|e| self.unsaved_manifest(e))
+    }
 
-        self.input_files.iter().len() as i32
+    fn unsaved_manifest(&self, error: io::Error) -> MapReduceError {
+        MapReduceError::InvalidWorkingDirectory {
+            path: self.working_directory.clone(),
+            reason: format!("could not save the job manifest: {}", error)
+        }
     }
 
-    fn do_reduce(&self) -> i32 {
-        if let Ok(entries) = read_dir(self.working_directory.clone()) {
-            let groups = entries.filter_map(|entry| entry.ok())
-                                .fold(HashMap::new(), |mut grouped, entry| {
-                                    let _ = entry.file_name()
-                                                 .into_string()
-                                                 .and_then(|filename| {
-                                                     filename.split(".")
-                                                             .nth(3)
-                                                             .and_then(|i| {
-                                                                  i32::from_str(i).ok()
-                                                             }).ok_or(entry.file_name())
-                                                 })
-                                                 .map(|key| {
-                                                     let mut files = grouped.entry(key).or_insert(vec![]);
-                                                     files.push(entry.path())
-                                                 });
-                                    grouped
-                                });
-            let n_reduce_jobs = groups.iter().len();
-            for (index, group) in groups {
-                self.job_queue.send(Job::Reduce((index, group)));
+    /// Reads the job manifest an earlier run left in the working directory,
+    /// if any, and checks that it is for this job. A record the earlier run
+    /// was killed while appending is ignored. Committed outputs that are
+    /// gone, or that were kept on workers, are dropped so that their tasks
+    /// run again; files left over from the earlier run's attempts are
+    /// removed, and new attempts are numbered after them.
+    fn load_manifest(&self) -> Result<TaskRegistry, MapReduceError> {
+        let invalid = |reason: String| {
+            MapReduceError::InvalidWorkingDirectory { path: self.working_directory.clone(), reason }
+        };
+        let unreadable = |e: io::Error| invalid(format!("could not read the job manifest: {}", e));
+        let mut manifest = match File::open(self.working_directory.join(MANIFEST)) {
+            Ok(file) => BufReader::new(file),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(TaskRegistry::default()),
+            Err(e) => return Err(unreadable(e))
+        };
+        match receive(&mut manifest).map_err(unreadable)? {
+            Some(ManifestRecord::Partitions(n_reduce)) if n_reduce == self.n_reduce => {}
+            Some(ManifestRecord::Partitions(_)) => {
+                return Err(invalid(format!("the job manifest is not for {} reduce partitions", self.n_reduce)));
+            }
+            _ => return Err(invalid("the job manifest does not start with the job's partitions".to_string()))
+        }
+        let mut registry = TaskRegistry::default();
+        loop {
+            let record = match receive(&mut manifest) {
+                Ok(Some(record)) => record,
+                Ok(None) => break,
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(unreadable(e))
+            };
+            match record {
+                ManifestRecord::Partitions(_) => return Err(invalid("the job manifest names its partitions twice".to_string())),
+                ManifestRecord::Launched(task, attempt) => {
+                    let launched = registry.launched.entry(task).or_insert(0);
+                    *launched = (*launched).max(attempt);
+                }
+                ManifestRecord::MapCommitted(job) => {
+                    let job_id = match job.task() {
+                        Task::Map(job_id) => job_id,
+                        Task::Reduce(_) => return Err(invalid("the job manifest commits a reduce job as a map task".to_string()))
+                    };
+                    let outputs = (0..self.n_reduce).map(|partition| map_output_name(&self.working_directory, job_id, partition))
+                                                    .collect();
+                    registry.map_outputs.insert(job_id, outputs);
+                    registry.quarantine_files.remove(&job_id);
+                    registry.map_jobs.insert(job_id, job);
+                }
+                ManifestRecord::Quarantined(job_id) => {
+                    registry.quarantine_files.insert(job_id, quarantine_name(&self.working_directory, job_id));
+                }
+                ManifestRecord::ReduceCommitted(job_id) => {
+                    registry.reduce_outputs.insert(job_id, self.result_file(job_id));
+                }
+                ManifestRecord::Skipped(task) => {
+                    registry.skipped.insert(task);
+                }
             }
-            n_reduce_jobs as i32
-        } else {
-            0
         }
+
+        let splits = self.map_jobs()
+                         .into_iter()
+                         .filter_map(|job| match job {
+                             Job::Map((job_id, _, split, _)) => Some((job_id, split)),
+                             Job::Reduce(_) => None
+                         })
+                         .collect::<BTreeMap<i32, InputSplit>>();
+        for job in registry.map_jobs.values() {
+            if let Job::Map((job_id, _, ref split, _)) = *job {
+                if splits.get(&job_id) != Some(split) {
+                    return Err(invalid("the job manifest is for other inputs".to_string()));
+                }
+            }
+        }
+
+        let on_workers = self.executor.map_output_on_workers();
+        let lost = registry.map_outputs
+                           .iter()
+                           .filter(|&(_, outputs)| on_workers || !outputs.iter().all(|output| output.exists()))
+                           .map(|(job_id, _)| *job_id)
+                           .collect::<Vec<i32>>();
+        for job_id in lost {
+            registry.map_outputs.remove(&job_id);
+            registry.map_jobs.remove(&job_id);
+            registry.quarantine_files.remove(&job_id);
+        }
+        registry.reduce_outputs.retain(|_, output| output.exists());
+        // The manifest is only written to when a task commits, so attempts
+        // launched after that are found by their files.
+        let entries = read_dir(&self.working_directory).map_err(|e| invalid(e.to_string()))?;
+        for entry in entries.flatten() {
+            if let Some((task, attempt)) = entry.file_name().to_str().and_then(attempt_file) {
+                let launched = registry.launched.entry(task).or_insert(0);
+                *launched = (*launched).max(attempt);
+                let _ = remove_file(entry.path());
+            }
+        }
+        Ok(registry)
     }
 
-    pub fn run(&self, n_workers: i32) -> Vec<PathBuf> {
-        self.spawn_workers(n_workers);
+    /// The result file of reduce task `job_id`.
+    fn result_file(&self, job_id: i32) -> PathBuf {
+        self.working_directory.join(self.output_format.file_name(job_id) + self.output_codec.extension())
+    }
 
-        let n_map = self.do_map();
-        self.wait_for_completion(n_map);
-        let n_reduce = self.do_reduce();
-        self.wait_for_completion(n_reduce);
+    /// Runs the job on `n_workers` workers. In a worker process launched by
+    /// a `ProcessExecutor` it serves the master's jobs instead, and exits
+    /// once the master is done.
+    ///
+    /// What has been committed is recorded in the job manifest, `MANIFEST` in
+    /// the working directory, each time a task commits or is skipped, and
+    /// removed once the job has finished.
+    pub fn run(&self, n_workers: i32) -> Result<JobOutput, MapReduceError> {
+        self.serve_if_worker();
+        self.check_configuration()?;
+        self.check_working_directory()?;
+        self.run_from(TaskRegistry::default(), n_workers)
+    }
 
-        self.aggregate_result_files()
+    /// Continues the job whose manifest an interrupted run left in the working
+    /// directory: tasks it committed or skipped are not run again, and the
+    /// others start at a fresh attempt. The master must have the same inputs
+    /// and number of reduce partitions as the interrupted one. Without a
+    /// manifest, runs the whole job like `run`.
+    ///
+    /// The working directory resumed is the one the master was built with,
+    /// rather than an argument: the master owns it along with the job's
+    /// inputs and functions, and a directory differing from it would leave
+    /// the manifest and the outputs it names out of reach of the workers.
+    pub fn resume(&self, n_workers: i32) -> Result<JobOutput, MapReduceError> {
+        self.serve_if_worker();
+        self.check_configuration()?;
+        self.check_working_directory()?;
+        let registry = self.load_manifest()?;
+        self.run_from(registry, n_workers)
     }
 
-    fn spawn_workers(&self, n_workers: i32) {
-        for _ in 0..n_workers {
-            let working_directory = self.working_directory.clone();
-            let map = self.map.clone();
-            let reduce = self.reduce.clone();
-            let job_queue = self.worker_job_queue.clone();
-            let results_queue = self.worker_results_queue.clone();
+    #[cfg(unix)]
+    fn serve_if_worker(&self) {
+        if let Some(id) = executor::worker_id() {
+            let code = match executor::serve(id, |_| Ok(self.run_worker())) {
+                Ok(()) => 0,
+                Err(_) => 1
+            };
+            process::exit(code);
+        }
+    }
 
-            thread::spawn(move || {
-                let worker = Worker {
-                    working_directory: working_directory,
-                    map: map,
-                    reduce: reduce,
-                    job_queue: job_queue,
-                    results_queue: results_queue
+    /// Worker processes are only launched on Unix.
+    #[cfg(not(unix))]
+    fn serve_if_worker(&self) {}
+
+    fn run_from(&self, mut registry: TaskRegistry, n_workers: i32) -> Result<JobOutput, MapReduceError> {
+        self.start_manifest(&mut registry)?;
+        *self.registry.lock().unwrap() = registry;
+        self.heartbeats.lock().unwrap().clear();
+        self.spawn_workers(n_workers)?;
+
+        let map_jobs = self.do_map()?;
+        self.wait_for_completion(map_jobs, n_workers as usize)?;
+        let reduce_jobs = self.do_reduce()?;
+        self.wait_for_completion(reduce_jobs, n_workers as usize)?;
+
+        let _ = remove_file(self.working_directory.join(MANIFEST));
+        let registry = self.registry.lock().unwrap();
+        Ok(JobOutput {
+            result_files: registry.reduce_outputs.values().cloned().collect(),
+            quarantine_files: registry.quarantine_files.values().cloned().collect(),
+            skipped: registry.skipped.iter().cloned().collect()
+        })
+    }
+
+    /// Runs this process as a worker of the master listening at
+    /// `master_address` with a `TcpExecutor`, until that master is done. Map
+    /// output is kept in this master's working directory and served from
+    /// there to the other workers; this master must have the same functions
+    /// and number of reduce partitions as the one it works for.
+    pub fn serve<A: ToSocketAddrs>(&self, master_address: A) -> Result<(), MapReduceError> {
+        self.check_configuration()?;
+        self.check_working_directory()?;
+        network::work_for(master_address, &self.working_directory, |_| Ok(self.run_worker()))
+    }
+
+    fn check_configuration(&self) -> Result<(), MapReduceError> {
+        if self.n_reduce == 0 {
+            return Err(MapReduceError::InvalidConfiguration { reason: "a job needs at least one reduce partition".to_string() });
+        }
+        Ok(())
+    }
+
+    fn check_working_directory(&self) -> Result<(), MapReduceError> {
+        let invalid = |reason: String| {
+            MapReduceError::InvalidWorkingDirectory { path: self.working_directory.clone(), reason }
+        };
+        match metadata(&self.working_directory) {
+            Ok(ref m) if m.is_dir() => Ok(()),
+            Ok(_) => Err(invalid("not a directory".to_string())),
+            Err(e) => Err(invalid(e.to_string()))
+        }
+    }
+
+    fn spawn_workers(&self, n_workers: i32) -> Result<(), MapReduceError> {
+        self.executor.start(n_workers as usize,
+                            &self.working_directory,
+                            self.run_worker(),
+                            self.job_spec().as_ref(),
+                            self.worker_job_queue.clone(),
+                            self.worker_results_queue.clone())
+    }
+
+    /// What workers outside this process need to build this master's
+    /// functions, if it was built from a `Registry`.
+    fn job_spec(&self) -> Option<JobSpec> {
+        self.names.clone().map(|functions| {
+            JobSpec {
+                functions,
+                working_directory: self.working_directory.clone(),
+                n_reduce: self.n_reduce,
+                map_buffer_size: self.map_buffer_size,
+                intermediate_codec: self.intermediate_codec,
+                output_codec: self.output_codec
+            }
+        })
+    }
+
+    /// Builds the function an executor runs each worker with.
+    pub(crate) fn run_worker(&self) -> RunWorker {
+        let working_directory = self.working_directory.clone();
+        let mapper = self.mapper.clone();
+        let reducer = self.reducer.clone();
+        let partitioner = self.partitioner.clone();
+        let combiner = self.combiner.clone();
+        let output_format = self.output_format.clone();
+        let intermediate_codec = self.intermediate_codec;
+        let output_codec = self.output_codec;
+        let n_reduce = self.n_reduce;
+        let map_buffer_size = self.map_buffer_size;
+        let input_formats = Arc::new(self.input_formats.clone());
+
+        Arc::new(move |id, job_queue, results_queue| {
+            let worker = Worker {
+                id,
+                working_directory: working_directory.clone(),
+                mapper: mapper.clone(),
+                reducer: reducer.clone(),
+                partitioner: partitioner.clone(),
+                combiner: combiner.clone(),
+                n_reduce,
+                map_buffer_size,
+                input_formats: input_formats.clone(),
+                output_format: output_format.clone(),
+                intermediate_codec,
+                output_codec,
+                job_queue,
+                results_queue
+            };
+            worker.run()
+        })
+    }
+
+    /// Waits for every job to finish. The first attempt of a task to finish
+    /// has its output committed; failed attempts are re-enqueued until a task
+    /// runs out of attempts, and output from any other attempt is deleted.
+    fn wait_for_completion(&self, jobs: Vec<Job>, n_workers: usize) -> Result<(), MapReduceError> {
+        let mut phase = Phase {
+            outstanding: jobs.into_iter()
+                             .map(|job| (job.task(), TaskState::new(job)))
+                             .collect(),
+            durations: vec![],
+            assigned: HashMap::new(),
+            n_workers
+        };
+        let ticker = Ticker::new(TICK_INTERVAL);
+        let results = self.results_queue.clone();
+        let ticks = ticker.ticks.clone();
+        while !phase.outstanding.is_empty() {
+            chan_select! {
+                results.recv() -> result => {
+                    match result {
+                        Some(result) => self.record_result(&mut phase, result)?,
+                        None => return Ok(())
+                    }
+                },
+                ticks.recv() => {
+                    self.check_running(&mut phase, Instant::now())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn record_result(&self, phase: &mut Phase, result: JobResult) -> Result<(), MapReduceError> {
+        match result {
+            // Heartbeats never get here; `forward_results` records them.
+            JobResult::Heartbeat(_) => {}
+            JobResult::Started(worker, task, attempt) => {
+                phase.assigned.insert((task, attempt), worker);
+                if let Some(state) = phase.outstanding.get_mut(&task) {
+                    if let Some(started) = state.running.get_mut(&attempt) {
+                        *started = Some(Instant::now());
+                    }
+                }
+            }
+            JobResult::MapFinished(job_id, attempt, outputs) => return self.finish(phase, Task::Map(job_id), attempt, outputs),
+            JobResult::ReduceFinished(job_id, attempt, output) => {
+                return self.finish(phase, Task::Reduce(job_id), attempt, vec![output]);
+            }
+            JobResult::FunctionFailed(task, attempt, message) => {
+                phase.assigned.remove(&(task, attempt));
+                self.discard_outputs(task, attempt);
+                // A failure of an attempt that lost to another one, or that was
+                // already given up on, says nothing about the task.
+                if !phase.outstanding.get(&task).is_some_and(|state| state.running.contains_key(&attempt)) {
+                    return Ok(());
+                }
+                let error = MapReduceError::FunctionFailed { task, attempt, message };
+                return match self.failure_policy {
+                    FailurePolicy::Retry => self.fail(phase, task, attempt, error),
+                    FailurePolicy::Skip => self.skip(phase, task),
+                    FailurePolicy::Abort => Err(error)
                 };
-                worker.run()
-            });
+            }
+            JobResult::RecordFailed(task, attempt, offset, error) => {
+                phase.assigned.remove(&(task, attempt));
+                self.discard_outputs(task, attempt);
+                if let Some(state) = phase.outstanding.get_mut(&task) {
+                    let failures = state.record_failures.entry(offset).or_insert(0);
+                    *failures += 1;
+                    if self.max_record_failures.is_some_and(|max| *failures >= max) {
+                        // Skipping the record is progress, so the task gets a
+                        // fresh set of attempts.
+                        state.job.skip_record(offset);
+                        state.failures = 0;
+                    }
+                }
+                return self.fail(phase, task, attempt, error);
+            }
+            JobResult::CorruptInput(task, attempt, path, error) => {
+                phase.assigned.remove(&(task, attempt));
+                self.discard_outputs(task, attempt);
+                let producer = self.registry
+                                   .lock()
+                                   .unwrap()
+                                   .map_outputs
+                                   .iter()
+                                   .find(|(_, outputs)| outputs.contains(&path))
+                                   .map(|(job_id, _)| *job_id);
+                if let Some(map_id) = producer {
+                    self.rerun_map(phase, map_id)?;
+                    // The attempt is not to blame for its lost input, so it
+                    // does not count against the task's attempts.
+                    if let Some(state) = phase.outstanding.get_mut(&task) {
+                        state.waiting_for.insert(map_id);
+                        state.running.remove(&attempt);
+                        return Ok(());
+                    }
+                }
+                return self.fail(phase, task, attempt, error);
+            }
+            JobResult::Failed(task, attempt, error) => {
+                phase.assigned.remove(&(task, attempt));
+                self.discard_outputs(task, attempt);
+                return self.fail(phase, task, attempt, error);
+            }
         }
+        Ok(())
     }
 
-    fn wait_for_completion(&self, n_jobs: i32) {
-        let mut n_complete = 0;
-        while n_complete < n_jobs {
-            self.results_queue.recv()
-                              .map(|_| {
-                                  n_complete += 1
-                              });
+    /// Re-runs a committed map task whose output a reduce task found corrupt,
+    /// or that was lost with the worker holding it.
+    /// The new attempt's output replaces the old files when it commits.
+    fn rerun_map(&self, phase: &mut Phase, job_id: i32) -> Result<(), MapReduceError> {
+        let task = Task::Map(job_id);
+        if phase.outstanding.contains_key(&task) {
+            return Ok(());
         }
+        let job = {
+            let registry = self.registry.lock().unwrap();
+            registry.map_jobs
+                    .get(&job_id)
+                    .map(|job| job.with_attempt(registry.launched.get(&task).map_or(job.attempt(), |&attempt| attempt) + 1))
+        };
+        if let Some(job) = job {
+            self.send_jobs(slice::from_ref(&job))?;
+            phase.outstanding.insert(task, TaskState::new(job));
+        }
+        Ok(())
     }
 
-    fn aggregate_result_files(&self) -> Vec<PathBuf> {
-        read_dir(self.working_directory.clone())
-            .map(|entries| {
-                entries.filter_map(|entry| entry.ok())
-                       .filter_map(|entry| {
-                           entry.file_name()
-                                .into_string()
-                                .ok()
-                                .and_then(|name| {
-                                    match name.split(".").last() {
-                                        Some("result") => Some(entry),
-                                        _ => None
-                                    }
-                                })
+    /// Commits the output of the first attempt of a task to finish, from the
+    /// files it reported; output of any later attempt is discarded.
+    fn finish(&self, phase: &mut Phase, task: Task, attempt: u32, outputs: Vec<PathBuf>) -> Result<(), MapReduceError> {
+        let worker = phase.assigned.remove(&(task, attempt));
+        let job = match phase.outstanding.get(&task) {
+            Some(state) => state.job.clone(),
+            None => {
+                self.discard_outputs(task, attempt);
+                return Ok(());
+            }
+        };
+        if let Err(error) = self.commit_outputs(&job, attempt, outputs) {
+            self.discard_outputs(task, attempt);
+            return self.fail(phase, task, attempt, error);
+        }
+        {
+            let mut registry = self.registry.lock().unwrap();
+            if let (Task::Map(job_id), Some(worker)) = (task, worker) {
+                registry.map_workers.insert(job_id, worker);
+            }
+            self.save_manifest(&mut registry, task)?;
+        }
+        if let Some(state) = phase.outstanding.remove(&task) {
+            if let Some(&Some(started)) = state.running.get(&attempt) {
+                phase.durations.push(started.elapsed());
+            }
+        }
+        if let Task::Map(job_id) = task {
+            for state in phase.outstanding.values_mut() {
+                if state.waiting_for.remove(&job_id) && state.waiting_for.is_empty() && state.running.is_empty() {
+                    self.launch(state)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Records a failed or abandoned attempt, launching a new one if no other
+    /// attempt of the task is still running.
+    fn fail(&self, phase: &mut Phase, task: Task, attempt: u32, error: MapReduceError) -> Result<(), MapReduceError> {
+        if let Some(state) = phase.outstanding.get_mut(&task) {
+            if state.running.remove(&attempt).is_none() {
+                return Ok(());
+            }
+            state.failures += 1;
+            if !state.running.is_empty() {
+                return Ok(());
+            }
+            if state.failures >= self.max_attempts {
+                return Err(MapReduceError::RetriesExhausted {
+                    task,
+                    attempts: state.failures,
+                    cause: Box::new(error)
+                });
+            }
+            if state.waiting_for.is_empty() {
+                self.launch(state)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Gives up on a task; any of its attempts still running are discarded
+    /// when they report back.
+    fn skip(&self, phase: &mut Phase, task: Task) -> Result<(), MapReduceError> {
+        if phase.outstanding.remove(&task).is_some() {
+            let mut registry = self.registry.lock().unwrap();
+            registry.skipped.insert(task);
+            self.save_manifest(&mut registry, task)?;
+        }
+        Ok(())
+    }
 
-                       })
-                       .map(|entry| entry.path())
-                       .collect::<Vec<PathBuf>>()
+    fn launch(&self, state: &mut TaskState) -> Result<(), MapReduceError> {
+        state.launched += 1;
+        state.running.insert(state.launched, None);
+        self.send_jobs(&[state.job.with_attempt(state.launched)])
+    }
+
+    fn check_running(&self, phase: &mut Phase, now: Instant) -> Result<(), MapReduceError> {
+        let dead = {
+            let mut heartbeats = self.heartbeats.lock().unwrap();
+            let dead = heartbeats.iter()
+                                 .filter(|&(_, seen)| now.saturating_duration_since(*seen) > self.worker_timeout)
+                                 .map(|(worker, _)| *worker)
+                                 .collect::<Vec<usize>>();
+            for worker in dead.iter() {
+                heartbeats.remove(worker);
+            }
+            dead
+        };
+        for worker in dead {
+            self.declare_dead(phase, worker)?;
+        }
+
+        if let Some(timeout) = self.task_timeout {
+            let timed_out = phase.outstanding
+                                 .iter()
+                                 .flat_map(|(task, state)| {
+                                     state.running
+                                          .iter()
+                                          .filter(|&(_, started)| started.is_some_and(|started| now - started > timeout))
+                                          .map(move |(attempt, _)| (*task, *attempt))
+                                 })
+                                 .collect::<Vec<(Task, u32)>>();
+            for (task, attempt) in timed_out {
+                phase.assigned.remove(&(task, attempt));
+                self.fail(phase, task, attempt, MapReduceError::TimedOut { task, attempt, timeout })?;
+            }
+        }
+
+        let queue_empty = phase.outstanding
+                               .values()
+                               .all(|state| state.running.values().any(|started| started.is_some()));
+        if self.speculative_execution && queue_empty && !phase.durations.is_empty() {
+            let mut durations = phase.durations.clone();
+            durations.sort();
+            let threshold = durations[durations.len() / 2] * SLOWDOWN;
+            let mut idle = phase.n_workers.saturating_sub(phase.assigned.len());
+            for state in phase.outstanding.values_mut() {
+                if idle == 0 {
+                    break;
+                }
+                let straggling = state.running
+                                      .values()
+                                      .all(|started| started.is_some_and(|started| now - started > threshold));
+                if !state.speculated && straggling {
+                    state.speculated = true;
+                    self.launch(state)?;
+                    idle -= 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails the attempts in flight on a worker that has stopped sending
+    /// heartbeats and, if its map output was lost with it, re-runs the map
+    /// tasks it committed.
+    fn declare_dead(&self, phase: &mut Phase, worker: usize) -> Result<(), MapReduceError> {
+        let attempts = phase.assigned
+                            .iter()
+                            .filter(|&(_, assignee)| *assignee == worker)
+                            .map(|(attempt, _)| *attempt)
+                            .collect::<Vec<(Task, u32)>>();
+        for (task, attempt) in attempts {
+            phase.assigned.remove(&(task, attempt));
+            self.discard_outputs(task, attempt);
+            let reason = format!("no heartbeat for {:?}", self.worker_timeout);
+            self.fail(phase, task, attempt, MapReduceError::WorkerLost { task, attempt, worker, reason })?;
+        }
+
+        if self.executor.map_output_on_workers() {
+            let lost = {
+                let mut registry = self.registry.lock().unwrap();
+                let lost = registry.map_workers
+                                   .iter()
+                                   .filter(|&(_, producer)| *producer == worker)
+                                   .map(|(job_id, _)| *job_id)
+                                   .collect::<Vec<i32>>();
+                for job_id in lost.iter() {
+                    registry.map_workers.remove(job_id);
+                }
+                lost
+            };
+            for job_id in lost {
+                self.rerun_map(phase, job_id)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Collects the temporary output files an attempt reported from the
+    /// executor, renames them into place and records them in the registry.
+    fn commit_outputs(&self, job: &Job, attempt: u32, reported: Vec<PathBuf>) -> Result<(), MapReduceError> {
+        let task = job.task();
+        let commit = |from: PathBuf, to: &Path| {
+            rename(&from, to).map_err(|e| {
+                MapReduceError::WriteOutput { task, attempt, path: to.to_path_buf(), reason: e.to_string() }
             })
-            .unwrap_or(vec![])
+        };
+        let missing = |path: PathBuf| {
+            MapReduceError::WriteOutput { task, attempt, path, reason: "the attempt reported no such output".to_string() }
+        };
+        let mut reported = reported.into_iter();
+        match task {
+            Task::Map(job_id) => {
+                let mut outputs = vec![];
+                for partition in 0..self.n_reduce {
+                    let output = map_output_name(&self.working_directory, job_id, partition);
+                    let from = reported.next().ok_or_else(|| missing(output.clone()))?;
+                    self.executor
+                        .commit_map_output(&from, &output)
+                        .map_err(|e| {
+                            MapReduceError::WriteOutput { task, attempt, path: output.clone(), reason: e.to_string() }
+                        })?;
+                    outputs.push(output);
+                }
+                let quarantine_attempt = quarantine_attempt_name(&self.working_directory, job_id, attempt);
+                if job.skips_records() {
+                    let quarantine = quarantine_name(&self.working_directory, job_id);
+                    self.executor.collect_output(&quarantine_attempt).map_err(|e| {
+                        MapReduceError::WriteOutput { task, attempt, path: quarantine.clone(), reason: e.to_string() }
+                    })?;
+                    commit(quarantine_attempt, &quarantine)?;
+                    self.registry.lock().unwrap().quarantine_files.insert(job_id, quarantine);
+                } else {
+                    self.executor.discard_output(&quarantine_attempt);
+                }
+                let mut registry = self.registry.lock().unwrap();
+                registry.map_outputs.insert(job_id, outputs);
+                registry.map_jobs.insert(job_id, job.with_attempt(attempt));
+            }
+            Task::Reduce(job_id) => {
+                let output = self.result_file(job_id);
+                let from = reported.next().ok_or_else(|| missing(output.clone()))?;
+                self.executor
+                    .collect_output(&from)
+                    .and_then(|()| self.output_format.commit(&from, &output))
+                    .map_err(|e| {
+                        MapReduceError::WriteOutput { task, attempt, path: output.clone(), reason: e.to_string() }
+                    })?;
+                self.registry.lock().unwrap().reduce_outputs.insert(job_id, output);
+            }
+        }
+        Ok(())
+    }
+
+    fn discard_outputs(&self, task: Task, attempt: u32) {
+        match task {
+            Task::Map(job_id) => {
+                for partition in 0..self.n_reduce {
+                    self.executor.discard_map_output(&map_attempt_name(&self.working_directory, job_id, attempt, partition));
+                }
+                self.executor.discard_output(&quarantine_attempt_name(&self.working_directory, job_id, attempt));
+            }
+            Task::Reduce(job_id) => {
+                self.executor.discard_output(&reduce_attempt_name(&self.working_directory, job_id, attempt));
+            }
+        }
     }
 }
 
+/// The task and attempt of a file an attempt writes in the working directory
+/// before it commits, if `name` is one.
+fn attempt_file(name: &str) -> Option<(Task, u32)> {
+    let (task, attempt) = match name.split('.').collect::<Vec<&str>>()[..] {
+        ["map", id, "reduce", _, "attempt", attempt, "tmp"] |
+        ["map", id, "reduce", _, "attempt", attempt, "spill", _, "tmp"] |
+        ["map", id, "quarantine", "attempt", attempt, "tmp"] => (Task::Map(id.parse().ok()?), attempt),
+        ["reduce", id, "attempt", attempt, "tmp"] => (Task::Reduce(id.parse().ok()?), attempt),
+        _ => return None
+    };
+    Some((task, attempt.parse().ok()?))
+}
+
+/// Records when each worker was last heard from, which is the only place
+/// worker liveness is tracked, and passes on everything but heartbeats.
+/// Workers send heartbeats all the time, but results are only read while a
+/// phase runs, so queued heartbeats would pile up.
+fn forward_results(reports: Receiver<JobResult>, results: Sender<JobResult>, heartbeats: Arc<Mutex<HashMap<usize, Instant>>>) {
+    thread::spawn(move || {
+        for report in reports.iter() {
+            match report {
+                JobResult::Heartbeat(worker) => {
+                    heartbeats.lock().unwrap().insert(worker, Instant::now());
+                }
+                JobResult::Started(worker, ..) => {
+                    heartbeats.lock().unwrap().insert(worker, Instant::now());
+                    results.send(report);
+                }
+                report => results.send(report)
+            }
+        }
+    });
+}
+
 #[cfg(test)]
 mod test {
+    use std::env;
+    use std::collections::HashMap;
     use std::fs::OpenOptions;
-    use std::io::{ Write, BufRead, BufReader };
-    use std::path::PathBuf;
-    use std::fs::{ File, remove_file };
+    use std::convert::Infallible;
+    use std::io;
+    use std::io::{ BufRead, BufReader, Write };
+    use std::path::{ Path, PathBuf };
+    use std::fs::{ File, create_dir_all, metadata, read_dir, remove_dir_all, remove_file, write };
+    use std::process;
+    use std::process::Stdio;
     use std::thread;
-    use std::sync::Arc;
-    use super::Master;
-    use worker::{ Job, JobResult };
+    use std::sync::{ Arc, Mutex };
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+    use std::time::{ Duration, Instant };
+    use chan;
+    use chan::{ Sender, Receiver };
+    use super::{ Master, JobOutput, FailurePolicy, Phase, ManifestRecord, TaskRegistry, TaskState, MANIFEST };
+    #[cfg(unix)]
+    use super::RegisteredMaster;
+    #[cfg(unix)]
+    use datum::Datum;
+    use error::MapReduceError;
+    use executor::{ Executor, RunWorker, ThreadExecutor };
+    #[cfg(unix)]
+    use executor::{ ProcessExecutor, worker_id };
+    use task::Task;
+    use split::InputSplit;
+    use input::{ Record, Delimited, FixedLength, WholeFile };
+    use output::JsonLines;
+    #[cfg(all(feature = "gzip", feature = "zstd"))]
+    use compression::{ Codec, open_result };
+    use functions::{ Mapper, Reducer, Combiner, Emitter, BoxError };
+    use partitioner::RangePartitioner;
+    use protocol::receive;
+    use registry::JobSpec;
+    #[cfg(unix)]
+    use registry::{ Registry, Functions, Named };
+    use worker::{ Job, JobResult, map_attempt_name, map_output_name, quarantine_name };
+
+    fn map_fn(_records: &mut dyn Iterator<Item = Record>, output: &mut Emitter<usize, String>) -> Result<(), BoxError> {
+        for (bucket, contents) in ["1", "2", "3", "4"].iter().enumerate() {
+            output.emit(bucket, contents.to_string());
+        }
+        Ok(())
+    }
+    fn reduce_fn(_input: Vec<BufReader<File>>) -> Result<String, BoxError> {
+        Ok("1234".to_string())
+    }
+
+    /// Counts words; rejects input that is not UTF-8.
+    struct WordCount;
+
+    impl Mapper for WordCount {
+        type Key = String;
+        type Value = u64;
+        type Error = io::Error;
+
+        fn map(&self, records: &mut dyn Iterator<Item = Record>, output: &mut Emitter<String, u64>) -> io::Result<()> {
+            for record in records {
+                let line = String::from_utf8(record.bytes).map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidData, "stream did not contain valid UTF-8")
+                })?;
+                for word in line.split_whitespace() {
+                    output.emit(word.to_lowercase(), 1);
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl Reducer for WordCount {
+        type Key = String;
+        type Value = u64;
+        type Output = u64;
+        type Error = Infallible;
+
+        fn reduce(&self, _word: &String, counts: &mut dyn Iterator<Item = u64>) -> Result<u64, Infallible> {
+            Ok(counts.sum())
+        }
+    }
+
+    /// Counts words of at least `min_length` characters.
+    #[cfg(unix)]
+    struct LongWords {
+        min_length: usize
+    }
+
+    #[cfg(unix)]
+    impl Mapper for LongWords {
+        type Key = String;
+        type Value = u64;
+        type Error = io::Error;
+
+        fn map(&self, records: &mut dyn Iterator<Item = Record>, output: &mut Emitter<String, u64>) -> io::Result<()> {
+            let min_length = self.min_length;
+            WordCount.map(records, &mut Emitter::new(&mut |word: String, count| {
+                if word.chars().count() >= min_length {
+                    output.emit(word, count);
+                }
+            }))
+        }
+    }
+
+    /// Set in the environment of a child process running a master that is
+    /// meant to die.
+    const DYING_MASTER: &str = "MAPREDUCE_TEST_DYING_MASTER";
+
+    /// Kills the process it runs in at the first key from `key` on. Counts
+    /// words until then.
+    struct DiesAt {
+        key: String
+    }
+
+    impl Reducer for DiesAt {
+        type Key = String;
+        type Value = u64;
+        type Output = u64;
+        type Error = Infallible;
+
+        fn reduce(&self, word: &String, counts: &mut dyn Iterator<Item = u64>) -> Result<u64, Infallible> {
+            if *word >= self.key {
+                process::exit(1);
+            }
+            Ok(counts.sum())
+        }
+    }
+
+    /// Never returns from the first key from `key` on, so that the process it
+    /// runs in can be killed there. Counts words until then.
+    struct HangsAt {
+        key: String
+    }
+
+    impl Reducer for HangsAt {
+        type Key = String;
+        type Value = u64;
+        type Output = u64;
+        type Error = Infallible;
+
+        fn reduce(&self, word: &String, counts: &mut dyn Iterator<Item = u64>) -> Result<u64, Infallible> {
+            if *word >= self.key {
+                loop {
+                    thread::sleep(Duration::from_secs(1));
+                }
+            }
+            Ok(counts.sum())
+        }
+    }
+
+    /// Counts words, recording how many times it maps and every key it
+    /// reduces.
+    #[derive(Default)]
+    struct Recording {
+        maps: AtomicUsize,
+        reduced: Mutex<Vec<String>>
+    }
+
+    impl Mapper for Recording {
+        type Key = String;
+        type Value = u64;
+        type Error = io::Error;
+
+        fn map(&self, records: &mut dyn Iterator<Item = Record>, output: &mut Emitter<String, u64>) -> io::Result<()> {
+            self.maps.fetch_add(1, Ordering::SeqCst);
+            WordCount.map(records, output)
+        }
+    }
+
+    impl Reducer for Recording {
+        type Key = String;
+        type Value = u64;
+        type Output = u64;
+        type Error = Infallible;
+
+        fn reduce(&self, word: &String, counts: &mut dyn Iterator<Item = u64>) -> Result<u64, Infallible> {
+            self.reduced.lock().unwrap().push(word.clone());
+            Ok(counts.sum())
+        }
+    }
 
-    fn map_fn(input: BufReader<File>) -> Vec<String> {
-        vec!["1", "2", "3", "4"].iter().map(|s| s.to_string()).collect()
+    /// Panics on its first `failures` calls, then counts words.
+    struct Flaky {
+        failures: usize,
+        calls: AtomicUsize
     }
-    fn reduce_fn(input: Vec<BufReader<File>>) -> String {
-        "1234".to_string()
+
+    impl Mapper for Flaky {
+        type Key = String;
+        type Value = u64;
+        type Error = io::Error;
+
+        fn map(&self, records: &mut dyn Iterator<Item = Record>, output: &mut Emitter<String, u64>) -> io::Result<()> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+                panic!("flaky mapper");
+            }
+            WordCount.map(records, output)
+        }
+    }
+
+    /// Kills the process it runs in the first time any process calls it, as
+    /// recorded by a marker file, then counts words.
+    #[cfg(unix)]
+    struct Crashing {
+        marker: PathBuf
+    }
+
+    #[cfg(unix)]
+    impl Mapper for Crashing {
+        type Key = String;
+        type Value = u64;
+        type Error = io::Error;
+
+        fn map(&self, records: &mut dyn Iterator<Item = Record>, output: &mut Emitter<String, u64>) -> io::Result<()> {
+            if OpenOptions::new().write(true).create_new(true).open(&self.marker).is_ok() {
+                process::exit(1);
+            }
+            WordCount.map(records, output)
+        }
+    }
+
+    /// Runs worker 1 for its first `finished` jobs, then has it take one more
+    /// and fall silent without finishing it. Worker 0 starts once worker 1
+    /// has fallen silent.
+    struct Flatline {
+        finished: usize,
+        holds_map_output: bool
+    }
+
+    impl Executor for Flatline {
+        fn start(&self,
+                 _n_workers: usize,
+                 working_directory: &Path,
+                 run_worker: RunWorker,
+                 _spec: Option<&JobSpec>,
+                 jobs: Receiver<Job>,
+                 results: Sender<JobResult>
+                 ) -> Result<(), MapReduceError>
+        {
+            let finished = self.finished;
+            let working_directory = working_directory.to_path_buf();
+            thread::spawn(move || {
+                let (job_send, job_recv) = chan::async();
+                let (result_send, result_recv) = chan::async();
+                let worker = run_worker.clone();
+                thread::spawn(move || worker(1, job_recv, result_send));
+                for _ in 0..finished {
+                    job_send.send(jobs.recv().unwrap());
+                    for result in result_recv.iter() {
+                        let started = matches!(result, JobResult::Started(..));
+                        results.send(result);
+                        if !started {
+                            break;
+                        }
+                    }
+                }
+                let job = jobs.recv().unwrap();
+                results.send(JobResult::Started(1, job.task(), job.attempt()));
+                ThreadExecutor.start(1, &working_directory, run_worker, None, jobs, results).unwrap();
+            });
+            Ok(())
+        }
+
+        fn map_output_on_workers(&self) -> bool {
+            self.holds_map_output
+        }
+    }
+
+    /// Stalls for `delay` on its first call only, then counts words.
+    struct Straggler {
+        delay: Duration,
+        calls: AtomicUsize
+    }
+
+    impl Mapper for Straggler {
+        type Key = String;
+        type Value = u64;
+        type Error = io::Error;
+
+        fn map(&self, records: &mut dyn Iterator<Item = Record>, output: &mut Emitter<String, u64>) -> io::Result<()> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                thread::sleep(self.delay);
+            }
+            WordCount.map(records, output)
+        }
+    }
+
+    /// Counts words, but panics on any input mentioning poison.
+    struct Poisoned {
+        poisonings: AtomicUsize
+    }
+
+    impl Mapper for Poisoned {
+        type Key = String;
+        type Value = u64;
+        type Error = io::Error;
+
+        fn map(&self, records: &mut dyn Iterator<Item = Record>, output: &mut Emitter<String, u64>) -> io::Result<()> {
+            let mut pairs = vec![];
+            WordCount.map(records, &mut Emitter::new(&mut |word, count| pairs.push((word, count))))?;
+            if pairs.iter().any(|(word, _)| word == "poison") {
+                self.poisonings.fetch_add(1, Ordering::SeqCst);
+                panic!("poisoned");
+            }
+            for (word, count) in pairs {
+                output.emit(word, count);
+            }
+            Ok(())
+        }
+    }
+
+    impl Combiner for WordCount {
+        type Key = String;
+        type Value = u64;
+
+        fn combine(&self, _word: &String, counts: &mut dyn Iterator<Item = u64>) -> u64 {
+            counts.sum()
+        }
+    }
+
+    /// The tasks the job manifest in `working_directory` records as
+    /// committed, in the order they committed.
+    fn committed(working_directory: &Path) -> Vec<Task> {
+        let mut manifest = BufReader::new(File::open(working_directory.join(MANIFEST)).unwrap());
+        let mut committed = vec![];
+        while let Ok(Some(record)) = receive(&mut manifest) {
+            match record {
+                ManifestRecord::MapCommitted(job) => committed.push(job.task()),
+                ManifestRecord::ReduceCommitted(job_id) => committed.push(Task::Reduce(job_id)),
+                _ => {}
+            }
+        }
+        committed
+    }
+
+    /// Word count over two inputs, with keys from "m" on in the second
+    /// partition.
+    fn resumable_word_count<M, R>(working_directory: &Path, mapper: Arc<M>, reducer: Arc<R>) -> Master<M, R>
+        where M: Mapper<Key = String, Value = u64> + 'static,
+              R: Reducer<Key = String, Value = u64, Output = u64> + 'static
+    {
+        let input_directory = PathBuf::from("./test-data/master_runs_word_count");
+        Master::with_functions(working_directory.to_path_buf(),
+                               vec![input_directory.join("input_1"), input_directory.join("input_2")],
+                               mapper,
+                               reducer,
+                               2)
+            .with_partitioner(Arc::new(RangePartitioner::new(vec!["m".to_string()])))
+    }
+
+    /// Runs the test named `test` again in a child process with
+    /// `DYING_MASTER` set, and returns whether it passed.
+    fn run_dying_master(test: &str) -> bool {
+        process::Command::new(env::current_exe().unwrap()).args([test, "--exact", "--test-threads=1"])
+                                                          .env(DYING_MASTER, "1")
+                                                          .stdout(Stdio::null())
+                                                          .stderr(Stdio::null())
+                                                          .status()
+                                                          .unwrap()
+                                                          .success()
+    }
+
+    /// Runs the test named `test` again in a child process with
+    /// `DYING_MASTER` set, and kills it once `killable` holds.
+    fn kill_master_when<F: Fn() -> bool>(test: &str, killable: F) {
+        let mut child = process::Command::new(env::current_exe().unwrap()).args([test, "--exact", "--test-threads=1"])
+                                                                          .env(DYING_MASTER, "1")
+                                                                          .stdout(Stdio::null())
+                                                                          .stderr(Stdio::null())
+                                                                          .spawn()
+                                                                          .unwrap();
+        let deadline = Instant::now() + Duration::from_secs(60);
+        while !killable() {
+            if child.try_wait().unwrap().is_some() || Instant::now() > deadline {
+                let _ = child.kill();
+                panic!("the master could not be killed when it was meant to");
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    fn read_lines(path: &PathBuf) -> Vec<String> {
+        BufReader::new(File::open(path).unwrap()).lines()
+                                                 .map(|l| l.unwrap())
+                                                 .collect()
     }
 
     #[test]
@@ -175,7 +1690,8 @@ mod test {
         let master = Master::new(working_directory.clone(),
                                  input_files.clone(),
                                  Arc::new(map_fn),
-                                 Arc::new(reduce_fn)
+                                 Arc::new(reduce_fn),
+                                 4
                                 );
 
         let job_recv = master.worker_job_queue.clone();
@@ -183,12 +1699,12 @@ mod test {
             job_recv.iter().collect::<Vec<Job>>()
         });
 
-        let n_map_jobs = master.do_map();
+        let n_map_jobs = master.do_map().unwrap().len();
         drop(master);
 
         let expected_jobs = input_files.iter()
                                        .enumerate()
-                                       .map(|(i, f)| Job::Map(((i + 1) as i32, f.clone())))
+                                       .map(|(i, f)| Job::Map(((i + 1) as i32, 1, InputSplit::whole(f.clone()), None)))
                                        .collect::<Vec<Job>>();
         assert_eq!(n_map_jobs, 4);
         assert_eq!(map_jobs.join().unwrap(), expected_jobs);
@@ -207,26 +1723,24 @@ mod test {
         let master = Master::new(working_directory.clone(),
                                  input_files,
                                  Arc::new(map_fn),
-                                 Arc::new(reduce_fn)
+                                 Arc::new(reduce_fn),
+                                 4
                                 );
 
         let job_recv = master.worker_job_queue.clone();
         let reduce_jobs = thread::spawn(move || {
-            job_recv.iter()
-                    .collect::<Vec<Job>>()
-                    .sort_by(|fst, snd| {
-                        match (fst, snd) {
-                            (&Job::Reduce((i, _)), &Job::Reduce((j, _))) => {
-                                i.cmp(&j)
-                            }
-                            _ => {
-                                1.cmp(&1)
-                            }
-                        }
-                    })
+            job_recv.iter().collect::<Vec<Job>>()
         });
 
-        let n_reduce_jobs = master.do_reduce();
+        {
+            let mut registry = master.registry.lock().unwrap();
+            for map_id in 1..(4 + 1) {
+                let outputs = (0..4).map(|partition| map_output_name(&working_directory, map_id, partition))
+                                    .collect::<Vec<PathBuf>>();
+                registry.map_outputs.insert(map_id, outputs);
+            }
+        }
+        let n_reduce_jobs = master.do_reduce().unwrap().len();
         drop(master);
 
         let expected_jobs = (1..(4 + 1)).map(|reduce_id| {
@@ -236,18 +1750,8 @@ mod test {
                 path
             }).collect::<Vec<PathBuf>>()
         }).enumerate()
-          .map(|(i, f)| Job::Reduce(((i + 1) as i32, f.clone())))
-          .collect::<Vec<Job>>()
-          .sort_by(|fst, snd| {
-              match (fst, snd) {
-                  (&Job::Reduce((i, _)), &Job::Reduce((j, _))) => {
-                      i.cmp(&j)
-                  }
-                  _ => {
-                      1.cmp(&1)
-                  }
-              }
-          });
+          .map(|(i, f)| Job::Reduce(((i + 1) as i32, 1, f.clone())))
+          .collect::<Vec<Job>>();
 
         assert_eq!(n_reduce_jobs, 4);
         assert_eq!(reduce_jobs.join().unwrap(), expected_jobs);
@@ -266,10 +1770,11 @@ mod test {
         let master = Master::new(working_directory.clone(),
                                  input_files.clone(),
                                  Arc::new(map_fn),
-                                 Arc::new(reduce_fn)
+                                 Arc::new(reduce_fn),
+                                 4
                                 );
 
-        let result_files = master.run(2);
+        let result_files = master.run(2).unwrap().result_files;
 
         let expected_files = vec!["reduce.1.result",
                                   "reduce.2.result",
@@ -307,4 +1812,947 @@ mod test {
             let _ = remove_file(result_file);
         }
     }
+
+    #[test]
+    fn silent_workers_are_declared_dead_and_their_map_output_rerun() {
+        let input_directory = PathBuf::from("./test-data/master_runs_word_count");
+        let run = |name: &str, executor: Flatline| {
+            let working_directory = PathBuf::from("./test-data").join(name);
+            create_dir_all(&working_directory).unwrap();
+            let mapper = Arc::new(Flaky { failures: 0, calls: AtomicUsize::new(0) });
+            let master = Master::with_functions(working_directory.clone(),
+                                                vec![input_directory.join("input_1"), input_directory.join("input_2")],
+                                                mapper.clone(),
+                                                Arc::new(WordCount),
+                                                2)
+                .with_executor(Arc::new(executor))
+                .with_worker_timeout(Duration::from_millis(300));
+
+            let results = master.run(2)
+                                .unwrap()
+                                .result_files
+                                .iter()
+                                .flat_map(read_lines)
+                                .collect::<Vec<String>>();
+            assert!(results.contains(&"the=4".to_string()));
+            remove_dir_all(&working_directory).unwrap();
+            mapper.calls.load(Ordering::SeqCst)
+        };
+
+        // The map task the silent worker took is re-queued.
+        assert_eq!(run("master_requeues_a_dead_workers_task", Flatline { finished: 0, holds_map_output: false }), 2);
+        // So is the map task it finished, whose output it held.
+        assert_eq!(run("master_reruns_a_dead_workers_map_output", Flatline { finished: 1, holds_map_output: true }), 3);
+        // Unless its output is kept elsewhere.
+        assert_eq!(run("master_keeps_a_dead_workers_map_output", Flatline { finished: 1, holds_map_output: false }), 2);
+    }
+
+    #[test]
+    fn run_ignores_stale_files_in_the_working_directory() {
+        let input_directory = PathBuf::from("./test-data/master_runs_word_count");
+        let working_directory = PathBuf::from("./test-data/master_ignores_stale_files");
+        create_dir_all(&working_directory).unwrap();
+        write(working_directory.join("map.7.reduce.1"), "left over from an earlier run").unwrap();
+        write(working_directory.join("reduce.3.result"), "left over from an earlier run").unwrap();
+        write(working_directory.join("notes.result"), "not ours").unwrap();
+        let master = Master::with_functions(working_directory.clone(),
+                                            vec![input_directory.join("input_1"), input_directory.join("input_2")],
+                                            Arc::new(WordCount),
+                                            Arc::new(WordCount),
+                                            2);
+
+        let result_files = master.run(2).unwrap().result_files;
+
+        assert_eq!(result_files, vec![working_directory.join("reduce.1.result"),
+                                      working_directory.join("reduce.2.result")]);
+        let results = result_files.iter()
+                                  .flat_map(read_lines)
+                                  .collect::<Vec<String>>();
+        assert!(results.contains(&"the=4".to_string()));
+        assert!(!results.iter().any(|line| line.contains("earlier run")));
+        remove_dir_all(&working_directory).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn process_workers_retry_the_attempt_of_a_crashed_worker() {
+        let input_directory = PathBuf::from("./test-data/master_runs_word_count");
+        let working_directory = PathBuf::from("./test-data/master_runs_process_workers");
+        // Worker processes run this test too, until `run` turns them into
+        // workers, so only the master may set up the working directory.
+        if worker_id().is_none() {
+            let _ = remove_dir_all(&working_directory);
+            create_dir_all(&working_directory).unwrap();
+        }
+        let executor = ProcessExecutor::new().unwrap()
+                                             .with_args(vec!["master::test::process_workers_retry_the_attempt_of_a_crashed_worker",
+                                                             "--exact",
+                                                             "--test-threads=1"]);
+        let master = Master::with_functions(working_directory.clone(),
+                                            vec![input_directory.join("input_1"), input_directory.join("input_2")],
+                                            Arc::new(Crashing { marker: working_directory.join("crashed") }),
+                                            Arc::new(WordCount),
+                                            2)
+            .with_executor(Arc::new(executor));
+
+        let results = master.run(2)
+                            .unwrap()
+                            .result_files
+                            .iter()
+                            .flat_map(read_lines)
+                            .collect::<Vec<String>>();
+
+        assert!(working_directory.join("crashed").exists(), "no worker process crashed");
+        assert!(results.contains(&"the=4".to_string()));
+        remove_dir_all(&working_directory).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn process_workers_look_up_registered_functions() {
+        let registry = Registry::new()
+            .mapper("long_words", |config: &[u8]| {
+                u64::decode(config).map(|min_length| LongWords { min_length: min_length as usize })
+                                   .ok_or_else(|| BoxError::from("expected the minimum length as a big-endian u64"))
+            })
+            .reducer("count", |_: &[u8]| Ok(WordCount))
+            .combiner("sum", |_: &[u8]| Ok(WordCount));
+        // Worker processes build the functions the master names and exit
+        // here, so they never build a master of their own.
+        registry.serve_if_worker();
+
+        let input_directory = PathBuf::from("./test-data/master_runs_word_count");
+        let working_directory = PathBuf::from("./test-data/master_runs_registered_functions");
+        let _ = remove_dir_all(&working_directory);
+        create_dir_all(&working_directory).unwrap();
+        let delimited = working_directory.join("delimited");
+        write(&delimited, "martians|tripods|the|").unwrap();
+        let executor = ProcessExecutor::new().unwrap()
+                                             .with_args(vec!["master::test::process_workers_look_up_registered_functions",
+                                                             "--exact",
+                                                             "--test-threads=1"]);
+        let functions = Functions::new(Named::new("long_words").with_config(7u64.to_be_bytes().to_vec()), Named::new("count"))
+            .with_combiner(Named::new("sum"))
+            .with_output_format(Named::new("tsv"))
+            .with_input(delimited, Named::new("delimited").with_config(b"|".to_vec()));
+        let master = RegisteredMaster::<String, u64, u64>::from_registry(&registry,
+                                                                         working_directory.clone(),
+                                                                         vec![input_directory.join("input_1"), input_directory.join("input_2")],
+                                                                         functions,
+                                                                         2)
+            .unwrap()
+            .with_executor(Arc::new(executor));
+
+        let mut results = master.run(2)
+                                .unwrap()
+                                .result_files
+                                .iter()
+                                .flat_map(read_lines)
+                                .collect::<Vec<String>>();
+        results.sort();
+
+        assert_eq!(results, vec!["crossed\t1", "horsell\t1", "martians\t2", "tripods\t2"]);
+        remove_dir_all(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn resume_skips_map_tasks_committed_before_the_master_died() {
+        let working_directory = PathBuf::from("./test-data/master_resumes_between_phases");
+        if env::var_os(DYING_MASTER).is_some() {
+            let dies = DiesAt { key: String::new() };
+            let _ = resumable_word_count(&working_directory, Arc::new(WordCount), Arc::new(dies)).run(1);
+            return;
+        }
+        let _ = remove_dir_all(&working_directory);
+        create_dir_all(&working_directory).unwrap();
+
+        assert!(!run_dying_master("master::test::resume_skips_map_tasks_committed_before_the_master_died"));
+        let mut committed = committed(&working_directory);
+        committed.sort();
+        assert_eq!(committed, vec![Task::Map(1), Task::Map(2)]);
+
+        let recording = Arc::new(Recording::default());
+        let output = resumable_word_count(&working_directory, recording.clone(), recording.clone()).resume(2)
+                                                                                                     .unwrap();
+        let results = output.result_files
+                            .iter()
+                            .flat_map(read_lines)
+                            .collect::<Vec<String>>();
+
+        assert_eq!(recording.maps.load(Ordering::SeqCst), 0);
+        assert!(results.contains(&"common=2".to_string()));
+        assert!(results.contains(&"the=4".to_string()));
+        remove_dir_all(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn resume_continues_a_reduce_phase_the_master_was_killed_in() {
+        let working_directory = PathBuf::from("./test-data/master_resumes_mid_reduce");
+        if env::var_os(DYING_MASTER).is_some() {
+            let hangs = HangsAt { key: "m".to_string() };
+            let _ = resumable_word_count(&working_directory, Arc::new(WordCount), Arc::new(hangs)).run(1);
+            return;
+        }
+        let _ = remove_dir_all(&working_directory);
+        create_dir_all(&working_directory).unwrap();
+
+        let killed_attempt = working_directory.join("reduce.2.attempt.1.tmp");
+        kill_master_when("master::test::resume_continues_a_reduce_phase_the_master_was_killed_in", || {
+            killed_attempt.exists() && working_directory.join(MANIFEST).exists()
+                && committed(&working_directory).contains(&Task::Reduce(1))
+        });
+        assert_eq!(committed(&working_directory).into_iter().filter(|task| matches!(task, Task::Reduce(_))).collect::<Vec<Task>>(),
+                   vec![Task::Reduce(1)]);
+        // As if a later attempt had been launched after the manifest was saved.
+        let leftover = working_directory.join("reduce.2.attempt.3.tmp");
+        write(&leftover, "the=1000\n").unwrap();
+
+        let recording = Arc::new(Recording::default());
+        let output = resumable_word_count(&working_directory, recording.clone(), recording.clone()).resume(2)
+                                                                                                     .unwrap();
+
+        assert_eq!(recording.maps.load(Ordering::SeqCst), 0);
+        let reduced = recording.reduced.lock().unwrap().clone();
+        assert!(!reduced.is_empty() && reduced.iter().all(|word| word.as_str() >= "m"), "reduced {:?}", reduced);
+        assert!(!working_directory.join(MANIFEST).exists());
+        assert!(!killed_attempt.exists());
+        assert!(!leftover.exists());
+        assert_eq!(output.result_files, vec![working_directory.join("reduce.1.result"),
+                                             working_directory.join("reduce.2.result")]);
+        assert_eq!(read_lines(&output.result_files[0]), vec!["at=1", "common=2", "crossed=1", "heat=1", "horsell=1", "landed=1"]);
+        assert_eq!(read_lines(&output.result_files[1]), vec!["martians=1", "ray=1", "the=4", "tripods=1"]);
+        remove_dir_all(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn the_manifest_is_a_log_of_commits() {
+        let working_directory = PathBuf::from("./test-data/master_logs_commits");
+        let _ = remove_dir_all(&working_directory);
+        create_dir_all(&working_directory).unwrap();
+        let master = resumable_word_count(&working_directory, Arc::new(WordCount), Arc::new(WordCount));
+        let mut registry = TaskRegistry::default();
+        master.start_manifest(&mut registry).unwrap();
+
+        let job = master.map_jobs()[0].with_attempt(2);
+        registry.launched.insert(Task::Map(1), 2);
+        registry.unsaved.insert(Task::Map(1));
+        registry.map_jobs.insert(1, job.clone());
+        registry.quarantine_files.insert(1, quarantine_name(&working_directory, 1));
+        master.save_manifest(&mut registry, Task::Map(1)).unwrap();
+        for partition in 0..2 {
+            write(map_output_name(&working_directory, 1, partition), "").unwrap();
+        }
+        // A record the master was killed while appending.
+        let mut manifest = OpenOptions::new().append(true).open(working_directory.join(MANIFEST)).unwrap();
+        manifest.write_all(&[0, 0, 0, 9, 0, 2]).unwrap();
+
+        let loaded = master.load_manifest().unwrap();
+        assert_eq!(loaded.launched.get(&Task::Map(1)), Some(&2));
+        assert_eq!(loaded.map_jobs.get(&1), Some(&job));
+        assert_eq!(loaded.map_outputs.get(&1), Some(&vec![map_output_name(&working_directory, 1, 0),
+                                                          map_output_name(&working_directory, 1, 1)]));
+        assert_eq!(loaded.quarantine_files.get(&1), Some(&quarantine_name(&working_directory, 1)));
+
+        let input_directory = PathBuf::from("./test-data/master_runs_word_count");
+        let other = Master::with_functions(working_directory.clone(),
+                                           vec![input_directory.join("input_1"), input_directory.join("input_2")],
+                                           Arc::new(WordCount),
+                                           Arc::new(WordCount),
+                                           3);
+        match other.load_manifest() {
+            Err(MapReduceError::InvalidWorkingDirectory { reason, .. }) => assert!(reason.contains("3 reduce partitions"), "{}", reason),
+            _ => panic!("expected the manifest to be rejected")
+        }
+        remove_dir_all(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn jobs_without_reduce_partitions_are_rejected() {
+        let input_directory = PathBuf::from("./test-data/master_runs_word_count");
+        let master = Master::with_functions(input_directory.clone(),
+                                            vec![input_directory.join("input_1")],
+                                            Arc::new(WordCount),
+                                            Arc::new(WordCount),
+                                            0);
+
+        match master.run(1) {
+            Err(MapReduceError::InvalidConfiguration { .. }) => {}
+            other => panic!("expected InvalidConfiguration, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn split_inputs_count_the_same_as_whole_inputs() {
+        let input_directory = PathBuf::from("./test-data/master_runs_word_count");
+        let input_files = vec![input_directory.join("input_1"), input_directory.join("input_2")];
+        let run = |name: &str, split_size: Option<u64>| {
+            let working_directory = PathBuf::from("./test-data").join(name);
+            create_dir_all(&working_directory).unwrap();
+            let mut master = Master::with_functions(working_directory.clone(),
+                                                    input_files.clone(),
+                                                    Arc::new(WordCount),
+                                                    Arc::new(WordCount),
+                                                    2);
+            if let Some(split_size) = split_size {
+                master = master.with_split_size(split_size);
+            }
+            let mut results = master.run(2)
+                                    .unwrap()
+                                    .result_files
+                                    .iter()
+                                    .flat_map(read_lines)
+                                    .collect::<Vec<String>>();
+            results.sort();
+            remove_dir_all(&working_directory).unwrap();
+            results
+        };
+
+        let whole_results = run("master_maps_whole_inputs", None);
+        let split_results = run("master_maps_split_inputs", Some(10));
+
+        let n_splits = input_files.iter()
+                                  .map(|input| InputSplit::split(input, 10).len())
+                                  .collect::<Vec<usize>>();
+        assert_eq!(n_splits, vec![6, 4]);
+        assert_eq!(split_results, whole_results);
+        assert!(split_results.contains(&"the=4".to_string()));
+    }
+
+    #[test]
+    fn inputs_can_mix_formats() {
+        let working_directory = PathBuf::from("./test-data/master_mixes_input_formats");
+        create_dir_all(&working_directory).unwrap();
+        let lines = working_directory.join("lines");
+        let delimited = working_directory.join("delimited");
+        let fixed_length = working_directory.join("fixed_length");
+        let whole_file = working_directory.join("whole_file");
+        write(&lines, "the heat ray\nthe tripods\n").unwrap();
+        write(&delimited, "martians|tripods|").unwrap();
+        write(&fixed_length, "red weed").unwrap();
+        write(&whole_file, "the end\nof the martians").unwrap();
+
+        let output = Master::with_functions(working_directory.clone(),
+                                            vec![lines],
+                                            Arc::new(WordCount),
+                                            Arc::new(WordCount),
+                                            1)
+            .with_input(delimited, Arc::new(Delimited::new(b"|")))
+            .with_input(fixed_length, Arc::new(FixedLength::new(4)))
+            .with_input(whole_file, Arc::new(WholeFile))
+            .with_split_size(5)
+            .run(2)
+            .unwrap();
+
+        assert_eq!(read_lines(&output.result_files[0]),
+                   vec!["end=1", "heat=1", "martians=2", "of=1", "ray=1", "red=1", "the=4", "tripods=2", "weed=1"]);
+        remove_dir_all(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn results_are_written_in_the_output_format() {
+        let working_directory = PathBuf::from("./test-data/master_writes_json_lines");
+        create_dir_all(&working_directory).unwrap();
+        let input = working_directory.join("input");
+        write(&input, "the heat ray\nthe tripods\n").unwrap();
+
+        let output = Master::with_functions(working_directory.clone(),
+                                            vec![input],
+                                            Arc::new(WordCount),
+                                            Arc::new(WordCount),
+                                            1)
+            .with_output_format(Arc::new(JsonLines))
+            .run(2)
+            .unwrap();
+
+        assert_eq!(output.result_files, vec![working_directory.join("reduce.1.jsonl")]);
+        assert_eq!(read_lines(&output.result_files[0]),
+                   vec!["{\"key\":\"heat\",\"value\":1}",
+                        "{\"key\":\"ray\",\"value\":1}",
+                        "{\"key\":\"the\",\"value\":2}",
+                        "{\"key\":\"tripods\",\"value\":1}"]);
+        remove_dir_all(&working_directory).unwrap();
+    }
+
+    #[test]
+    #[cfg(all(feature = "gzip", feature = "zstd"))]
+    fn intermediate_and_result_files_can_be_compressed() {
+        let input_directory = PathBuf::from("./test-data/master_runs_word_count");
+        let input_files = vec![input_directory.join("input_1"), input_directory.join("input_2")];
+        let run = |name: &str, intermediate: Codec, output: Codec| {
+            let working_directory = PathBuf::from("./test-data").join(name);
+            create_dir_all(&working_directory).unwrap();
+            let result_files = Master::with_functions(working_directory.clone(),
+                                                      input_files.clone(),
+                                                      Arc::new(WordCount),
+                                                      Arc::new(WordCount),
+                                                      2)
+                .with_intermediate_compression(intermediate)
+                .with_output_compression(output)
+                .run(2)
+                .unwrap()
+                .result_files;
+            let results = result_files.iter()
+                                      .flat_map(|path| open_result(path).unwrap().lines().map(|l| l.unwrap()))
+                                      .collect::<Vec<String>>();
+            remove_dir_all(&working_directory).unwrap();
+            (result_files, results)
+        };
+
+        let (_, plain) = run("master_writes_plain_files", Codec::None, Codec::None);
+        let (result_files, compressed) = run("master_writes_compressed_files", Codec::Zstd, Codec::Gzip);
+
+        assert_eq!(result_files[0].file_name().unwrap(), "reduce.1.result.gz");
+        assert_eq!(compressed, plain);
+    }
+
+    #[test]
+    fn corrupt_map_outputs_are_regenerated() {
+        let working_directory = PathBuf::from("./test-data/master_regenerates_corrupt_outputs");
+        create_dir_all(&working_directory).unwrap();
+        let input = working_directory.join("input");
+        write(&input, "the heat ray\nthe tripods\n").unwrap();
+        let map_output = map_output_name(&working_directory, 1, 0);
+        write(&map_output, "not a sorted run").unwrap();
+
+        // Pretend map task 1 committed the corrupt file, then run the reduce phase.
+        let master = Master::with_functions(working_directory.clone(),
+                                            vec![input.clone()],
+                                            Arc::new(WordCount),
+                                            Arc::new(WordCount),
+                                            1);
+        {
+            let mut registry = master.registry.lock().unwrap();
+            registry.map_jobs.insert(1, Job::Map((1, 1, InputSplit::whole(input), None)));
+            registry.map_outputs.insert(1, vec![map_output]);
+        }
+        master.spawn_workers(2).unwrap();
+        let reduce_jobs = master.do_reduce().unwrap();
+        master.wait_for_completion(reduce_jobs, 2).unwrap();
+
+        let registry = master.registry.lock().unwrap();
+        assert_eq!(read_lines(&registry.reduce_outputs[&1]),
+                   vec!["heat=1", "ray=1", "the=2", "tripods=1"]);
+        assert_eq!(registry.map_jobs[&1].attempt(), 2);
+        drop(registry);
+        remove_dir_all(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn run_map_reduce_with_range_partitioner() {
+        let input_directory = PathBuf::from("./test-data/master_runs_word_count");
+        let working_directory = PathBuf::from("./test-data/master_runs_range_partitioner");
+        create_dir_all(&working_directory).unwrap();
+        let input_files = vec![input_directory.join("input_1"), input_directory.join("input_2")];
+        let word_count = Arc::new(WordCount);
+        let partitioner = RangePartitioner::sample_inputs(&*word_count, &input_files, 2, 100);
+        let master = Master::with_functions(working_directory.clone(),
+                                            input_files,
+                                            word_count.clone(),
+                                            word_count,
+                                            2)
+            .with_partitioner(Arc::new(partitioner));
+
+        let result_files = master.run(2).unwrap().result_files;
+        assert_eq!(result_files.len(), 2);
+
+        let results = result_files.iter()
+                                  .map(read_lines)
+                                  .collect::<Vec<Vec<String>>>();
+        assert!(results.iter().all(|lines| !lines.is_empty()));
+        assert!(results[0].last() < results[1].first());
+        assert_eq!(results.concat(),
+                   vec!["at=1", "common=2", "crossed=1", "heat=1", "horsell=1", "landed=1",
+                        "martians=1", "ray=1", "the=4", "tripods=1"]);
+
+        remove_dir_all(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn combiner_shrinks_map_output_without_changing_results() {
+        let input_directory = PathBuf::from("./test-data/master_runs_word_count");
+        let input_files = vec![input_directory.join("input_1"), input_directory.join("input_2")];
+
+        let run = |name: &str, combine: bool| {
+            let working_directory = PathBuf::from(format!("./test-data/master_combines_map_output_{}", name));
+            create_dir_all(&working_directory).unwrap();
+            let word_count = Arc::new(WordCount);
+            let mut master = Master::with_functions(working_directory.clone(),
+                                                    input_files.clone(),
+                                                    word_count.clone(),
+                                                    word_count.clone(),
+                                                    2);
+            if combine {
+                master = master.with_combiner(word_count);
+            }
+
+            let results = master.run(2)
+                                .unwrap()
+                                .result_files
+                                .iter()
+                                .flat_map(read_lines)
+                                .collect::<Vec<String>>();
+            let map_output_size = (1..3).flat_map(|map_id| (1..3).map(move |reduce_id| (map_id, reduce_id)))
+                                        .map(|(map_id, reduce_id)| {
+                                            let name = format!("map.{}.reduce.{}", map_id, reduce_id);
+                                            metadata(working_directory.join(name)).unwrap().len()
+                                        })
+                                        .sum::<u64>();
+            remove_dir_all(&working_directory).unwrap();
+            (map_output_size, results)
+        };
+
+        let (plain_size, mut plain_results) = run("plain", false);
+        let (combined_size, mut combined_results) = run("combined", true);
+        plain_results.sort();
+        combined_results.sort();
+
+        assert!(combined_size < plain_size);
+        assert_eq!(combined_results, plain_results);
+        assert!(combined_results.contains(&"the=4".to_string()));
+    }
+
+    #[test]
+    fn spilled_map_output_counts_the_same_as_buffered() {
+        let input_directory = PathBuf::from("./test-data/master_runs_word_count");
+        let input_files = vec![input_directory.join("input_1"), input_directory.join("input_2")];
+
+        let run = |name: &str, map_buffer_size: usize, combine: bool| {
+            let working_directory = PathBuf::from(format!("./test-data/master_spills_map_output_{}", name));
+            create_dir_all(&working_directory).unwrap();
+            let word_count = Arc::new(WordCount);
+            let mut master = Master::with_functions(working_directory.clone(),
+                                                    input_files.clone(),
+                                                    word_count.clone(),
+                                                    word_count.clone(),
+                                                    2)
+                .with_map_buffer_size(map_buffer_size);
+            if combine {
+                master = master.with_combiner(word_count);
+            }
+
+            let mut results = master.run(2)
+                                    .unwrap()
+                                    .result_files
+                                    .iter()
+                                    .flat_map(read_lines)
+                                    .collect::<Vec<String>>();
+            results.sort();
+            let leftovers = read_dir(&working_directory).unwrap()
+                                                        .filter(|entry| {
+                                                            entry.as_ref().unwrap().file_name().to_string_lossy().ends_with(".tmp")
+                                                        })
+                                                        .count();
+            remove_dir_all(&working_directory).unwrap();
+            (results, leftovers)
+        };
+
+        let (buffered, _) = run("buffered", 1024 * 1024, false);
+        assert!(buffered.contains(&"the=4".to_string()));
+        assert_eq!(run("spilled", 64, false), (buffered.clone(), 0));
+        assert_eq!(run("spilled_and_combined", 64, true), (buffered, 0));
+    }
+
+    #[test]
+    fn failed_tasks_are_retried() {
+        let input_directory = PathBuf::from("./test-data/master_runs_word_count");
+        let working_directory = PathBuf::from("./test-data/master_retries_failed_tasks");
+        create_dir_all(&working_directory).unwrap();
+        let mapper = Arc::new(Flaky { failures: 2, calls: AtomicUsize::new(0) });
+        let master = Master::with_functions(working_directory.clone(),
+                                            vec![input_directory.join("input_1")],
+                                            mapper.clone(),
+                                            Arc::new(WordCount),
+                                            1)
+            .with_max_attempts(3);
+
+        let result_files = master.run(2).unwrap().result_files;
+
+        assert_eq!(mapper.calls.load(Ordering::SeqCst), 3);
+        assert!(read_lines(&result_files[0]).contains(&"the=2".to_string()));
+        remove_dir_all(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn run_fails_once_retries_are_exhausted() {
+        let input_directory = PathBuf::from("./test-data/master_runs_word_count");
+        let working_directory = PathBuf::from("./test-data/master_exhausts_retries");
+        create_dir_all(&working_directory).unwrap();
+        let mapper = Arc::new(Flaky { failures: usize::MAX, calls: AtomicUsize::new(0) });
+        let master = Master::with_functions(working_directory.clone(),
+                                            vec![input_directory.join("input_1")],
+                                            mapper.clone(),
+                                            Arc::new(WordCount),
+                                            1)
+            .with_max_attempts(2);
+
+        match master.run(2) {
+            Err(MapReduceError::RetriesExhausted { task, attempts, cause }) => {
+                assert_eq!(task, Task::Map(1));
+                assert_eq!(attempts, 2);
+                assert_eq!(*cause, MapReduceError::Panicked {
+                    task: Task::Map(1),
+                    attempt: 2,
+                    message: "flaky mapper".to_string()
+                });
+            }
+            other => panic!("expected exhausted retries, got {:?}", other)
+        }
+        assert_eq!(mapper.calls.load(Ordering::SeqCst), 2);
+        remove_dir_all(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn run_rejects_a_missing_working_directory() {
+        let working_directory = PathBuf::from("./test-data/master_has_no_working_directory");
+        let master = Master::with_functions(working_directory.clone(),
+                                            vec![],
+                                            Arc::new(WordCount),
+                                            Arc::new(WordCount),
+                                            1);
+
+        match master.run(1) {
+            Err(MapReduceError::InvalidWorkingDirectory { path, .. }) => assert_eq!(path, working_directory),
+            other => panic!("expected an invalid working directory, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn run_reports_the_input_that_could_not_be_read() {
+        let working_directory = PathBuf::from("./test-data/master_reports_unreadable_inputs");
+        create_dir_all(&working_directory).unwrap();
+        let missing_input = working_directory.join("missing_input");
+        let master = Master::with_functions(working_directory.clone(),
+                                            vec![missing_input.clone()],
+                                            Arc::new(WordCount),
+                                            Arc::new(WordCount),
+                                            1)
+            .with_max_attempts(2);
+
+        match master.run(1) {
+            Err(MapReduceError::RetriesExhausted { task, attempts, cause }) => {
+                assert_eq!((task, attempts), (Task::Map(1), 2));
+                match *cause {
+                    MapReduceError::ReadInput { task, attempt, path, .. } => {
+                        assert_eq!((task, attempt, path), (Task::Map(1), 2, missing_input));
+                    }
+                    other => panic!("expected an unreadable input, got {:?}", other)
+                }
+            }
+            other => panic!("expected exhausted retries, got {:?}", other)
+        }
+        remove_dir_all(&working_directory).unwrap();
+    }
+
+    /// Runs word count over `input_1` and an input that is not UTF-8, which
+    /// `WordCount` rejects.
+    fn run_with_rejected_input(name: &str, failure_policy: FailurePolicy) -> Result<JobOutput, MapReduceError> {
+        let input_directory = PathBuf::from("./test-data/master_runs_word_count");
+        let working_directory = PathBuf::from("./test-data").join(name);
+        create_dir_all(&working_directory).unwrap();
+        let rejected_input = working_directory.join("rejected_input");
+        write(&rejected_input, [0xff, 0xfe, b'\n']).unwrap();
+        let master = Master::with_functions(working_directory.clone(),
+                                            vec![input_directory.join("input_1"), rejected_input],
+                                            Arc::new(WordCount),
+                                            Arc::new(WordCount),
+                                            1)
+            .with_max_attempts(2)
+            .with_failure_policy(failure_policy);
+
+        let output = master.run(2);
+        if let Ok(ref output) = output {
+            assert!(read_lines(&output.result_files[0]).contains(&"the=2".to_string()));
+        }
+        remove_dir_all(&working_directory).unwrap();
+        output
+    }
+
+    #[test]
+    fn rejected_inputs_are_retried_by_default() {
+        match run_with_rejected_input("master_retries_rejected_inputs", FailurePolicy::Retry) {
+            Err(MapReduceError::RetriesExhausted { task, attempts, cause }) => {
+                assert_eq!((task, attempts), (Task::Map(2), 2));
+                match *cause {
+                    MapReduceError::FunctionFailed { task, attempt, message } => {
+                        assert_eq!((task, attempt), (Task::Map(2), 2));
+                        assert!(message.contains("UTF-8"));
+                    }
+                    other => panic!("expected a failed map function, got {:?}", other)
+                }
+            }
+            other => panic!("expected exhausted retries, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn rejected_inputs_can_be_skipped() {
+        let output = run_with_rejected_input("master_skips_rejected_inputs", FailurePolicy::Skip).unwrap();
+
+        assert_eq!(output.skipped, vec![Task::Map(2)]);
+        assert_eq!(output.result_files.len(), 1);
+    }
+
+    #[test]
+    fn rejected_inputs_can_abort_the_run() {
+        match run_with_rejected_input("master_aborts_on_rejected_inputs", FailurePolicy::Abort) {
+            Err(MapReduceError::FunctionFailed { task, attempt, .. }) => {
+                assert_eq!((task, attempt), (Task::Map(2), 1));
+            }
+            other => panic!("expected a failed map function, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn bad_records_are_skipped_and_quarantined() {
+        let working_directory = PathBuf::from("./test-data/master_skips_bad_records");
+        create_dir_all(&working_directory).unwrap();
+        let input = working_directory.join("input_1");
+        write(&input, "the heat ray\npoison pill\nthe tripods\n").unwrap();
+        let mapper = Arc::new(Poisoned { poisonings: AtomicUsize::new(0) });
+        let master = Master::with_functions(working_directory.clone(),
+                                            vec![input],
+                                            mapper.clone(),
+                                            Arc::new(WordCount),
+                                            1)
+            .with_max_attempts(2)
+            .with_bad_record_skipping(2);
+
+        let output = master.run(2).unwrap();
+
+        assert_eq!(mapper.poisonings.load(Ordering::SeqCst), 2);
+        let results = read_lines(&output.result_files[0]);
+        assert!(results.contains(&"the=2".to_string()));
+        assert!(results.contains(&"tripods=1".to_string()));
+        assert!(!results.iter().any(|line| line.starts_with("poison")));
+        assert_eq!(output.quarantine_files, vec![working_directory.join("map.1.quarantine")]);
+        assert_eq!(read_lines(&output.quarantine_files[0]), vec!["13\tpoison pill".to_string()]);
+        assert!(temp_files(&working_directory).is_empty());
+        remove_dir_all(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn timed_out_tasks_are_relaunched() {
+        let input_directory = PathBuf::from("./test-data/master_runs_word_count");
+        let working_directory = PathBuf::from("./test-data/master_relaunches_timed_out_tasks");
+        create_dir_all(&working_directory).unwrap();
+        let mapper = Arc::new(Straggler { delay: Duration::from_secs(5), calls: AtomicUsize::new(0) });
+        let master = Master::with_functions(working_directory.clone(),
+                                            vec![input_directory.join("input_1")],
+                                            mapper.clone(),
+                                            Arc::new(WordCount),
+                                            1)
+            .with_task_timeout(Duration::from_millis(100));
+
+        let start = Instant::now();
+        let result_files = master.run(2).unwrap().result_files;
+
+        assert!(start.elapsed() < Duration::from_secs(4));
+        assert_eq!(mapper.calls.load(Ordering::SeqCst), 2);
+        assert!(read_lines(&result_files[0]).contains(&"the=2".to_string()));
+        remove_dir_all(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn timed_out_attempts_no_longer_hold_their_worker() {
+        let input_directory = PathBuf::from("./test-data/master_runs_word_count");
+        let working_directory = PathBuf::from("./test-data/master_unassigns_timed_out_attempts");
+        create_dir_all(&working_directory).unwrap();
+        let master = Master::with_functions(working_directory.clone(),
+                                            vec![input_directory.join("input_1")],
+                                            Arc::new(WordCount),
+                                            Arc::new(WordCount),
+                                            1)
+            .with_task_timeout(Duration::from_millis(100));
+        let mut phase = Phase {
+            outstanding: master.do_map().unwrap().into_iter().map(|job| (job.task(), TaskState::new(job))).collect(),
+            durations: vec![],
+            assigned: HashMap::new(),
+            n_workers: 1
+        };
+        master.record_result(&mut phase, JobResult::Started(0, Task::Map(1), 1)).unwrap();
+
+        master.check_running(&mut phase, Instant::now() + Duration::from_millis(200)).unwrap();
+
+        assert!(phase.assigned.is_empty());
+        assert!(phase.outstanding[&Task::Map(1)].running.contains_key(&2));
+        remove_dir_all(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn heartbeats_do_not_queue_up_while_no_phase_runs() {
+        let working_directory = PathBuf::from("./test-data/master_records_heartbeats");
+        create_dir_all(&working_directory).unwrap();
+        let master = Master::with_functions(working_directory.clone(), vec![], Arc::new(WordCount), Arc::new(WordCount), 1);
+        master.spawn_workers(2).unwrap();
+
+        thread::sleep(Duration::from_millis(300));
+
+        assert_eq!(master.heartbeats.lock().unwrap().len(), 2);
+        let results = master.results_queue.clone();
+        chan_select! {
+            default => {},
+            results.recv() -> result => panic!("expected no queued results, got {:?}", result)
+        }
+        remove_dir_all(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn stragglers_get_a_backup_attempt() {
+        let input_directory = PathBuf::from("./test-data/master_runs_word_count");
+        let working_directory = PathBuf::from("./test-data/master_speculates_on_stragglers");
+        create_dir_all(&working_directory).unwrap();
+        let input_files = vec!["input_1", "input_2", "input_1", "input_2"].into_iter()
+                                                                      .map(|name| input_directory.join(name))
+                                                                      .collect::<Vec<PathBuf>>();
+        let mapper = Arc::new(Straggler { delay: Duration::from_secs(5), calls: AtomicUsize::new(0) });
+        let master = Master::with_functions(working_directory.clone(),
+                                            input_files,
+                                            mapper.clone(),
+                                            Arc::new(WordCount),
+                                            2)
+            .with_speculative_execution();
+
+        let start = Instant::now();
+        let results = master.run(3)
+                            .unwrap()
+                            .result_files
+                            .iter()
+                            .flat_map(read_lines)
+                            .collect::<Vec<String>>();
+
+        assert!(start.elapsed() < Duration::from_secs(4));
+        assert_eq!(mapper.calls.load(Ordering::SeqCst), 5);
+        assert!(results.contains(&"the=8".to_string()));
+        assert!(results.contains(&"common=4".to_string()));
+        remove_dir_all(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn failures_of_losing_attempts_are_ignored() {
+        let input_directory = PathBuf::from("./test-data/master_runs_word_count");
+        let working_directory = PathBuf::from("./test-data/master_ignores_losing_attempts");
+        create_dir_all(&working_directory).unwrap();
+        let master = Master::with_functions(working_directory.clone(),
+                                            vec![input_directory.join("input_1")],
+                                            Arc::new(WordCount),
+                                            Arc::new(WordCount),
+                                            1)
+            .with_speculative_execution()
+            .with_failure_policy(FailurePolicy::Abort);
+        master.spawn_workers(1).unwrap();
+
+        let mut phase = Phase {
+            outstanding: master.do_map().unwrap().into_iter().map(|job| (job.task(), TaskState::new(job))).collect(),
+            durations: vec![],
+            assigned: HashMap::new(),
+            n_workers: 1
+        };
+        // A backup attempt, which the single worker runs after the first one.
+        master.launch(phase.outstanding.get_mut(&Task::Map(1)).unwrap()).unwrap();
+
+        let mut finished = 0;
+        while finished < 2 {
+            match master.results_queue.recv().unwrap() {
+                JobResult::MapFinished(job_id, 2, _) => {
+                    // The backup loses, and fails once the winner has committed.
+                    finished += 1;
+                    let failure = JobResult::FunctionFailed(Task::Map(job_id), 2, "too late".to_string());
+                    assert!(master.record_result(&mut phase, failure).is_ok());
+                }
+                result => {
+                    if let JobResult::MapFinished(..) = result {
+                        finished += 1;
+                    }
+                    master.record_result(&mut phase, result).unwrap();
+                }
+            }
+        }
+
+        assert!(phase.outstanding.is_empty());
+        assert!(master.registry.lock().unwrap().map_outputs.contains_key(&1));
+        remove_dir_all(&working_directory).unwrap();
+    }
+
+    fn temp_files(working_directory: &Path) -> Vec<String> {
+        read_dir(working_directory).unwrap()
+                                   .filter_map(|entry| entry.unwrap().file_name().into_string().ok())
+                                   .filter(|name| name.ends_with(".tmp"))
+                                   .collect()
+    }
+
+    #[test]
+    fn killed_attempts_leave_no_output_behind() {
+        let working_directory = PathBuf::from("./test-data/master_discards_killed_attempts");
+        create_dir_all(&working_directory).unwrap();
+        let input = working_directory.join("input_1");
+        let master = Master::with_functions(working_directory.clone(),
+                                            vec![input.clone()],
+                                            Arc::new(WordCount),
+                                            Arc::new(WordCount),
+                                            2);
+
+        // The first attempt dies halfway through writing its partitions.
+        write(map_attempt_name(&working_directory, 1, 1, 0), "partial").unwrap();
+        master.worker_results_queue.send(JobResult::Started(0, Task::Map(1), 1));
+        master.worker_results_queue.send(JobResult::Failed(Task::Map(1), 1, MapReduceError::Panicked {
+            task: Task::Map(1),
+            attempt: 1,
+            message: "killed".to_string()
+        }));
+        let mut outputs = vec![];
+        for partition in 0..2 {
+            outputs.push(map_attempt_name(&working_directory, 1, 2, partition));
+            write(&outputs[partition], "complete").unwrap();
+        }
+        master.worker_results_queue.send(JobResult::MapFinished(1, 2, outputs));
+
+        master.wait_for_completion(vec![Job::Map((1, 1, InputSplit::whole(input.clone()), None))], 1).unwrap();
+
+        assert_eq!(master.worker_job_queue.recv(), Some(Job::Map((1, 2, InputSplit::whole(input), None))));
+        assert!(temp_files(&working_directory).is_empty());
+        for partition in 0..2 {
+            let output = map_output_name(&working_directory, 1, partition);
+            assert_eq!(read_lines(&output), vec!["complete".to_string()]);
+        }
+        remove_dir_all(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn duplicate_completions_commit_only_the_first() {
+        let working_directory = PathBuf::from("./test-data/master_ignores_duplicate_completions");
+        create_dir_all(&working_directory).unwrap();
+        let input = working_directory.join("input_1");
+        let master = Master::with_functions(working_directory.clone(),
+                                            vec![input.clone()],
+                                            Arc::new(WordCount),
+                                            Arc::new(WordCount),
+                                            2);
+
+        // Both attempts of map task 1 finish; map task 2 keeps the phase open
+        // until the late duplicate has been seen.
+        for &(job_id, attempt) in [(1, 1), (1, 2), (2, 1)].iter() {
+            for partition in 0..2 {
+                write(map_attempt_name(&working_directory, job_id, attempt, partition),
+                      format!("attempt {}", attempt)).unwrap();
+            }
+        }
+        let finished = |job_id, attempt| {
+            let outputs = (0..2).map(|partition| map_attempt_name(&working_directory, job_id, attempt, partition)).collect();
+            JobResult::MapFinished(job_id, attempt, outputs)
+        };
+        master.worker_results_queue.send(finished(1, 2));
+        master.worker_results_queue.send(finished(1, 1));
+        master.worker_results_queue.send(finished(2, 1));
+
+        let jobs = vec![Job::Map((1, 1, InputSplit::whole(input.clone()), None)),
+                        Job::Map((2, 1, InputSplit::whole(input), None))];
+        master.wait_for_completion(jobs, 0).unwrap();
+
+        assert!(temp_files(&working_directory).is_empty());
+        for partition in 0..2 {
+            let output = map_output_name(&working_directory, 1, partition);
+            assert_eq!(read_lines(&output), vec!["attempt 2".to_string()]);
+        }
+        remove_dir_all(&working_directory).unwrap();
+    }
 }
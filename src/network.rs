@@ -0,0 +1,1240 @@
+use std::collections::HashMap;
+use std::fs::{ File, remove_file };
+use std::io;
+use std::io::{ BufReader, Read, Write };
+use std::iter;
+use std::net::{ SocketAddr, TcpListener, TcpStream, ToSocketAddrs };
+use std::path::{ Path, PathBuf };
+use std::sync::{ Arc, Mutex, Weak };
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::thread;
+use chan;
+use chan::{ Sender, Receiver };
+
+use error::MapReduceError;
+use executor::{ Executor, RunWorker, HEARTBEAT_INTERVAL };
+use protocol::{ Message, send, receive };
+use registry::JobSpec;
+use task::Task;
+use worker::{ Job, JobResult, quarantine_attempt_name };
+
+/// A file kept by a worker, fetched from its data server by name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Source {
+    address: String,
+    name: String
+}
+
+/// Sent by a worker to the master over its connection.
+#[derive(Debug, PartialEq, Eq)]
+enum Request {
+    /// The first message on a connection, giving the address of the worker's
+    /// data server.
+    Register { data_address: String },
+    /// Asks for the next job.
+    Pull,
+    Heartbeat,
+    /// A result of the running job. A finished map job lists its output files,
+    /// which stay on the worker, in `intermediate`; a finished job lists the
+    /// files the master collects if it commits the attempt, a reduce job's
+    /// result or a map job's quarantine file, in `outputs`.
+    Report { result: JobResult, intermediate: Vec<String>, outputs: Vec<String> }
+}
+
+/// Sent by the master to a worker.
+#[derive(Debug, PartialEq, Eq)]
+enum Reply {
+    /// Accepts a worker, giving it its id and, if the master was built from
+    /// a `Registry`, the spec to build its functions from.
+    Registered { worker: usize, spec: Option<Box<JobSpec>> },
+    /// A job, and for a reduce job the worker holding each of its input
+    /// files, or `None` if the file is in the master's working directory.
+    Assign { job: Job, sources: Vec<Option<Source>> },
+    /// There are no jobs left; the worker should exit.
+    Shutdown
+}
+
+/// Sent to a worker's data server, on a connection of its own.
+#[derive(Debug, PartialEq, Eq)]
+enum DataRequest {
+    /// Asks for a file, which is sent back as a `u64` length and the file's
+    /// bytes, or an error message.
+    Fetch(String),
+    Remove(String)
+}
+
+impl Message for Source {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        self.address.write(buffer);
+        self.name.write(buffer);
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        Ok(Source { address: String::read(bytes)?, name: String::read(bytes)? })
+    }
+}
+
+impl Message for Request {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        match *self {
+            Request::Register { ref data_address } => {
+                buffer.push(0);
+                data_address.write(buffer);
+            }
+            Request::Pull => buffer.push(1),
+            Request::Heartbeat => buffer.push(2),
+            Request::Report { ref result, ref intermediate, ref outputs } => {
+                buffer.push(3);
+                result.write(buffer);
+                intermediate.write(buffer);
+                outputs.write(buffer);
+            }
+        }
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        Ok(match u8::read(bytes)? {
+            0 => Request::Register { data_address: String::read(bytes)? },
+            1 => Request::Pull,
+            2 => Request::Heartbeat,
+            3 => Request::Report { result: JobResult::read(bytes)?, intermediate: Vec::read(bytes)?, outputs: Vec::read(bytes)? },
+            tag => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown request tag {}", tag)))
+        })
+    }
+}
+
+impl Message for Reply {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        match *self {
+            Reply::Registered { worker, ref spec } => {
+                buffer.push(0);
+                worker.write(buffer);
+                spec.write(buffer);
+            }
+            Reply::Assign { ref job, ref sources } => {
+                buffer.push(1);
+                job.write(buffer);
+                sources.write(buffer);
+            }
+            Reply::Shutdown => buffer.push(2)
+        }
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        Ok(match u8::read(bytes)? {
+            0 => Reply::Registered { worker: usize::read(bytes)?, spec: Option::read(bytes)? },
+            1 => Reply::Assign { job: Job::read(bytes)?, sources: Vec::read(bytes)? },
+            2 => Reply::Shutdown,
+            tag => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown reply tag {}", tag)))
+        })
+    }
+}
+
+impl Message for DataRequest {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        match *self {
+            DataRequest::Fetch(ref name) => {
+                buffer.push(0);
+                name.write(buffer);
+            }
+            DataRequest::Remove(ref name) => {
+                buffer.push(1);
+                name.write(buffer);
+            }
+        }
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        Ok(match u8::read(bytes)? {
+            0 => DataRequest::Fetch(String::read(bytes)?),
+            1 => DataRequest::Remove(String::read(bytes)?),
+            tag => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown data request tag {}", tag)))
+        })
+    }
+}
+
+impl Message for Result<u64, String> {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        match *self {
+            Ok(length) => {
+                buffer.push(0);
+                length.write(buffer);
+            }
+            Err(ref message) => {
+                buffer.push(1);
+                message.write(buffer);
+            }
+        }
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        Ok(match u8::read(bytes)? {
+            0 => Ok(u64::read(bytes)?),
+            1 => Err(String::read(bytes)?),
+            tag => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown fetch reply tag {}", tag)))
+        })
+    }
+}
+
+fn closed() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed")
+}
+
+fn invalid_name(name: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("invalid file name {:?}", name))
+}
+
+/// Whether `name` is one of the files a worker keeps for others to fetch: a
+/// map attempt's output for a partition, its quarantine file or a reduce
+/// attempt's result. Data servers serve and remove nothing else.
+fn is_served(name: &str) -> bool {
+    let number = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    match name.split('.').collect::<Vec<&str>>()[..] {
+        ["map", id, "reduce", partition, "attempt", attempt, "tmp"] => number(id) && number(partition) && number(attempt),
+        ["map", id, "quarantine", "attempt", attempt, "tmp"] => number(id) && number(attempt),
+        ["reduce", id, "attempt", attempt, "tmp"] => number(id) && number(attempt),
+        _ => false
+    }
+}
+
+/// Copies a file from the data server at `source.address` to `path`. A file
+/// the server does not have is `NotFound`; any other error means the server
+/// could not be reached or the transfer failed.
+fn fetch(source: &Source, path: &Path) -> io::Result<()> {
+    if !is_served(&source.name) {
+        return Err(invalid_name(&source.name));
+    }
+    let mut stream = TcpStream::connect(&source.address)?;
+    send(&mut stream, &DataRequest::Fetch(source.name.clone()))?;
+    let mut reader = BufReader::new(stream);
+    let length = match receive::<Result<u64, String>, _>(&mut reader)?.ok_or_else(closed)? {
+        Ok(length) => length,
+        Err(message) => return Err(io::Error::new(io::ErrorKind::NotFound, message))
+    };
+    let mut file = File::create(path)?;
+    if io::copy(&mut reader.take(length), &mut file)? != length {
+        let _ = remove_file(path);
+        return Err(closed());
+    }
+    file.flush()
+}
+
+/// Asks the data server at `source.address` to remove a file. Failures are
+/// ignored: the worker may be gone, and the file with it.
+fn remove_remote(source: Source) {
+    if !is_served(&source.name) {
+        return;
+    }
+    thread::spawn(move || {
+        if let Ok(mut stream) = TcpStream::connect(&source.address) {
+            let _ = send(&mut stream, &DataRequest::Remove(source.name));
+        }
+    });
+}
+
+/// Runs workers that connect to the master over TCP, from this or other
+/// processes or machines. Each worker registers, then pulls one job at a time
+/// and reports its results, sending heartbeats as it goes. Map output stays on
+/// the worker that wrote it, which serves it to the reduce tasks that need it;
+/// the reduce results and quarantine files the master commits are collected
+/// into its working directory. An attempt whose worker disconnects fails with
+/// `MapReduceError::WorkerLost`.
+///
+/// Workers are started with `Master::serve`, and read their input splits from
+/// the paths the master was given. Every worker that connects is given jobs,
+/// whatever the number passed to `Master::run`, until the executor is
+/// dropped.
+///
+/// Connections are not authenticated or encrypted: anyone who can reach the
+/// master or a worker can take jobs, report results and fetch map output, so
+/// run it on trusted networks only.
+pub struct TcpExecutor {
+    listener: TcpListener,
+    /// Where each committed or finished map output is kept, by its name in the
+    /// master's working directory.
+    map_outputs: Locations,
+    /// Where each reduce and quarantine file workers reported and the master
+    /// has not collected or discarded yet is kept.
+    outputs: Locations,
+    /// What workers that connect are given, set by each `start`. The first
+    /// `start` spawns the thread accepting connections, which runs until the
+    /// executor is dropped.
+    serving: Arc<Mutex<Option<Serving>>>
+}
+
+/// Where files kept by workers are, by their names in the master's working
+/// directory.
+type Locations = Arc<Mutex<HashMap<PathBuf, Source>>>;
+
+/// The job a `TcpExecutor` is running.
+#[derive(Clone)]
+struct Serving {
+    working_directory: PathBuf,
+    spec: Option<Box<JobSpec>>,
+    jobs: Receiver<Job>,
+    results: Sender<JobResult>
+}
+
+impl TcpExecutor {
+    /// Listens for workers on `address`.
+    pub fn bind<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        Ok(TcpExecutor {
+            listener: TcpListener::bind(address)?,
+            map_outputs: Arc::new(Mutex::new(HashMap::new())),
+            outputs: Arc::new(Mutex::new(HashMap::new())),
+            serving: Arc::new(Mutex::new(None))
+        })
+    }
+
+    /// The address workers connect to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts workers until the executor is dropped, connecting each to the
+    /// job being served when it connects.
+    fn accept(listener: TcpListener, map_outputs: Locations, outputs: Locations, serving: Weak<Mutex<Option<Serving>>>) {
+        let mut next_id = 0;
+        for stream in listener.incoming() {
+            let serving = match serving.upgrade() {
+                Some(serving) => serving.lock().unwrap().clone(),
+                None => break
+            };
+            let (stream, serving) = match (stream, serving) {
+                (Ok(stream), Some(serving)) => (stream, serving),
+                _ => continue
+            };
+            let connection = Connection {
+                worker: next_id,
+                working_directory: serving.working_directory,
+                map_outputs: map_outputs.clone(),
+                outputs: outputs.clone(),
+                spec: serving.spec,
+                running: Arc::new(Mutex::new(None))
+            };
+            next_id += 1;
+            let (jobs, results) = (serving.jobs, serving.results);
+            thread::spawn(move || connection.serve(stream, jobs, results));
+        }
+    }
+}
+
+impl Drop for TcpExecutor {
+    fn drop(&mut self) {
+        // Wake the accepting thread so that it sees the executor is gone.
+        if let Ok(address) = self.listener.local_addr() {
+            let _ = TcpStream::connect(address);
+        }
+    }
+}
+
+impl Executor for TcpExecutor {
+    fn start(&self,
+             _n_workers: usize,
+             working_directory: &Path,
+             _run_worker: RunWorker,
+             spec: Option<&JobSpec>,
+             jobs: Receiver<Job>,
+             results: Sender<JobResult>
+             ) -> Result<(), MapReduceError>
+    {
+        let mut serving = self.serving.lock().unwrap();
+        if serving.is_none() {
+            let listener = self.listener.try_clone().map_err(|e| MapReduceError::WorkerFailed { worker: 0, reason: e.to_string() })?;
+            let (map_outputs, outputs) = (self.map_outputs.clone(), self.outputs.clone());
+            let weak = Arc::downgrade(&self.serving);
+            thread::spawn(move || TcpExecutor::accept(listener, map_outputs, outputs, weak));
+        }
+        *serving = Some(Serving {
+            working_directory: working_directory.to_path_buf(),
+            spec: spec.cloned().map(Box::new),
+            jobs,
+            results
+        });
+        Ok(())
+    }
+
+    fn commit_map_output(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut map_outputs = self.map_outputs.lock().unwrap();
+        match map_outputs.remove(from) {
+            Some(source) => {
+                if let Some(replaced) = map_outputs.insert(to.to_path_buf(), source) {
+                    remove_remote(replaced);
+                }
+                Ok(())
+            }
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "no worker holds this map output"))
+        }
+    }
+
+    fn discard_map_output(&self, path: &Path) {
+        if let Some(source) = self.map_outputs.lock().unwrap().remove(path) {
+            remove_remote(source);
+        }
+    }
+
+    fn collect_output(&self, path: &Path) -> io::Result<()> {
+        let source = self.outputs.lock().unwrap().remove(path);
+        let source = source.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no worker holds this output"))?;
+        fetch(&source, path)?;
+        remove_remote(source);
+        Ok(())
+    }
+
+    fn discard_output(&self, path: &Path) {
+        if let Some(source) = self.outputs.lock().unwrap().remove(path) {
+            remove_remote(source);
+        }
+    }
+
+    fn map_output_on_workers(&self) -> bool {
+        true
+    }
+}
+
+/// The master's end of a worker's connection. `running` is the job the
+/// worker was last given, until it reports the attempt's end.
+#[derive(Clone)]
+struct Connection {
+    worker: usize,
+    working_directory: PathBuf,
+    map_outputs: Locations,
+    outputs: Locations,
+    spec: Option<Box<JobSpec>>,
+    running: Arc<Mutex<Option<Job>>>
+}
+
+impl Connection {
+    /// Registers the worker, then hands it a job each time it asks for one
+    /// while a thread reads everything else it sends.
+    fn serve(self, stream: TcpStream, jobs: Receiver<Job>, results: Sender<JobResult>) {
+        let _ = stream.set_nodelay(true);
+        let mut writer = match stream.try_clone() {
+            Ok(writer) => writer,
+            Err(_) => return
+        };
+        let mut reader = BufReader::new(stream);
+        let data_address = match receive(&mut reader) {
+            Ok(Some(Request::Register { data_address })) => data_address,
+            _ => return
+        };
+        if send(&mut writer, &Reply::Registered { worker: self.worker, spec: self.spec.clone() }).is_err() {
+            return;
+        }
+
+        let (pull_send, pulls) = chan::async();
+        let (lost_send, lost) = chan::async();
+        let connection = self.clone();
+        let worker_results = results.clone();
+        thread::spawn(move || {
+            let error = connection.read(reader, &data_address, pull_send, &worker_results);
+            lost_send.send(error);
+        });
+
+        loop {
+            let pulled;
+            chan_select! {
+                pulls.recv() -> pull => pulled = pull.is_some(),
+                lost.recv() -> e => return self.lose(e.unwrap_or_else(closed), &results)
+            }
+            // The reader stops signalling pulls once the connection is lost.
+            if !pulled {
+                return self.lose(lost.recv().unwrap_or_else(closed), &results);
+            }
+            let next;
+            chan_select! {
+                jobs.recv() -> job => next = job,
+                lost.recv() -> e => return self.lose(e.unwrap_or_else(closed), &results)
+            }
+            let job = match next {
+                Some(job) => job,
+                None => {
+                    let _ = send(&mut writer, &Reply::Shutdown);
+                    return;
+                }
+            };
+            let sources = self.sources(&job);
+            *self.running.lock().unwrap() = Some(job.clone());
+            if let Err(e) = send(&mut writer, &Reply::Assign { job, sources }) {
+                return self.lose(e, &results);
+            }
+        }
+    }
+
+    /// Reads what the worker sends until the connection fails, passing on
+    /// its results and heartbeats and signalling `pulls` when it asks for a
+    /// job.
+    fn read(&self, mut reader: BufReader<TcpStream>, data_address: &str, pulls: Sender<()>, results: &Sender<JobResult>) -> io::Error {
+        loop {
+            let request = match receive(&mut reader) {
+                Ok(Some(request)) => request,
+                Ok(None) => return closed(),
+                Err(e) => return e
+            };
+            match request {
+                Request::Register { .. } => return io::Error::new(io::ErrorKind::InvalidData, "worker registered twice"),
+                Request::Pull => pulls.send(()),
+                Request::Heartbeat => results.send(JobResult::Heartbeat(self.worker)),
+                Request::Report { result, intermediate, outputs } => {
+                    let result = self.locate(result, data_address, intermediate, outputs);
+                    if !matches!(result, JobResult::Started(..)) {
+                        self.running.lock().unwrap().take();
+                    }
+                    results.send(result);
+                }
+            }
+        }
+    }
+
+    /// Fails the attempt the worker was running when its connection was lost.
+    fn lose(&self, error: io::Error, results: &Sender<JobResult>) {
+        if let Some(job) = self.running.lock().unwrap().take() {
+            let (task, attempt) = (job.task(), job.attempt());
+            results.send(JobResult::Failed(task, attempt, MapReduceError::WorkerLost {
+                task,
+                attempt,
+                worker: self.worker,
+                reason: error.to_string()
+            }));
+        }
+    }
+
+    /// Where a reduce job's inputs are kept.
+    fn sources(&self, job: &Job) -> Vec<Option<Source>> {
+        match *job {
+            Job::Reduce((_, _, ref paths)) => {
+                let map_outputs = self.map_outputs.lock().unwrap();
+                paths.iter().map(|path| map_outputs.get(path).cloned()).collect()
+            }
+            Job::Map(_) => vec![]
+        }
+    }
+
+    /// Records where the files a finished job reports are kept, for the
+    /// master to fetch those it commits. A job that reports files other than
+    /// its own fails; a finished job's result is returned with the files it
+    /// reports in the master's working directory.
+    fn locate(&self, result: JobResult, data_address: &str, intermediate: Vec<String>, outputs: Vec<String>) -> JobResult {
+        let source = |name: String| Source { address: data_address.to_string(), name };
+        let failure = |path: PathBuf, reason: String| {
+            let (task, attempt) = match result {
+                JobResult::MapFinished(job_id, attempt, _) => (Task::Map(job_id), attempt),
+                JobResult::ReduceFinished(job_id, attempt, _) => (Task::Reduce(job_id), attempt),
+                _ => return None
+            };
+            Some(JobResult::Failed(task, attempt, MapReduceError::WriteOutput { task, attempt, path, reason }))
+        };
+        if let Some(name) = intermediate.iter().chain(outputs.iter()).find(|name| !is_served(name)) {
+            // The name is not joined to the working directory, as it may point
+            // outside it.
+            return failure(PathBuf::from(name), invalid_name(name).to_string()).unwrap_or(result);
+        }
+        for (names, locations) in [(intermediate, &self.map_outputs), (outputs, &self.outputs)] {
+            let mut locations = locations.lock().unwrap();
+            for name in names {
+                locations.insert(self.working_directory.join(&name), source(name));
+            }
+        }
+        // The master knows the files a worker reports by the same names in
+        // its own working directory.
+        let here = |path: PathBuf| path.file_name().map(|name| self.working_directory.join(name)).unwrap_or(path);
+        match result {
+            JobResult::MapFinished(job_id, attempt, partitions) => {
+                JobResult::MapFinished(job_id, attempt, partitions.into_iter().map(here).collect())
+            }
+            JobResult::ReduceFinished(job_id, attempt, output) => JobResult::ReduceFinished(job_id, attempt, here(output)),
+            result => result
+        }
+    }
+}
+
+/// Serves the files in a worker's working directory to the master and to
+/// other workers, until dropped.
+struct DataServer {
+    address: SocketAddr,
+    stopped: Arc<AtomicBool>
+}
+
+impl DataServer {
+    /// Listens on an unused port of the address `local` is on.
+    fn start(local: SocketAddr, working_directory: &Path) -> io::Result<Self> {
+        let listener = TcpListener::bind((local.ip(), 0))?;
+        let address = listener.local_addr()?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let working_directory = working_directory.to_path_buf();
+        let stop = stopped.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    let working_directory = working_directory.clone();
+                    thread::spawn(move || {
+                        let _ = serve_file(stream, &working_directory);
+                    });
+                }
+            }
+        });
+        Ok(DataServer { address, stopped })
+    }
+}
+
+impl Drop for DataServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // Wake the accepting thread so that it sees it has been stopped.
+        let _ = TcpStream::connect(self.address);
+    }
+}
+
+fn serve_file(mut stream: TcpStream, working_directory: &Path) -> io::Result<()> {
+    let request = match receive(&mut stream)? {
+        Some(request) => request,
+        None => return Ok(())
+    };
+    let (name, fetch) = match request {
+        DataRequest::Fetch(name) => (name, true),
+        DataRequest::Remove(name) => (name, false)
+    };
+    if !is_served(&name) {
+        return send(&mut stream, &Err::<u64, String>(invalid_name(&name).to_string()));
+    }
+    let path = working_directory.join(name);
+    if fetch {
+        let file = File::open(&path).and_then(|f| f.metadata().map(|m| (f, m.len())));
+        match file {
+            Ok((file, length)) => {
+                send(&mut stream, &Ok::<u64, String>(length))?;
+                io::copy(&mut file.take(length), &mut stream)?;
+                stream.flush()
+            }
+            Err(e) => send(&mut stream, &Err::<u64, String>(e.to_string()))
+        }
+    } else {
+        let _ = remove_file(&path);
+        Ok(())
+    }
+}
+
+/// Runs a worker of the master at `master_address`, keeping its map output in
+/// `working_directory`, until the master is done. The worker is built by
+/// `build`, from the spec the master sent if it sent one; an error building it
+/// is returned as is, and any other failure as `ConnectionFailed`.
+pub fn work_for<A, F>(master_address: A, working_directory: &Path, build: F) -> Result<(), MapReduceError>
+    where A: ToSocketAddrs,
+          F: FnOnce(Option<JobSpec>) -> Result<RunWorker, MapReduceError>
+{
+    let connection_failed = |e: io::Error| MapReduceError::ConnectionFailed { reason: e.to_string() };
+    let Registration { worker, spec, data_server, writer, mut reader } = register(master_address, working_directory).map_err(connection_failed)?;
+    let run_worker = build(spec)?;
+    let data_address = data_server.address.to_string();
+
+    let heartbeats = writer.clone();
+    let done = Arc::new(AtomicBool::new(false));
+    let stop_heartbeats = done.clone();
+    thread::spawn(move || {
+        while !stop_heartbeats.load(Ordering::SeqCst) {
+            if send(&mut *heartbeats.lock().unwrap(), &Request::Heartbeat).is_err() {
+                break;
+            }
+            thread::sleep(HEARTBEAT_INTERVAL);
+        }
+    });
+
+    let (job_send, job_recv) = chan::async();
+    let (result_send, result_recv) = chan::async();
+    thread::spawn(move || run_worker(worker, job_recv, result_send));
+
+    let local = LocalWorker { working_directory, data_address: &data_address, jobs: job_send, results: result_recv };
+    let served = pull_jobs(&local, &writer, &mut reader);
+    done.store(true, Ordering::SeqCst);
+    served.map_err(connection_failed)
+}
+
+/// A worker's connection to its master, the id and spec the master gave it,
+/// and the data server it registered.
+struct Registration {
+    worker: usize,
+    spec: Option<JobSpec>,
+    data_server: DataServer,
+    writer: Arc<Mutex<TcpStream>>,
+    reader: BufReader<TcpStream>
+}
+
+/// Connects to the master and registers with it.
+fn register<A: ToSocketAddrs>(master_address: A, working_directory: &Path) -> io::Result<Registration> {
+    let stream = TcpStream::connect(master_address)?;
+    stream.set_nodelay(true)?;
+    let data_server = DataServer::start(stream.local_addr()?, working_directory)?;
+    let writer = Arc::new(Mutex::new(stream.try_clone()?));
+    let mut reader = BufReader::new(stream);
+
+    send(&mut *writer.lock().unwrap(), &Request::Register { data_address: data_server.address.to_string() })?;
+    match receive(&mut reader)? {
+        Some(Reply::Registered { worker, spec }) => {
+            Ok(Registration { worker, spec: spec.map(|spec| *spec), data_server, writer, reader })
+        }
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "master did not accept the registration"))
+    }
+}
+
+/// Asks the master for jobs and runs them until it has none left.
+fn pull_jobs(local: &LocalWorker, writer: &Mutex<TcpStream>, reader: &mut BufReader<TcpStream>) -> io::Result<()> {
+    loop {
+        send(&mut *writer.lock().unwrap(), &Request::Pull)?;
+        match receive(reader)?.ok_or_else(closed)? {
+            Reply::Assign { job, sources } => local.run(job, sources, |request| send(&mut *writer.lock().unwrap(), &request))?,
+            Reply::Shutdown => return Ok(()),
+            Reply::Registered { .. } => return Err(io::Error::new(io::ErrorKind::InvalidData, "worker registered twice"))
+        }
+    }
+}
+
+/// The worker a networked worker process runs its jobs on.
+struct LocalWorker<'a> {
+    working_directory: &'a Path,
+    data_address: &'a str,
+    jobs: Sender<Job>,
+    results: Receiver<JobResult>
+}
+
+impl<'a> LocalWorker<'a> {
+    /// Runs a job, fetching a reduce job's inputs from the workers that hold
+    /// them first, and reports its results.
+    fn run<F>(&self, job: Job, sources: Vec<Option<Source>>, mut report: F) -> io::Result<()>
+        where F: FnMut(Request) -> io::Result<()>
+    {
+        let task = job.task();
+        let mut fetched = vec![];
+        let mut originals = HashMap::new();
+        let job = match job {
+            Job::Reduce((job_id, attempt, paths)) => {
+                let mut local_paths = vec![];
+                for (index, (path, source)) in paths.into_iter().zip(sources.into_iter().chain(iter::repeat(None))).enumerate() {
+                    let local_path = match source {
+                        Some(ref source) if !is_served(&source.name) => {
+                            remove_all(&fetched);
+                            return Err(invalid_name(&source.name));
+                        }
+                        Some(ref source) if source.address == self.data_address => self.working_directory.join(&source.name),
+                        Some(source) => {
+                            let local_path = self.working_directory
+                                                 .join(format!("reduce.{}.attempt.{}.input.{}.tmp", job_id, attempt, index + 1));
+                            fetched.push(local_path.clone());
+                            if let Err(e) = fetch(&source, &local_path) {
+                                remove_all(&fetched);
+                                let error = MapReduceError::ReadInput {
+                                    task,
+                                    attempt,
+                                    path: path.clone(),
+                                    reason: format!("could not fetch it from {}: {}", source.address, e)
+                                };
+                                // Map output that cannot be fetched, whether its
+                                // worker no longer has it or cannot be reached, is
+                                // lost, and its map task is re-run.
+                                let result = JobResult::CorruptInput(task, attempt, path, error);
+                                return report(Request::Report { result, intermediate: vec![], outputs: vec![] });
+                            }
+                            local_path
+                        }
+                        None => path.clone()
+                    };
+                    originals.insert(local_path.clone(), path);
+                    local_paths.push(local_path);
+                }
+                Job::Reduce((job_id, attempt, local_paths))
+            }
+            job => job
+        };
+
+        self.jobs.send(job);
+        let reported = loop {
+            let result = match self.results.recv() {
+                Some(result) => result,
+                None => break Err(io::Error::other("worker stopped"))
+            };
+            let finished = !matches!(result, JobResult::Started(..));
+            let (result, intermediate, outputs) = self.files(result, &originals);
+            if let Err(e) = report(Request::Report { result, intermediate, outputs }) {
+                break Err(e);
+            }
+            if finished {
+                break Ok(());
+            }
+        };
+        remove_all(&fetched);
+        reported
+    }
+
+    /// The files to report with a result, and the result with any fetched
+    /// input's path replaced by the path the master knows it by.
+    fn files(&self, result: JobResult, originals: &HashMap<PathBuf, PathBuf>) -> (JobResult, Vec<String>, Vec<String>) {
+        let name = |path: PathBuf| path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        match result {
+            JobResult::MapFinished(job_id, attempt, ref partitions) => {
+                let intermediate = partitions.iter().cloned().map(name).collect();
+                let quarantine = quarantine_attempt_name(self.working_directory, job_id, attempt);
+                let outputs = if quarantine.exists() { vec![name(quarantine)] } else { vec![] };
+                (result, intermediate, outputs)
+            }
+            JobResult::ReduceFinished(_, _, ref output) => {
+                let outputs = vec![name(output.clone())];
+                (result, vec![], outputs)
+            }
+            JobResult::CorruptInput(task, attempt, path, error) => {
+                let original = originals.get(&path).cloned().unwrap_or(path);
+                let error = match error {
+                    MapReduceError::CorruptIntermediate { task, attempt, reason, .. } => {
+                        MapReduceError::CorruptIntermediate { task, attempt, path: original.clone(), reason }
+                    }
+                    MapReduceError::ReadInput { task, attempt, reason, .. } => {
+                        MapReduceError::ReadInput { task, attempt, path: original.clone(), reason }
+                    }
+                    error => error
+                };
+                (JobResult::CorruptInput(task, attempt, original, error), vec![], vec![])
+            }
+            result => (result, vec![], vec![])
+        }
+    }
+}
+
+fn remove_all(paths: &[PathBuf]) {
+    for path in paths {
+        let _ = remove_file(path);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::Infallible;
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs::{ create_dir_all, read_dir, read_to_string, remove_dir_all, write };
+    use std::io;
+    use std::io::Read;
+    use std::net::{ TcpListener, TcpStream };
+    use std::path::{ Path, PathBuf };
+    use std::process;
+    use std::process::Stdio;
+    use std::sync::{ Arc, Mutex };
+    use std::thread;
+    use std::time::{ Duration, Instant };
+    use chan;
+
+    use executor::{ Executor, ThreadExecutor };
+    use functions::{ Mapper, Reducer, Emitter };
+    use input::Record;
+    use master::{ Master, RegisteredMaster };
+    use error::MapReduceError;
+    use protocol::{ send, receive };
+    use registry::{ Registry, Functions, Named };
+    use split::InputSplit;
+    use task::Task;
+    use worker::{ Job, JobResult };
+    use super::{ Connection, DataRequest, DataServer, LocalWorker, Reply, Request, Source, TcpExecutor, fetch };
+
+    struct WordCount;
+
+    impl Mapper for WordCount {
+        type Key = String;
+        type Value = u64;
+        type Error = io::Error;
+
+        fn map(&self, records: &mut dyn Iterator<Item = Record>, output: &mut Emitter<String, u64>) -> io::Result<()> {
+            for record in records {
+                for word in String::from_utf8_lossy(&record.bytes).split_whitespace() {
+                    output.emit(word.to_lowercase(), 1);
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl Reducer for WordCount {
+        type Key = String;
+        type Value = u64;
+        type Output = u64;
+        type Error = Infallible;
+
+        fn reduce(&self, _word: &String, counts: &mut dyn Iterator<Item = u64>) -> Result<u64, Infallible> {
+            Ok(counts.sum())
+        }
+    }
+
+    /// Marks that it was reached by creating `marker`, then never returns.
+    struct Stalls {
+        marker: PathBuf
+    }
+
+    impl Reducer for Stalls {
+        type Key = String;
+        type Value = u64;
+        type Output = u64;
+        type Error = Infallible;
+
+        fn reduce(&self, _word: &String, _counts: &mut dyn Iterator<Item = u64>) -> Result<u64, Infallible> {
+            let _ = write(&self.marker, "");
+            loop {
+                thread::sleep(Duration::from_secs(1));
+            }
+        }
+    }
+
+    /// Set in the environment of a child process running a worker that is
+    /// meant to be killed, to the address of its master.
+    const DOOMED_WORKER: &str = "MAPREDUCE_TEST_DOOMED_WORKER";
+
+    fn word_count(working_directory: &Path) -> Master<WordCount, WordCount> {
+        let input_directory = PathBuf::from("./test-data/master_runs_word_count");
+        create_dir_all(working_directory).unwrap();
+        Master::with_functions(working_directory.to_path_buf(),
+                               vec![input_directory.join("input_1"), input_directory.join("input_2")],
+                               Arc::new(WordCount),
+                               Arc::new(WordCount),
+                               2)
+            .with_split_size(10)
+    }
+
+    fn file_names(directory: &Path) -> Vec<String> {
+        read_dir(directory).unwrap()
+                           .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                           .collect()
+    }
+
+    #[test]
+    fn tcp_workers_fetch_map_output_from_each_other() {
+        let test_directory = PathBuf::from("./test-data/tcp_workers_fetch_map_output_from_each_other");
+        let _ = remove_dir_all(&test_directory);
+
+        let expected = word_count(&test_directory.join("threads")).with_executor(Arc::new(ThreadExecutor))
+                                                                  .run(2)
+                                                                  .unwrap()
+                                                                  .result_files
+                                                                  .iter()
+                                                                  .map(|path| read_to_string(path).unwrap())
+                                                                  .collect::<Vec<String>>();
+
+        let executor = TcpExecutor::bind("127.0.0.1:0").unwrap();
+        let address = executor.local_addr().unwrap();
+        let master_directory = test_directory.join("master");
+        let master = word_count(&master_directory).with_executor(Arc::new(executor));
+        let worker_directories = (0..3).map(|i| test_directory.join(format!("worker_{}", i)))
+                                       .collect::<Vec<PathBuf>>();
+        let workers = worker_directories.iter()
+                                        .map(|directory| {
+                                            let worker = word_count(directory);
+                                            thread::spawn(move || worker.serve(address))
+                                        })
+                                        .collect::<Vec<_>>();
+
+        let results = master.run(3)
+                            .unwrap()
+                            .result_files
+                            .iter()
+                            .map(|path| read_to_string(path).unwrap())
+                            .collect::<Vec<String>>();
+        assert_eq!(results, expected);
+        assert!(results.iter().any(|result| result.contains("the=4")));
+
+        // Map output stayed on the workers that wrote it.
+        assert!(!file_names(&master_directory).iter().any(|name| name.starts_with("map.")));
+        let kept = worker_directories.iter()
+                                     .map(|directory| {
+                                         file_names(directory).iter()
+                                                              .filter(|name| name.starts_with("map.") && !name.contains("quarantine"))
+                                                              .count()
+                                     })
+                                     .collect::<Vec<usize>>();
+        assert_eq!(kept.iter().sum::<usize>(), 10 * 2);
+        assert!(kept.iter().filter(|&&n| n > 0).count() > 1, "only one worker ran map tasks: {:?}", kept);
+
+        drop(master);
+        for worker in workers {
+            worker.join().unwrap().unwrap();
+        }
+        remove_dir_all(&test_directory).unwrap();
+    }
+
+    #[test]
+    fn jobs_finish_after_a_worker_holding_map_output_is_killed() {
+        let test_directory = PathBuf::from("./test-data/tcp_jobs_survive_killed_workers");
+        let doomed_directory = test_directory.join("doomed");
+        let stalled = doomed_directory.join("stalled");
+        if let Some(address) = env::var_os(DOOMED_WORKER) {
+            let input_directory = PathBuf::from("./test-data/master_runs_word_count");
+            let worker = Master::with_functions(doomed_directory.clone(),
+                                                vec![input_directory.join("input_1"), input_directory.join("input_2")],
+                                                Arc::new(WordCount),
+                                                Arc::new(Stalls { marker: stalled }),
+                                                2)
+                .with_split_size(10);
+            let _ = worker.serve(address.to_str().unwrap());
+            return;
+        }
+        let _ = remove_dir_all(&test_directory);
+        create_dir_all(&doomed_directory).unwrap();
+
+        let executor = TcpExecutor::bind("127.0.0.1:0").unwrap();
+        let address = executor.local_addr().unwrap();
+        // Long enough that the doomed worker is never declared dead.
+        let master = word_count(&test_directory.join("master")).with_executor(Arc::new(executor))
+                                                               .with_worker_timeout(Duration::from_secs(600));
+        let run = thread::spawn(move || master.run(2));
+
+        // The doomed worker runs every map task, then stalls in the reduce
+        // phase, and is killed there.
+        let mut doomed = process::Command::new(env::current_exe().unwrap())
+            .args(["network::test::jobs_finish_after_a_worker_holding_map_output_is_killed", "--exact", "--test-threads=1"])
+            .env(DOOMED_WORKER, address.to_string())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let deadline = Instant::now() + Duration::from_secs(60);
+        while !stalled.exists() {
+            if doomed.try_wait().unwrap().is_some() || Instant::now() > deadline {
+                let _ = doomed.kill();
+                panic!("the doomed worker did not reach the reduce phase");
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        doomed.kill().unwrap();
+        doomed.wait().unwrap();
+
+        let survivor = word_count(&test_directory.join("survivor"));
+        let survivor = thread::spawn(move || survivor.serve(address));
+        let results = run.join()
+                         .unwrap()
+                         .unwrap()
+                         .result_files
+                         .iter()
+                         .map(|path| read_to_string(path).unwrap())
+                         .collect::<Vec<String>>();
+        assert!(results.iter().any(|result| result.contains("the=4")), "{:?}", results);
+        assert!(results.iter().any(|result| result.contains("common=2")), "{:?}", results);
+
+        survivor.join().unwrap().unwrap();
+        remove_dir_all(&test_directory).unwrap();
+    }
+
+    #[test]
+    fn tcp_executors_accept_workers_on_one_thread_until_dropped() {
+        let input = PathBuf::from("./test-data/master_runs_word_count/input_1");
+        let executor = TcpExecutor::bind("127.0.0.1:0").unwrap();
+        let address = executor.local_addr().unwrap();
+        let (_, stale_jobs) = chan::async();
+        let (jobs_send, jobs) = chan::async();
+        let (results_send, _results) = chan::async();
+        for jobs in [stale_jobs, jobs] {
+            executor.start(1, Path::new("."), Arc::new(|_, _, _| {}), None, jobs, results_send.clone()).unwrap();
+        }
+
+        // Every worker is handed jobs from the last start.
+        for id in 0..4 {
+            jobs_send.send(Job::Map((id, 1, InputSplit::whole(input.clone()), None)));
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+            send(&mut stream, &Request::Register { data_address: "127.0.0.1:1".to_string() }).unwrap();
+            assert!(matches!(receive(&mut stream).unwrap(), Some(Reply::Registered { .. })));
+            send(&mut stream, &Request::Pull).unwrap();
+            match receive(&mut stream).unwrap() {
+                Some(Reply::Assign { job: Job::Map((job_id, ..)), .. }) => assert_eq!(job_id, id),
+                other => panic!("expected map job {}, got {:?}", id, other)
+            }
+        }
+
+        drop(executor);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while TcpStream::connect(address).is_ok() {
+            assert!(Instant::now() < deadline, "the executor still accepts workers");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn only_committed_outputs_are_collected() {
+        let test_directory = PathBuf::from("./test-data/network_collects_committed_outputs");
+        let _ = remove_dir_all(&test_directory);
+        let (master_directory, worker_directory) = (test_directory.join("master"), test_directory.join("worker"));
+        create_dir_all(&master_directory).unwrap();
+        create_dir_all(&worker_directory).unwrap();
+        let server = DataServer::start("127.0.0.1:0".parse().unwrap(), &worker_directory).unwrap();
+        let executor = TcpExecutor::bind("127.0.0.1:0").unwrap();
+        for attempt in ["reduce.1.attempt.1.tmp", "reduce.1.attempt.2.tmp"] {
+            write(worker_directory.join(attempt), "the=4\n").unwrap();
+            let source = Source { address: server.address.to_string(), name: attempt.to_string() };
+            executor.outputs.lock().unwrap().insert(master_directory.join(attempt), source);
+        }
+
+        executor.collect_output(&master_directory.join("reduce.1.attempt.2.tmp")).unwrap();
+        executor.discard_output(&master_directory.join("reduce.1.attempt.1.tmp"));
+
+        assert_eq!(file_names(&master_directory), vec!["reduce.1.attempt.2.tmp"]);
+        assert_eq!(read_to_string(master_directory.join("reduce.1.attempt.2.tmp")).unwrap(), "the=4\n");
+        assert!(executor.outputs.lock().unwrap().is_empty());
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !file_names(&worker_directory).is_empty() {
+            assert!(Instant::now() < deadline, "the worker kept {:?}", file_names(&worker_directory));
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(executor.collect_output(&master_directory.join("reduce.1.attempt.1.tmp")).unwrap_err().kind(),
+                   io::ErrorKind::NotFound);
+
+        drop(server);
+        remove_dir_all(&test_directory).unwrap();
+    }
+
+    #[test]
+    fn only_attempt_outputs_are_served() {
+        let working_directory = PathBuf::from("./test-data/network_serves_only_attempt_outputs");
+        let _ = remove_dir_all(&working_directory);
+        create_dir_all(&working_directory).unwrap();
+        write(working_directory.join("map.1.reduce.2.attempt.3.tmp"), "heat-ray").unwrap();
+        write(working_directory.join("secret"), "tripods").unwrap();
+        let server = DataServer::start("127.0.0.1:0".parse().unwrap(), &working_directory).unwrap();
+        let source = |name: &str| Source { address: server.address.to_string(), name: name.to_string() };
+
+        let fetched = working_directory.join("fetched");
+        fetch(&source("map.1.reduce.2.attempt.3.tmp"), &fetched).unwrap();
+        assert_eq!(read_to_string(&fetched).unwrap(), "heat-ray");
+        assert_eq!(fetch(&source("secret"), &fetched).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        // The server refuses other names from any client, and removes nothing.
+        for request in [DataRequest::Fetch("secret".to_string()), DataRequest::Fetch("../network_serves_only_attempt_outputs/secret".to_string())] {
+            let mut stream = TcpStream::connect(server.address).unwrap();
+            send(&mut stream, &request).unwrap();
+            let reply = receive::<Result<u64, String>, _>(&mut stream).unwrap().unwrap();
+            assert!(reply.unwrap_err().contains("invalid file name"));
+        }
+        let mut stream = TcpStream::connect(server.address).unwrap();
+        send(&mut stream, &DataRequest::Remove("secret".to_string())).unwrap();
+        let _ = stream.read(&mut [0]);
+        assert!(working_directory.join("secret").exists());
+
+        drop(server);
+        remove_dir_all(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn workers_cannot_report_files_outside_their_working_directory() {
+        let working_directory = PathBuf::from("./test-data/network_rejects_reported_names");
+        let connection = Connection {
+            worker: 1,
+            working_directory: working_directory.clone(),
+            map_outputs: Arc::new(Mutex::new(HashMap::new())),
+            outputs: Arc::new(Mutex::new(HashMap::new())),
+            spec: None,
+            running: Arc::new(Mutex::new(None))
+        };
+
+        for (intermediate, outputs) in [(vec!["../map.1.reduce.1"], vec![]), (vec![], vec!["/etc/passwd"])] {
+            let result = connection.locate(JobResult::MapFinished(1, 1, vec![]),
+                                            "127.0.0.1:1",
+                                            intermediate.into_iter().map(String::from).collect(),
+                                            outputs.into_iter().map(String::from).collect());
+            match result {
+                JobResult::Failed(Task::Map(1), 1, MapReduceError::WriteOutput { reason, .. }) => {
+                    assert!(reason.contains("invalid file name"), "{}", reason);
+                }
+                other => panic!("expected a failed attempt, got {:?}", other)
+            }
+        }
+        assert!(connection.map_outputs.lock().unwrap().is_empty());
+        assert!(connection.outputs.lock().unwrap().is_empty());
+        assert!(!working_directory.exists());
+    }
+
+    #[test]
+    fn map_output_that_cannot_be_fetched_is_lost() {
+        let working_directory = PathBuf::from("./test-data/network_reports_fetch_failures");
+        let _ = remove_dir_all(&working_directory);
+        create_dir_all(&working_directory).unwrap();
+        let server = DataServer::start("127.0.0.1:0".parse().unwrap(), &working_directory).unwrap();
+        let unreachable = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let (jobs, _) = chan::async();
+        let (_, results) = chan::async();
+        let local = LocalWorker { working_directory: &working_directory, data_address: "127.0.0.1:1", jobs, results };
+        let path = working_directory.join("map.1.reduce.1");
+        let name = "map.1.reduce.1.attempt.1.tmp".to_string();
+
+        // Whether its worker cannot be reached or no longer has it.
+        let mut reported = vec![];
+        for address in [unreachable, server.address] {
+            let source = Source { address: address.to_string(), name: name.clone() };
+            local.run(Job::Reduce((1, 1, vec![path.clone()])), vec![Some(source)], |request| {
+                reported.push(request);
+                Ok(())
+            }).unwrap();
+        }
+        assert_eq!(reported.len(), 2);
+        for request in reported {
+            match request {
+                Request::Report { result: JobResult::CorruptInput(Task::Reduce(1), 1, lost, MapReduceError::ReadInput { .. }), .. } => {
+                    assert_eq!(lost, path);
+                }
+                other => panic!("expected a lost map output, got {:?}", other)
+            }
+        }
+
+        drop(server);
+        remove_dir_all(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn workers_report_failed_connections() {
+        let working_directory = PathBuf::from("./test-data/network_reports_failed_connections");
+        let unreachable = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+        match word_count(&working_directory).serve(unreachable) {
+            Err(MapReduceError::ConnectionFailed { .. }) => {}
+            other => panic!("expected a failed connection, got {:?}", other)
+        }
+        remove_dir_all(&working_directory).unwrap();
+    }
+
+    #[test]
+    fn tcp_workers_look_up_registered_functions() {
+        let test_directory = PathBuf::from("./test-data/tcp_workers_look_up_registered_functions");
+        let _ = remove_dir_all(&test_directory);
+        let registry = Arc::new(Registry::new().mapper("word_count", |_: &[u8]| Ok(WordCount))
+                                               .reducer("word_count", |_: &[u8]| Ok(WordCount)));
+
+        let executor = TcpExecutor::bind("127.0.0.1:0").unwrap();
+        let address = executor.local_addr().unwrap();
+        let master_directory = test_directory.join("master");
+        create_dir_all(&master_directory).unwrap();
+        let input_directory = PathBuf::from("./test-data/master_runs_word_count");
+        let functions = Functions::new(Named::new("word_count"), Named::new("word_count"))
+            .with_output_format(Named::new("json_lines"));
+        let master = RegisteredMaster::<String, u64, u64>::from_registry(&registry,
+                                                                         master_directory,
+                                                                         vec![input_directory.join("input_1"), input_directory.join("input_2")],
+                                                                         functions,
+                                                                         2)
+            .unwrap()
+            .with_executor(Arc::new(executor));
+        let workers = (0..2).map(|i| {
+                                let directory = test_directory.join(format!("worker_{}", i));
+                                create_dir_all(&directory).unwrap();
+                                let registry = registry.clone();
+                                thread::spawn(move || registry.work_for(address, &directory))
+                            })
+                            .collect::<Vec<_>>();
+
+        let results = master.run(2)
+                            .unwrap()
+                            .result_files
+                            .iter()
+                            .map(|path| read_to_string(path).unwrap())
+                            .collect::<String>();
+        assert!(results.contains("{\"key\":\"the\",\"value\":4}\n"), "{}", results);
+
+        drop(master);
+        for worker in workers {
+            worker.join().unwrap().unwrap();
+        }
+        remove_dir_all(&test_directory).unwrap();
+    }
+}
@@ -0,0 +1,150 @@
+use std::fs::rename;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+use datum::Datum;
+use intermediate::write_pair;
+
+/// Decides how a reduce partition's results are written: the name of its
+/// result file, how each key and reduced value is encoded, and how a finished
+/// attempt's file is moved into place.
+pub trait OutputFormat<K, V>: Send + Sync {
+    /// The name of partition `partition`'s result file in the working directory.
+    fn file_name(&self, partition: i32) -> String {
+        format!("reduce.{}.result", partition)
+    }
+
+    fn write_record(&self, writer: &mut dyn Write, key: &K, value: &V) -> io::Result<()>;
+
+    /// Commits a finished attempt's file to its final path. Defaults to a
+    /// rename, so a result file is never seen half written.
+    fn commit(&self, attempt: &Path, output: &Path) -> io::Result<()> {
+        rename(attempt, output)
+    }
+}
+
+/// One `key=value` line per key.
+pub struct KeyValueText;
+
+impl<K: Datum, V: Datum> OutputFormat<K, V> for KeyValueText {
+    fn write_record(&self, writer: &mut dyn Write, key: &K, value: &V) -> io::Result<()> {
+        let mut line = vec![];
+        key.write_text(&mut line);
+        line.push(b'=');
+        value.write_text(&mut line);
+        line.push(b'\n');
+        writer.write_all(&line)
+    }
+}
+
+/// One tab-separated `key value` line per key. Tabs, newlines and backslashes
+/// inside keys and values are backslash-escaped.
+pub struct Tsv;
+
+impl<K: Datum, V: Datum> OutputFormat<K, V> for Tsv {
+    fn file_name(&self, partition: i32) -> String {
+        format!("reduce.{}.tsv", partition)
+    }
+
+    fn write_record(&self, writer: &mut dyn Write, key: &K, value: &V) -> io::Result<()> {
+        let mut line = vec![];
+        write_tsv_field(key, &mut line);
+        line.push(b'\t');
+        write_tsv_field(value, &mut line);
+        line.push(b'\n');
+        writer.write_all(&line)
+    }
+}
+
+fn write_tsv_field<T: Datum>(datum: &T, line: &mut Vec<u8>) {
+    let mut text = vec![];
+    datum.write_text(&mut text);
+    for byte in text {
+        match byte {
+            b'\t' => line.extend_from_slice(b"\\t"),
+            b'\n' => line.extend_from_slice(b"\\n"),
+            b'\r' => line.extend_from_slice(b"\\r"),
+            b'\\' => line.extend_from_slice(b"\\\\"),
+            byte => line.push(byte)
+        }
+    }
+}
+
+/// One `{"key": ..., "value": ...}` object per line. Integers and booleans are
+/// written as JSON numbers and booleans, everything else as strings.
+pub struct JsonLines;
+
+impl<K: Datum, V: Datum> OutputFormat<K, V> for JsonLines {
+    fn file_name(&self, partition: i32) -> String {
+        format!("reduce.{}.jsonl", partition)
+    }
+
+    fn write_record(&self, writer: &mut dyn Write, key: &K, value: &V) -> io::Result<()> {
+        let mut line = b"{\"key\":".to_vec();
+        key.write_json(&mut line);
+        line.extend_from_slice(b",\"value\":");
+        value.write_json(&mut line);
+        line.extend_from_slice(b"}\n");
+        writer.write_all(&line)
+    }
+}
+
+/// Binary records in the intermediate file layout: a big-endian `u32` key
+/// length, the encoded key, a `u32` value length and the encoded value.
+pub struct LengthPrefixed;
+
+impl<K: Datum, V: Datum> OutputFormat<K, V> for LengthPrefixed {
+    fn file_name(&self, partition: i32) -> String {
+        format!("reduce.{}.bin", partition)
+    }
+
+    fn write_record(&self, writer: &mut dyn Write, key: &K, value: &V) -> io::Result<()> {
+        write_pair(writer, key, value)
+    }
+}
+
+/// Each value's text as is, with no key or separator. Used by the closure API,
+/// whose reduce function builds its own output.
+pub struct PlainText;
+
+impl<K, V: Datum> OutputFormat<K, V> for PlainText {
+    fn write_record(&self, writer: &mut dyn Write, _key: &K, value: &V) -> io::Result<()> {
+        let mut text = vec![];
+        value.write_text(&mut text);
+        writer.write_all(&text)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use intermediate::read_pairs;
+    use super::{ OutputFormat, KeyValueText, Tsv, JsonLines, LengthPrefixed, PlainText };
+
+    fn write_all(format: &dyn OutputFormat<String, u64>) -> Vec<u8> {
+        let mut buffer = vec![];
+        format.write_record(&mut buffer, &"martians".to_string(), &120).unwrap();
+        format.write_record(&mut buffer, &"heat\tray \"1\"".to_string(), &3).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn text_formats_encode_keys_and_values() {
+        assert_eq!(String::from_utf8(write_all(&KeyValueText)).unwrap(),
+                   "martians=120\nheat\tray \"1\"=3\n");
+        assert_eq!(String::from_utf8(write_all(&Tsv)).unwrap(),
+                   "martians\t120\nheat\\tray \"1\"\t3\n");
+        assert_eq!(String::from_utf8(write_all(&JsonLines)).unwrap(),
+                   "{\"key\":\"martians\",\"value\":120}\n{\"key\":\"heat\\tray \\\"1\\\"\",\"value\":3}\n");
+        assert_eq!(String::from_utf8(write_all(&PlainText)).unwrap(), "1203");
+    }
+
+    #[test]
+    fn length_prefixed_records_read_back_as_pairs() {
+        let pairs = read_pairs::<String, u64, _>(&mut Cursor::new(write_all(&LengthPrefixed))).unwrap();
+
+        assert_eq!(pairs, vec![("martians".to_string(), 120), ("heat\tray \"1\"".to_string(), 3)]);
+        assert_eq!(OutputFormat::<String, u64>::file_name(&LengthPrefixed, 2), "reduce.2.bin");
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{ Hash, Hasher };
+use std::path::PathBuf;
+
+use functions::{ Mapper, Emitter };
+use input::{ InputFormat, TextLines };
+use split::InputSplit;
+
+/// Chooses which of `n_partitions` reduce partitions a key is sent to.
+pub trait Partitioner<K>: Send + Sync {
+    fn partition(&self, key: &K, n_partitions: usize) -> usize;
+}
+
+/// The default partitioner: spreads keys across partitions by hash.
+pub struct HashPartitioner;
+
+impl<K: Hash> Partitioner<K> for HashPartitioner {
+    fn partition(&self, key: &K, n_partitions: usize) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % n_partitions as u64) as usize
+    }
+}
+
+/// Sends bucket `i` produced by a `ClosureMapper` to partition `i`, wrapping
+/// around when there are more buckets than partitions.
+pub struct BucketPartitioner;
+
+impl Partitioner<usize> for BucketPartitioner {
+    fn partition(&self, bucket: &usize, n_partitions: usize) -> usize {
+        bucket % n_partitions
+    }
+}
+
+/// Assigns contiguous key ranges to partitions so that result files are
+/// globally ordered. Partition `i` holds keys below `boundaries[i]`; the last
+/// partition holds everything else.
+pub struct RangePartitioner<K> {
+    boundaries: Vec<K>
+}
+
+impl<K: Ord + Clone> RangePartitioner<K> {
+    pub fn new(boundaries: Vec<K>) -> Self {
+        RangePartitioner { boundaries }
+    }
+
+    /// Picks `n_partitions - 1` evenly spaced boundaries from a sample of keys.
+    pub fn from_sample(mut sample: Vec<K>, n_partitions: usize) -> Self {
+        sample.sort();
+        sample.dedup();
+        let boundaries = (1..n_partitions).filter_map(|i| {
+                                              sample.get(i * sample.len() / n_partitions).cloned()
+                                          })
+                                          .collect::<Vec<K>>();
+        RangePartitioner::new(boundaries)
+    }
+
+    /// Runs `mapper` over `inputs` and builds the partitioner from a uniform
+    /// random sample of at most `keys_per_input` keys of each input's output,
+    /// so only the sample is held in memory. Inputs are read as `TextLines`;
+    /// inputs the mapper rejects contribute no keys.
+    pub fn sample_inputs<M>(mapper: &M,
+                            inputs: &[PathBuf],
+                            n_partitions: usize,
+                            keys_per_input: usize
+                            ) -> Self
+        where M: Mapper<Key = K>
+    {
+        let mut random = SAMPLE_SEED;
+        let sample = inputs.iter()
+                           .filter_map(|path| TextLines.records(&InputSplit::whole(path.clone())).ok())
+                           .flat_map(|records| {
+                               // Reservoir sampling: the `seen`th key replaces a
+                               // sampled one with probability `keys_per_input / seen`.
+                               let mut keys = Vec::with_capacity(keys_per_input);
+                               let mut seen = 0;
+                               let mapped = mapper.map(&mut records.map_while(Result::ok),
+                                                       &mut Emitter::new(&mut |key, _| {
+                                                           seen += 1;
+                                                           if keys.len() < keys_per_input {
+                                                               keys.push(key);
+                                                           } else {
+                                                               let slot = (xorshift(&mut random) % seen) as usize;
+                                                               if slot < keys_per_input {
+                                                                   keys[slot] = key;
+                                                               }
+                                                           }
+                                                       }));
+                               if mapped.is_err() {
+                                   keys.clear();
+                               }
+                               keys
+                           })
+                           .collect::<Vec<K>>();
+        RangePartitioner::from_sample(sample, n_partitions)
+    }
+}
+
+/// Seeds the generator `sample_inputs` samples with, so that the same inputs
+/// always give the same partitioner.
+const SAMPLE_SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+fn xorshift(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+impl<K: Ord + Send + Sync> Partitioner<K> for RangePartitioner<K> {
+    fn partition(&self, key: &K, n_partitions: usize) -> usize {
+        let index = match self.boundaries.binary_search(key) {
+            Ok(i) => i + 1,
+            Err(i) => i
+        };
+        index.min(n_partitions - 1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::Infallible;
+    use std::fs::{ create_dir_all, remove_dir_all, write };
+    use std::path::PathBuf;
+
+    use functions::{ Mapper, Emitter };
+    use input::Record;
+    use super::{ Partitioner, HashPartitioner, RangePartitioner };
+
+    /// Emits each line as a key.
+    struct Lines;
+
+    impl Mapper for Lines {
+        type Key = String;
+        type Value = u64;
+        type Error = Infallible;
+
+        fn map(&self, records: &mut dyn Iterator<Item = Record>, output: &mut Emitter<String, u64>) -> Result<(), Infallible> {
+            for record in records {
+                output.emit(String::from_utf8_lossy(&record.bytes).into_owned(), 1);
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn hash_partitioner_is_stable_and_in_range() {
+        let words = vec!["the", "martians", "landed", "at", "horsell", "common"];
+        for word in words {
+            let partition = HashPartitioner.partition(&word, 3);
+            assert!(partition < 3);
+            assert_eq!(HashPartitioner.partition(&word, 3), partition);
+        }
+    }
+
+    #[test]
+    fn range_partitioner_orders_partitions_by_key() {
+        let sample = (0..100).rev().collect::<Vec<u32>>();
+        let partitioner = RangePartitioner::from_sample(sample, 4);
+
+        assert_eq!(partitioner.partition(&0, 4), 0);
+        assert_eq!(partitioner.partition(&24, 4), 0);
+        assert_eq!(partitioner.partition(&25, 4), 1);
+        assert_eq!(partitioner.partition(&74, 4), 2);
+        assert_eq!(partitioner.partition(&99, 4), 3);
+        assert_eq!(partitioner.partition(&1000, 4), 3);
+    }
+
+    #[test]
+    fn sampled_boundaries_come_from_the_whole_input() {
+        let directory = PathBuf::from("./test-data/range_partitioner_samples_inputs");
+        create_dir_all(&directory).unwrap();
+        let input = directory.join("input");
+        let lines = (0..10000).map(|n| format!("{:05}\n", n)).collect::<String>();
+        write(&input, lines).unwrap();
+
+        let partitioner = RangePartitioner::sample_inputs(&Lines, &[input], 2, 50);
+
+        // Sampling only the start of the sorted input would put the boundary
+        // near its first keys.
+        assert_eq!(partitioner.boundaries.len(), 1);
+        let boundary = partitioner.boundaries[0].parse::<u32>().unwrap();
+        assert!(boundary > 2500 && boundary < 7500, "boundary {}", boundary);
+        remove_dir_all(&directory).unwrap();
+    }
+}
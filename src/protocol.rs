@@ -0,0 +1,790 @@
+use std::collections::{ BTreeMap, BTreeSet };
+#[cfg(unix)]
+use std::ffi::OsStr;
+use std::io;
+use std::io::{ Read, Write };
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use compression::Codec;
+use error::MapReduceError;
+use registry::{ Named, Functions, JobSpec };
+use split::InputSplit;
+use task::Task;
+use worker::{ Job, JobResult };
+
+/// The version of the encoding `send` writes. `receive` accepts frames of
+/// this version or any earlier one.
+pub const VERSION: u16 = 2;
+
+/// The longest frame, not counting its length, that `send` writes and
+/// `receive` accepts.
+pub const MAX_FRAME: usize = 64 * 1024 * 1024;
+
+/// A value exchanged between a master and its workers. Integers are
+/// big-endian, strings and paths are a `u32` length and their bytes, lists a
+/// `u32` count and their items, and enums a tag byte and their fields. The
+/// README lists the encoding of every message.
+pub trait Message: Sized {
+    fn write(&self, buffer: &mut Vec<u8>);
+    fn read(bytes: &mut &[u8]) -> io::Result<Self>;
+}
+
+/// Sent by a master to a worker process.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Run this attempt and report its results.
+    Assign(Job),
+    /// There are no jobs left; the worker should exit.
+    Shutdown,
+    /// Build the functions named in this spec from the worker's `Registry`.
+    /// Sent before any job, by masters built from a registry. Added in
+    /// version 2.
+    Configure(Box<JobSpec>)
+}
+
+/// Sends a message framed by its `u32` length and the protocol `VERSION`.
+pub fn send<T: Message, W: Write>(writer: &mut W, message: &T) -> io::Result<()> {
+    let mut body = VERSION.to_be_bytes().to_vec();
+    message.write(&mut body);
+    if body.len() > MAX_FRAME {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "message is longer than the longest frame"));
+    }
+    let mut frame = (body.len() as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(&body);
+    writer.write_all(&frame)?;
+    writer.flush()
+}
+
+/// Receives a message sent by `send`, or `None` if the other end has closed
+/// the connection. A frame of a later version than `VERSION`, or longer than
+/// `MAX_FRAME`, is an error.
+pub fn receive<T: Message, R: Read>(reader: &mut R) -> io::Result<Option<T>> {
+    let mut length = [0; 4];
+    match reader.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e)
+    }
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_FRAME {
+        return Err(invalid_data(&format!("frame of {} bytes is longer than the longest frame", length)));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    let mut bytes = &body[..];
+    let version = u16::read(&mut bytes)?;
+    if version == 0 || version > VERSION {
+        return Err(invalid_data(&format!("unsupported protocol version {}", version)));
+    }
+    let message = T::read(&mut bytes)?;
+    if !bytes.is_empty() {
+        return Err(invalid_data("message has trailing bytes"));
+    }
+    Ok(Some(message))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub(crate) fn read_tag(bytes: &mut &[u8]) -> io::Result<u8> {
+    let mut tag = [0];
+    bytes.read_exact(&mut tag)?;
+    Ok(tag[0])
+}
+
+pub(crate) fn unknown_tag<T>(what: &str, tag: u8) -> io::Result<T> {
+    Err(invalid_data(&format!("unknown {} tag {}", what, tag)))
+}
+
+macro_rules! integer_message {
+    ($($t:ty),*) => {
+        $(
+            impl Message for $t {
+                fn write(&self, buffer: &mut Vec<u8>) {
+                    buffer.extend_from_slice(&self.to_be_bytes());
+                }
+
+                fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+                    let mut raw = [0; ::std::mem::size_of::<$t>()];
+                    bytes.read_exact(&mut raw)?;
+                    Ok(<$t>::from_be_bytes(raw))
+                }
+            }
+        )*
+    }
+}
+
+integer_message!(u8, u16, u32, u64, i32);
+
+impl Message for usize {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        (*self as u64).write(buffer)
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        u64::read(bytes).map(|n| n as usize)
+    }
+}
+
+fn write_bytes(buffer: &mut Vec<u8>, bytes: &[u8]) {
+    (bytes.len() as u32).write(buffer);
+    buffer.extend_from_slice(bytes);
+}
+
+fn read_bytes<'a>(bytes: &mut &'a [u8]) -> io::Result<&'a [u8]> {
+    let length = u32::read(bytes)? as usize;
+    if bytes.len() < length {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated field"));
+    }
+    let (field, rest) = bytes.split_at(length);
+    *bytes = rest;
+    Ok(field)
+}
+
+impl Message for String {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        write_bytes(buffer, self.as_bytes())
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        String::from_utf8(read_bytes(bytes)?.to_vec()).map_err(|_| invalid_data("string is not UTF-8"))
+    }
+}
+
+/// Paths are sent as their raw bytes on Unix, and as UTF-8 elsewhere.
+#[cfg(unix)]
+impl Message for PathBuf {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        write_bytes(buffer, self.as_os_str().as_bytes())
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        Ok(PathBuf::from(OsStr::from_bytes(read_bytes(bytes)?)))
+    }
+}
+
+#[cfg(not(unix))]
+impl Message for PathBuf {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        write_bytes(buffer, self.to_string_lossy().as_bytes())
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        String::read(bytes).map(PathBuf::from)
+    }
+}
+
+impl<T: Message> Message for Vec<T> {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        (self.len() as u32).write(buffer);
+        for item in self {
+            item.write(buffer);
+        }
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        let count = u32::read(bytes)?;
+        (0..count).map(|_| T::read(bytes)).collect()
+    }
+}
+
+impl<K: Message + Ord, V: Message> Message for BTreeMap<K, V> {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        (self.len() as u32).write(buffer);
+        for (key, value) in self {
+            key.write(buffer);
+            value.write(buffer);
+        }
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        let count = u32::read(bytes)?;
+        (0..count).map(|_| Ok((K::read(bytes)?, V::read(bytes)?))).collect()
+    }
+}
+
+impl<T: Message + Ord> Message for BTreeSet<T> {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        (self.len() as u32).write(buffer);
+        for item in self {
+            item.write(buffer);
+        }
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        let count = u32::read(bytes)?;
+        (0..count).map(|_| T::read(bytes)).collect()
+    }
+}
+
+impl<T: Message> Message for Option<T> {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        match *self {
+            None => buffer.push(0),
+            Some(ref value) => {
+                buffer.push(1);
+                value.write(buffer);
+            }
+        }
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        match read_tag(bytes)? {
+            0 => Ok(None),
+            1 => T::read(bytes).map(Some),
+            tag => unknown_tag("option", tag)
+        }
+    }
+}
+
+impl<T: Message> Message for Box<T> {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        (**self).write(buffer)
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        T::read(bytes).map(Box::new)
+    }
+}
+
+impl<A: Message, B: Message> Message for (A, B) {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        self.0.write(buffer);
+        self.1.write(buffer);
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        Ok((A::read(bytes)?, B::read(bytes)?))
+    }
+}
+
+impl Message for Duration {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        self.as_secs().write(buffer);
+        self.subsec_nanos().write(buffer);
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        Ok(Duration::new(u64::read(bytes)?, u32::read(bytes)?))
+    }
+}
+
+impl Message for Task {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        match *self {
+            Task::Map(id) => {
+                buffer.push(0);
+                id.write(buffer);
+            }
+            Task::Reduce(id) => {
+                buffer.push(1);
+                id.write(buffer);
+            }
+        }
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        match read_tag(bytes)? {
+            0 => Ok(Task::Map(i32::read(bytes)?)),
+            1 => Ok(Task::Reduce(i32::read(bytes)?)),
+            tag => unknown_tag("task", tag)
+        }
+    }
+}
+
+impl Message for InputSplit {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        self.path.write(buffer);
+        self.offset.write(buffer);
+        self.length.write(buffer);
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        Ok(InputSplit { path: PathBuf::read(bytes)?, offset: u64::read(bytes)?, length: u64::read(bytes)? })
+    }
+}
+
+impl Message for Codec {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        buffer.push(self.id())
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        Codec::from_id(read_tag(bytes)?)
+    }
+}
+
+impl Message for Named {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        self.name.write(buffer);
+        write_bytes(buffer, &self.config);
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        Ok(Named { name: String::read(bytes)?, config: read_bytes(bytes)?.to_vec() })
+    }
+}
+
+impl Message for Functions {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        self.mapper.write(buffer);
+        self.reducer.write(buffer);
+        self.combiner.write(buffer);
+        self.partitioner.write(buffer);
+        self.output_format.write(buffer);
+        self.inputs.write(buffer);
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        Ok(Functions {
+            mapper: Named::read(bytes)?,
+            reducer: Named::read(bytes)?,
+            combiner: Option::read(bytes)?,
+            partitioner: Option::read(bytes)?,
+            output_format: Option::read(bytes)?,
+            inputs: Vec::read(bytes)?
+        })
+    }
+}
+
+impl Message for JobSpec {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        self.functions.write(buffer);
+        self.working_directory.write(buffer);
+        self.n_reduce.write(buffer);
+        self.map_buffer_size.write(buffer);
+        self.intermediate_codec.write(buffer);
+        self.output_codec.write(buffer);
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        Ok(JobSpec {
+            functions: Functions::read(bytes)?,
+            working_directory: PathBuf::read(bytes)?,
+            n_reduce: usize::read(bytes)?,
+            map_buffer_size: usize::read(bytes)?,
+            intermediate_codec: Codec::read(bytes)?,
+            output_codec: Codec::read(bytes)?
+        })
+    }
+}
+
+impl Message for Job {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        match *self {
+            Job::Map((job_id, attempt, ref split, ref skipped)) => {
+                buffer.push(0);
+                job_id.write(buffer);
+                attempt.write(buffer);
+                split.write(buffer);
+                skipped.write(buffer);
+            }
+            Job::Reduce((job_id, attempt, ref paths)) => {
+                buffer.push(1);
+                job_id.write(buffer);
+                attempt.write(buffer);
+                paths.write(buffer);
+            }
+        }
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        match read_tag(bytes)? {
+            0 => Ok(Job::Map((i32::read(bytes)?, u32::read(bytes)?, InputSplit::read(bytes)?, Option::read(bytes)?))),
+            1 => Ok(Job::Reduce((i32::read(bytes)?, u32::read(bytes)?, Vec::read(bytes)?))),
+            tag => unknown_tag("job", tag)
+        }
+    }
+}
+
+impl Message for Command {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        match *self {
+            Command::Assign(ref job) => {
+                buffer.push(0);
+                job.write(buffer);
+            }
+            Command::Shutdown => buffer.push(1),
+            Command::Configure(ref spec) => {
+                buffer.push(2);
+                spec.write(buffer);
+            }
+        }
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        match read_tag(bytes)? {
+            0 => Ok(Command::Assign(Job::read(bytes)?)),
+            1 => Ok(Command::Shutdown),
+            2 => Ok(Command::Configure(Box::new(JobSpec::read(bytes)?))),
+            tag => unknown_tag("command", tag)
+        }
+    }
+}
+
+impl Message for MapReduceError {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        match *self {
+            MapReduceError::ReadInput { task, attempt, ref path, ref reason } => {
+                buffer.push(0);
+                task.write(buffer);
+                attempt.write(buffer);
+                path.write(buffer);
+                reason.write(buffer);
+            }
+            MapReduceError::CorruptIntermediate { task, attempt, ref path, ref reason } => {
+                buffer.push(1);
+                task.write(buffer);
+                attempt.write(buffer);
+                path.write(buffer);
+                reason.write(buffer);
+            }
+            MapReduceError::WriteOutput { task, attempt, ref path, ref reason } => {
+                buffer.push(2);
+                task.write(buffer);
+                attempt.write(buffer);
+                path.write(buffer);
+                reason.write(buffer);
+            }
+            MapReduceError::FunctionFailed { task, attempt, ref message } => {
+                buffer.push(3);
+                task.write(buffer);
+                attempt.write(buffer);
+                message.write(buffer);
+            }
+            MapReduceError::Panicked { task, attempt, ref message } => {
+                buffer.push(4);
+                task.write(buffer);
+                attempt.write(buffer);
+                message.write(buffer);
+            }
+            MapReduceError::TimedOut { task, attempt, timeout } => {
+                buffer.push(5);
+                task.write(buffer);
+                attempt.write(buffer);
+                timeout.write(buffer);
+            }
+            MapReduceError::InvalidWorkingDirectory { ref path, ref reason } => {
+                buffer.push(6);
+                path.write(buffer);
+                reason.write(buffer);
+            }
+            MapReduceError::RetriesExhausted { task, attempts, ref cause } => {
+                buffer.push(7);
+                task.write(buffer);
+                attempts.write(buffer);
+                cause.write(buffer);
+            }
+            MapReduceError::WorkerFailed { worker, ref reason } => {
+                buffer.push(8);
+                worker.write(buffer);
+                reason.write(buffer);
+            }
+            MapReduceError::WorkerLost { task, attempt, worker, ref reason } => {
+                buffer.push(9);
+                task.write(buffer);
+                attempt.write(buffer);
+                worker.write(buffer);
+                reason.write(buffer);
+            }
+            MapReduceError::InvalidFunction { ref name, ref reason } => {
+                buffer.push(10);
+                name.write(buffer);
+                reason.write(buffer);
+            }
+            MapReduceError::InvalidConfiguration { ref reason } => {
+                buffer.push(11);
+                reason.write(buffer);
+            }
+            MapReduceError::ConnectionFailed { ref reason } => {
+                buffer.push(12);
+                reason.write(buffer);
+            }
+        }
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        Ok(match read_tag(bytes)? {
+            0 => MapReduceError::ReadInput {
+                task: Task::read(bytes)?,
+                attempt: u32::read(bytes)?,
+                path: PathBuf::read(bytes)?,
+                reason: String::read(bytes)?
+            },
+            1 => MapReduceError::CorruptIntermediate {
+                task: Task::read(bytes)?,
+                attempt: u32::read(bytes)?,
+                path: PathBuf::read(bytes)?,
+                reason: String::read(bytes)?
+            },
+            2 => MapReduceError::WriteOutput {
+                task: Task::read(bytes)?,
+                attempt: u32::read(bytes)?,
+                path: PathBuf::read(bytes)?,
+                reason: String::read(bytes)?
+            },
+            3 => MapReduceError::FunctionFailed {
+                task: Task::read(bytes)?,
+                attempt: u32::read(bytes)?,
+                message: String::read(bytes)?
+            },
+            4 => MapReduceError::Panicked {
+                task: Task::read(bytes)?,
+                attempt: u32::read(bytes)?,
+                message: String::read(bytes)?
+            },
+            5 => MapReduceError::TimedOut {
+                task: Task::read(bytes)?,
+                attempt: u32::read(bytes)?,
+                timeout: Duration::read(bytes)?
+            },
+            6 => MapReduceError::InvalidWorkingDirectory {
+                path: PathBuf::read(bytes)?,
+                reason: String::read(bytes)?
+            },
+            7 => MapReduceError::RetriesExhausted {
+                task: Task::read(bytes)?,
+                attempts: u32::read(bytes)?,
+                cause: Box::new(MapReduceError::read(bytes)?)
+            },
+            8 => MapReduceError::WorkerFailed {
+                worker: usize::read(bytes)?,
+                reason: String::read(bytes)?
+            },
+            9 => MapReduceError::WorkerLost {
+                task: Task::read(bytes)?,
+                attempt: u32::read(bytes)?,
+                worker: usize::read(bytes)?,
+                reason: String::read(bytes)?
+            },
+            10 => MapReduceError::InvalidFunction {
+                name: String::read(bytes)?,
+                reason: String::read(bytes)?
+            },
+            11 => MapReduceError::InvalidConfiguration {
+                reason: String::read(bytes)?
+            },
+            12 => MapReduceError::ConnectionFailed {
+                reason: String::read(bytes)?
+            },
+            tag => return unknown_tag("error", tag)
+        })
+    }
+}
+
+impl Message for JobResult {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        match *self {
+            JobResult::Started(worker, task, attempt) => {
+                buffer.push(0);
+                worker.write(buffer);
+                task.write(buffer);
+                attempt.write(buffer);
+            }
+            JobResult::MapFinished(job_id, attempt, ref outputs) => {
+                buffer.push(1);
+                job_id.write(buffer);
+                attempt.write(buffer);
+                outputs.write(buffer);
+            }
+            JobResult::ReduceFinished(job_id, attempt, ref output) => {
+                buffer.push(2);
+                job_id.write(buffer);
+                attempt.write(buffer);
+                output.write(buffer);
+            }
+            JobResult::FunctionFailed(task, attempt, ref message) => {
+                buffer.push(3);
+                task.write(buffer);
+                attempt.write(buffer);
+                message.write(buffer);
+            }
+            JobResult::RecordFailed(task, attempt, offset, ref error) => {
+                buffer.push(4);
+                task.write(buffer);
+                attempt.write(buffer);
+                offset.write(buffer);
+                error.write(buffer);
+            }
+            JobResult::CorruptInput(task, attempt, ref path, ref error) => {
+                buffer.push(5);
+                task.write(buffer);
+                attempt.write(buffer);
+                path.write(buffer);
+                error.write(buffer);
+            }
+            JobResult::Failed(task, attempt, ref error) => {
+                buffer.push(6);
+                task.write(buffer);
+                attempt.write(buffer);
+                error.write(buffer);
+            }
+            JobResult::Heartbeat(worker) => {
+                buffer.push(7);
+                worker.write(buffer);
+            }
+        }
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        Ok(match read_tag(bytes)? {
+            0 => JobResult::Started(usize::read(bytes)?, Task::read(bytes)?, u32::read(bytes)?),
+            1 => JobResult::MapFinished(i32::read(bytes)?, u32::read(bytes)?, Vec::read(bytes)?),
+            2 => JobResult::ReduceFinished(i32::read(bytes)?, u32::read(bytes)?, PathBuf::read(bytes)?),
+            3 => JobResult::FunctionFailed(Task::read(bytes)?, u32::read(bytes)?, String::read(bytes)?),
+            4 => JobResult::RecordFailed(Task::read(bytes)?, u32::read(bytes)?, u64::read(bytes)?, MapReduceError::read(bytes)?),
+            5 => JobResult::CorruptInput(Task::read(bytes)?, u32::read(bytes)?, PathBuf::read(bytes)?, MapReduceError::read(bytes)?),
+            6 => JobResult::Failed(Task::read(bytes)?, u32::read(bytes)?, MapReduceError::read(bytes)?),
+            7 => JobResult::Heartbeat(usize::read(bytes)?),
+            tag => return unknown_tag("result", tag)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{ Cursor, ErrorKind };
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use compression::Codec;
+    use error::MapReduceError;
+    use registry::{ Named, Functions, JobSpec };
+    use split::InputSplit;
+    use task::Task;
+    use worker::{ Job, JobResult };
+    use super::{ Command, MAX_FRAME, VERSION, send, receive };
+
+    #[test]
+    fn jobs_and_results_round_trip() {
+        let jobs = vec![Job::Map((3, 2, InputSplit { path: PathBuf::from("input"), offset: 10, length: 20 }, Some(vec![4, 9]))),
+                        Job::Map((4, 1, InputSplit::whole(PathBuf::from("other")), None)),
+                        Job::Reduce((1, 5, vec![PathBuf::from("map.1.reduce.1"), PathBuf::from("map.2.reduce.1")]))];
+        let functions = Functions::new(Named::new("word_count").with_config(vec![0, 7]), Named::new("sum"))
+            .with_combiner(Named::new("sum"))
+            .with_input(PathBuf::from("records"), Named::new("delimited").with_config(b"|".to_vec()));
+        let spec = JobSpec {
+            functions,
+            working_directory: PathBuf::from("work"),
+            n_reduce: 3,
+            map_buffer_size: 4096,
+            intermediate_codec: Codec::None,
+            output_codec: Codec::None
+        };
+        let commands = vec![Command::Configure(Box::new(spec)), Command::Assign(jobs[0].clone()), Command::Shutdown];
+        let timed_out = MapReduceError::TimedOut { task: Task::Map(3), attempt: 2, timeout: Duration::from_millis(1500) };
+        let results = vec![JobResult::Started(7, Task::Reduce(1), 5),
+                           JobResult::Heartbeat(7),
+                           JobResult::MapFinished(3, 2, vec![PathBuf::from("map.3.reduce.1.attempt.2.tmp"),
+                                                             PathBuf::from("map.3.reduce.2.attempt.2.tmp")]),
+                           JobResult::ReduceFinished(1, 5, PathBuf::from("reduce.1.attempt.5.tmp")),
+                           JobResult::FunctionFailed(Task::Map(3), 2, "malformed record".to_string()),
+                           JobResult::RecordFailed(Task::Map(3), 2, 42, MapReduceError::Panicked {
+                               task: Task::Map(3),
+                               attempt: 2,
+                               message: "poisoned".to_string()
+                           }),
+                           JobResult::CorruptInput(Task::Reduce(1), 5, PathBuf::from("map.1.reduce.1"), MapReduceError::CorruptIntermediate {
+                               task: Task::Reduce(1),
+                               attempt: 5,
+                               path: PathBuf::from("map.1.reduce.1"),
+                               reason: "block 0 failed its checksum".to_string()
+                           }),
+                           JobResult::Failed(Task::Map(4), 1, MapReduceError::RetriesExhausted {
+                               task: Task::Map(4),
+                               attempts: 4,
+                               cause: Box::new(timed_out)
+                           })];
+
+        let mut stream = vec![];
+        for job in jobs.iter() {
+            send(&mut stream, job).unwrap();
+        }
+        for command in commands.iter() {
+            send(&mut stream, command).unwrap();
+        }
+        for result in results.iter() {
+            send(&mut stream, result).unwrap();
+        }
+
+        let mut stream = Cursor::new(stream);
+        for job in jobs {
+            assert_eq!(receive::<Job, _>(&mut stream).unwrap(), Some(job));
+        }
+        for command in commands {
+            assert_eq!(receive::<Command, _>(&mut stream).unwrap(), Some(command));
+        }
+        for result in results {
+            assert_eq!(receive::<JobResult, _>(&mut stream).unwrap(), Some(result));
+        }
+        assert_eq!(receive::<JobResult, _>(&mut stream).unwrap(), None);
+    }
+
+    #[test]
+    fn version_1_frames_still_decode() {
+        // Written out by hand from the encoding in the README, so that a
+        // change to the encoding of version 1 fails here.
+        let commands: Vec<&[&[u8]]> = vec![
+            &[&[0, 0, 0, 50, 0, 1, 0, 0, 0, 0, 0, 3, 0, 0, 0, 2, 0, 0, 0, 5], b"input",
+              &[0, 0, 0, 0, 0, 0, 0, 10, 0, 0, 0, 0, 0, 0, 0, 20, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 4]],
+            &[&[0, 0, 0, 34, 0, 1, 0, 1, 0, 0, 0, 1, 0, 0, 0, 5, 0, 0, 0, 1, 0, 0, 0, 14], b"map.1.reduce.1"],
+            &[&[0, 0, 0, 3, 0, 1, 1]]
+        ];
+        let results: Vec<&[&[u8]]> = vec![
+            &[&[0, 0, 0, 20, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 0, 3, 0, 0, 0, 2]],
+            &[&[0, 0, 0, 11, 0, 1, 7, 0, 0, 0, 0, 0, 0, 0, 7]],
+            &[&[0, 0, 0, 47, 0, 1, 1, 0, 0, 0, 3, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 28], b"map.3.reduce.1.attempt.2.tmp"],
+            &[&[0, 0, 0, 37, 0, 1, 2, 0, 0, 0, 1, 0, 0, 0, 5, 0, 0, 0, 22], b"reduce.1.attempt.5.tmp"],
+            &[&[0, 0, 0, 38, 0, 1, 6, 1, 0, 0, 0, 1, 0, 0, 0, 5, 9, 1, 0, 0, 0, 1, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 4], b"gone"]
+        ];
+
+        let mut stream = Cursor::new(commands.concat().concat());
+        assert_eq!(receive::<Command, _>(&mut stream).unwrap(),
+                   Some(Command::Assign(Job::Map((3, 2, InputSplit { path: PathBuf::from("input"), offset: 10, length: 20 }, Some(vec![4]))))));
+        assert_eq!(receive::<Command, _>(&mut stream).unwrap(),
+                   Some(Command::Assign(Job::Reduce((1, 5, vec![PathBuf::from("map.1.reduce.1")])))));
+        assert_eq!(receive::<Command, _>(&mut stream).unwrap(), Some(Command::Shutdown));
+        assert_eq!(receive::<Command, _>(&mut stream).unwrap(), None);
+
+        let mut stream = Cursor::new(results.concat().concat());
+        let lost = MapReduceError::WorkerLost { task: Task::Reduce(1), attempt: 5, worker: 2, reason: "gone".to_string() };
+        let expected = vec![JobResult::Started(7, Task::Map(3), 2),
+                            JobResult::Heartbeat(7),
+                            JobResult::MapFinished(3, 2, vec![PathBuf::from("map.3.reduce.1.attempt.2.tmp")]),
+                            JobResult::ReduceFinished(1, 5, PathBuf::from("reduce.1.attempt.5.tmp")),
+                            JobResult::Failed(Task::Reduce(1), 5, lost)];
+        for result in expected {
+            assert_eq!(receive::<JobResult, _>(&mut stream).unwrap(), Some(result));
+        }
+        assert_eq!(receive::<JobResult, _>(&mut stream).unwrap(), None);
+    }
+
+    #[test]
+    fn frames_of_a_later_version_are_rejected() {
+        let mut frame = vec![];
+        send(&mut frame, &Command::Shutdown).unwrap();
+        frame[4..6].copy_from_slice(&(VERSION + 1).to_be_bytes());
+
+        let error = receive::<Command, _>(&mut Cursor::new(frame)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("unsupported protocol version"));
+    }
+
+    #[test]
+    fn overlong_frames_are_rejected() {
+        // Only the length is sent, so reading the body would fail differently.
+        let frame = (MAX_FRAME as u32 + 1).to_be_bytes().to_vec();
+
+        let error = receive::<Command, _>(&mut Cursor::new(frame)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("longer than the longest frame"));
+
+        let message = "x".repeat(MAX_FRAME);
+        let error = send(&mut vec![], &message).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+}
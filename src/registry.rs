@@ -0,0 +1,450 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::ToSocketAddrs;
+use std::path::{ Path, PathBuf };
+#[cfg(unix)]
+use std::process;
+use std::sync::Arc;
+
+use compression::Codec;
+use datum::Datum;
+use error::MapReduceError;
+#[cfg(unix)]
+use executor;
+use executor::RunWorker;
+use functions::{ Mapper, Reducer, Combiner, Emitter, BoxError, ClosureError };
+use input::{ InputFormat, Record, TextLines, Delimited, FixedLength, WholeFile };
+use master::Master;
+use network;
+use output::{ OutputFormat, KeyValueText, Tsv, JsonLines, LengthPrefixed, PlainText };
+use partitioner::{ Partitioner, HashPartitioner };
+
+/// A registered function or format: the name it was registered under and
+/// the configuration its factory is called with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Named {
+    pub name: String,
+    pub config: Vec<u8>
+}
+
+impl Named {
+    pub fn new(name: &str) -> Self {
+        Named { name: name.to_string(), config: vec![] }
+    }
+
+    pub fn with_config(mut self, config: Vec<u8>) -> Self {
+        self.config = config;
+        self
+    }
+}
+
+/// The functions and formats of a job, by name. Without a partitioner or
+/// output format, `hash` and `key_value_text` are used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Functions {
+    pub mapper: Named,
+    pub reducer: Named,
+    pub combiner: Option<Named>,
+    pub partitioner: Option<Named>,
+    pub output_format: Option<Named>,
+    /// Inputs read with a format of their own rather than as `text_lines`.
+    pub inputs: Vec<(PathBuf, Named)>
+}
+
+impl Functions {
+    pub fn new(mapper: Named, reducer: Named) -> Self {
+        Functions { mapper, reducer, combiner: None, partitioner: None, output_format: None, inputs: vec![] }
+    }
+
+    pub fn with_combiner(mut self, combiner: Named) -> Self {
+        self.combiner = Some(combiner);
+        self
+    }
+
+    pub fn with_partitioner(mut self, partitioner: Named) -> Self {
+        self.partitioner = Some(partitioner);
+        self
+    }
+
+    pub fn with_output_format(mut self, output_format: Named) -> Self {
+        self.output_format = Some(output_format);
+        self
+    }
+
+    /// Adds an input read with the named input format.
+    pub fn with_input(mut self, input_file: PathBuf, format: Named) -> Self {
+        self.inputs.push((input_file, format));
+        self
+    }
+}
+
+/// Everything a worker needs to run a master's jobs: the master's functions
+/// by name, and its settings. Masters built from a `Registry` send it to
+/// their workers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobSpec {
+    pub functions: Functions,
+    pub working_directory: PathBuf,
+    pub n_reduce: usize,
+    pub map_buffer_size: usize,
+    pub intermediate_codec: Codec,
+    pub output_codec: Codec
+}
+
+type Factory<T> = Box<dyn Fn(&[u8]) -> Result<T, BoxError> + Send + Sync>;
+type Entry = Box<dyn Any + Send + Sync>;
+type BuildWorker = Box<dyn Fn(&Registry, &JobSpec) -> Result<RunWorker, MapReduceError> + Send + Sync>;
+
+/// Mappers, reducers, combiners, partitioners and formats registered by
+/// name, each with a factory building it from a configuration. A master
+/// built from a registry sends its workers only names and configurations,
+/// which they look up in the registry of their own copy of the binary, so
+/// a worker process does not have to build the master itself.
+///
+/// The partitioner `hash`, the input formats `text_lines`, `whole_file`,
+/// `delimited` (configured with the delimiter) and `fixed_length`
+/// (configured with the record length as a big-endian `u64`), and the output
+/// formats `key_value_text`, `tsv`, `json_lines`, `length_prefixed` and
+/// `plain_text` are always available.
+#[derive(Default)]
+pub struct Registry {
+    mappers: HashMap<String, Entry>,
+    reducers: HashMap<String, (Entry, BuildWorker)>,
+    combiners: HashMap<String, Entry>,
+    partitioners: HashMap<String, Entry>,
+    input_formats: HashMap<String, Factory<Arc<dyn InputFormat>>>,
+    output_formats: HashMap<String, Entry>
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Registry::default()
+    }
+
+    pub fn mapper<M, F>(mut self, name: &str, factory: F) -> Self
+        where M: Mapper + 'static,
+              F: Fn(&[u8]) -> Result<M, BoxError> + Send + Sync + 'static
+    {
+        let factory: Factory<RegisteredMapper<M::Key, M::Value>> = Box::new(move |config| factory(config).map(RegisteredMapper::new));
+        self.mappers.insert(name.to_string(), Box::new(factory));
+        self
+    }
+
+    /// Registers a reducer. Workers are built around the reducer a master
+    /// names, so its key, value and output types decide those of the other
+    /// functions.
+    pub fn reducer<R, F>(mut self, name: &str, factory: F) -> Self
+        where R: Reducer + 'static,
+              R::Key: Hash,
+              F: Fn(&[u8]) -> Result<R, BoxError> + Send + Sync + 'static
+    {
+        let factory: Factory<RegisteredReducer<R::Key, R::Value, R::Output>> = Box::new(move |config| factory(config).map(RegisteredReducer::new));
+        let build: BuildWorker = Box::new(|registry, spec| {
+            Master::<RegisteredMapper<R::Key, R::Value>, RegisteredReducer<R::Key, R::Value, R::Output>>::from_spec(registry, spec)
+                .map(|master| master.run_worker())
+        });
+        self.reducers.insert(name.to_string(), (Box::new(factory), build));
+        self
+    }
+
+    pub fn combiner<C, F>(mut self, name: &str, factory: F) -> Self
+        where C: Combiner + 'static,
+              C::Key: 'static,
+              C::Value: 'static,
+              F: Fn(&[u8]) -> Result<C, BoxError> + Send + Sync + 'static
+    {
+        let factory: Factory<Arc<dyn Combiner<Key = C::Key, Value = C::Value>>> = Box::new(move |config| {
+            factory(config).map(|combiner| Arc::new(combiner) as Arc<dyn Combiner<Key = C::Key, Value = C::Value>>)
+        });
+        self.combiners.insert(name.to_string(), Box::new(factory));
+        self
+    }
+
+    pub fn partitioner<K, P, F>(mut self, name: &str, factory: F) -> Self
+        where K: 'static,
+              P: Partitioner<K> + 'static,
+              F: Fn(&[u8]) -> Result<P, BoxError> + Send + Sync + 'static
+    {
+        let factory: Factory<Arc<dyn Partitioner<K>>> = Box::new(move |config| {
+            factory(config).map(|partitioner| Arc::new(partitioner) as Arc<dyn Partitioner<K>>)
+        });
+        self.partitioners.insert(name.to_string(), Box::new(factory));
+        self
+    }
+
+    pub fn input_format<I, F>(mut self, name: &str, factory: F) -> Self
+        where I: InputFormat + 'static,
+              F: Fn(&[u8]) -> Result<I, BoxError> + Send + Sync + 'static
+    {
+        self.input_formats.insert(name.to_string(), Box::new(move |config| {
+            factory(config).map(|format| Arc::new(format) as Arc<dyn InputFormat>)
+        }));
+        self
+    }
+
+    pub fn output_format<K, V, O, F>(mut self, name: &str, factory: F) -> Self
+        where K: 'static,
+              V: 'static,
+              O: OutputFormat<K, V> + 'static,
+              F: Fn(&[u8]) -> Result<O, BoxError> + Send + Sync + 'static
+    {
+        let factory: Factory<Arc<dyn OutputFormat<K, V>>> = Box::new(move |config| {
+            factory(config).map(|format| Arc::new(format) as Arc<dyn OutputFormat<K, V>>)
+        });
+        self.output_formats.insert(name.to_string(), Box::new(factory));
+        self
+    }
+
+    /// Builds the function an executor runs each worker with from the names
+    /// and settings in `spec`.
+    pub fn run_worker(&self, spec: &JobSpec) -> Result<RunWorker, MapReduceError> {
+        let named = &spec.functions.reducer;
+        match self.reducers.get(&named.name) {
+            Some((_, build)) => build(self, spec),
+            None => Err(unknown("reducer", named))
+        }
+    }
+
+    /// If this process is a worker launched by a `ProcessExecutor` for a
+    /// master built from a registry, serves that master's jobs with the
+    /// functions it names, looked up in this registry, and exits. Otherwise
+    /// returns straight away. Call it at the start of `main`, so that worker
+    /// processes skip whatever the master does before `Master::run`.
+    #[cfg(unix)]
+    pub fn serve_if_worker(&self) {
+        if let Some(id) = executor::worker_id() {
+            let code = match executor::serve(id, |spec| self.run_worker(&spec.ok_or_else(no_spec)?)) {
+                Ok(()) => 0,
+                Err(_) => 1
+            };
+            process::exit(code);
+        }
+    }
+
+    /// Worker processes are only launched on Unix, so this returns straight
+    /// away.
+    #[cfg(not(unix))]
+    pub fn serve_if_worker(&self) {}
+
+    /// Runs this process as a worker of the master listening at
+    /// `master_address` with a `TcpExecutor`, with the functions that master
+    /// names looked up in this registry. Map output is kept in
+    /// `working_directory`.
+    pub fn work_for<A: ToSocketAddrs>(&self, master_address: A, working_directory: &Path) -> Result<(), MapReduceError> {
+        network::work_for(master_address, working_directory, |spec| {
+            let spec = JobSpec { working_directory: working_directory.to_path_buf(), ..spec.ok_or_else(no_spec)? };
+            self.run_worker(&spec)
+        })
+    }
+
+    pub(crate) fn find_mapper<K: 'static, V: 'static>(&self, named: &Named) -> Result<RegisteredMapper<K, V>, MapReduceError> {
+        build("mapper", self.mappers.get(&named.name), named)
+    }
+
+    pub(crate) fn find_reducer<K: 'static, V: 'static, O: 'static>(&self, named: &Named) -> Result<RegisteredReducer<K, V, O>, MapReduceError> {
+        build("reducer", self.reducers.get(&named.name).map(|(entry, _)| entry), named)
+    }
+
+    pub(crate) fn find_combiner<K: 'static, V: 'static>(&self, named: &Named) -> Result<Arc<dyn Combiner<Key = K, Value = V>>, MapReduceError> {
+        build("combiner", self.combiners.get(&named.name), named)
+    }
+
+    pub(crate) fn find_partitioner<K: Hash + 'static>(&self, named: &Named) -> Result<Arc<dyn Partitioner<K>>, MapReduceError> {
+        match named.name.as_str() {
+            "hash" => Ok(Arc::new(HashPartitioner)),
+            _ => build("partitioner", self.partitioners.get(&named.name), named)
+        }
+    }
+
+    pub(crate) fn find_input_format(&self, named: &Named) -> Result<Arc<dyn InputFormat>, MapReduceError> {
+        let config = &named.config;
+        match named.name.as_str() {
+            "text_lines" => Ok(Arc::new(TextLines)),
+            "whole_file" => Ok(Arc::new(WholeFile)),
+            "delimited" if config.is_empty() => Err(rejected(named, "the delimiter is empty")),
+            "delimited" => Ok(Arc::new(Delimited::new(config))),
+            "fixed_length" => match u64::decode(config) {
+                Some(length) if length > 0 => Ok(Arc::new(FixedLength::new(length))),
+                _ => Err(rejected(named, "expected a non-zero big-endian u64 record length"))
+            },
+            _ => match self.input_formats.get(&named.name) {
+                Some(factory) => factory(config).map_err(|e| rejected(named, &e.to_string())),
+                None => Err(unknown("input format", named))
+            }
+        }
+    }
+
+    pub(crate) fn find_output_format<K: Datum, V: Datum>(&self, named: &Named) -> Result<Arc<dyn OutputFormat<K, V>>, MapReduceError> {
+        match named.name.as_str() {
+            "key_value_text" => Ok(Arc::new(KeyValueText)),
+            "tsv" => Ok(Arc::new(Tsv)),
+            "json_lines" => Ok(Arc::new(JsonLines)),
+            "length_prefixed" => Ok(Arc::new(LengthPrefixed)),
+            "plain_text" => Ok(Arc::new(PlainText)),
+            _ => build("output format", self.output_formats.get(&named.name), named)
+        }
+    }
+}
+
+/// Calls the factory registered for `named`, if it builds a `T`.
+fn build<T: 'static>(kind: &str, entry: Option<&Entry>, named: &Named) -> Result<T, MapReduceError> {
+    let factory = entry.ok_or_else(|| unknown(kind, named))?
+                       .downcast_ref::<Factory<T>>()
+                       .ok_or_else(|| rejected(named, &format!("the {} has different key, value or output types", kind)))?;
+    factory(&named.config).map_err(|e| rejected(named, &e.to_string()))
+}
+
+fn unknown(kind: &str, named: &Named) -> MapReduceError {
+    MapReduceError::InvalidFunction { name: named.name.clone(), reason: format!("no {} is registered under this name", kind) }
+}
+
+fn rejected(named: &Named, reason: &str) -> MapReduceError {
+    MapReduceError::InvalidFunction { name: named.name.clone(), reason: reason.to_string() }
+}
+
+fn no_spec() -> MapReduceError {
+    MapReduceError::InvalidFunction { name: String::new(), reason: "the master did not name its functions".to_string() }
+}
+
+type MapFunction<K, V> = dyn Fn(&mut dyn Iterator<Item = Record>, &mut Emitter<K, V>) -> Result<(), BoxError> + Send + Sync;
+type ReduceFunction<K, V, O> = dyn Fn(&K, &mut dyn Iterator<Item = V>) -> Result<O, BoxError> + Send + Sync;
+
+/// A mapper looked up in a `Registry`. Its errors are kept as messages.
+pub struct RegisteredMapper<K, V> {
+    map: Arc<MapFunction<K, V>>
+}
+
+impl<K, V> RegisteredMapper<K, V> {
+    fn new<M: Mapper<Key = K, Value = V> + 'static>(mapper: M) -> Self {
+        RegisteredMapper {
+            map: Arc::new(move |records, output| mapper.map(records, output).map_err(|e| BoxError::from(e.to_string())))
+        }
+    }
+}
+
+impl<K: Datum + Ord + Hash, V: Datum> Mapper for RegisteredMapper<K, V> {
+    type Key = K;
+    type Value = V;
+    type Error = ClosureError;
+
+    fn map(&self, records: &mut dyn Iterator<Item = Record>, output: &mut Emitter<K, V>) -> Result<(), ClosureError> {
+        (self.map)(records, output).map_err(ClosureError)
+    }
+}
+
+/// A reducer looked up in a `Registry`. Its errors are kept as messages.
+pub struct RegisteredReducer<K, V, O> {
+    reduce: Arc<ReduceFunction<K, V, O>>
+}
+
+impl<K, V, O> RegisteredReducer<K, V, O> {
+    fn new<R: Reducer<Key = K, Value = V, Output = O> + 'static>(reducer: R) -> Self {
+        RegisteredReducer {
+            reduce: Arc::new(move |key, values| reducer.reduce(key, values).map_err(|e| BoxError::from(e.to_string())))
+        }
+    }
+}
+
+impl<K: Datum + Ord, V: Datum, O: Datum> Reducer for RegisteredReducer<K, V, O> {
+    type Key = K;
+    type Value = V;
+    type Output = O;
+    type Error = ClosureError;
+
+    fn reduce(&self, key: &K, values: &mut dyn Iterator<Item = V>) -> Result<O, ClosureError> {
+        (self.reduce)(key, values).map_err(ClosureError)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::Infallible;
+    use std::path::PathBuf;
+
+    use datum::Datum;
+    use error::MapReduceError;
+    use functions::{ Mapper, Reducer, Emitter, BoxError };
+    use input::Record;
+    use master::RegisteredMaster;
+    use super::{ Registry, Functions, Named };
+
+    /// Emits every record `times` times.
+    struct Repeat {
+        times: u64
+    }
+
+    impl Mapper for Repeat {
+        type Key = String;
+        type Value = u64;
+        type Error = Infallible;
+
+        fn map(&self, records: &mut dyn Iterator<Item = Record>, output: &mut Emitter<String, u64>) -> Result<(), Infallible> {
+            for record in records {
+                output.emit(String::from_utf8_lossy(&record.bytes).into_owned(), self.times);
+            }
+            Ok(())
+        }
+    }
+
+    struct Sum;
+
+    impl Reducer for Sum {
+        type Key = String;
+        type Value = u64;
+        type Output = u64;
+        type Error = Infallible;
+
+        fn reduce(&self, _key: &String, values: &mut dyn Iterator<Item = u64>) -> Result<u64, Infallible> {
+            Ok(values.sum())
+        }
+    }
+
+    fn registry() -> Registry {
+        Registry::new()
+            .mapper("repeat", |config: &[u8]| {
+                u64::decode(config).map(|times| Repeat { times })
+                                   .ok_or_else(|| BoxError::from("expected a big-endian u64"))
+            })
+            .reducer("sum", |_: &[u8]| Ok(Sum))
+    }
+
+    fn master(functions: Functions) -> Result<RegisteredMaster<String, u64, u64>, MapReduceError> {
+        RegisteredMaster::from_registry(&registry(), PathBuf::from("."), vec![], functions, 1)
+    }
+
+    fn invalid(name: &str, reason: &str) -> MapReduceError {
+        MapReduceError::InvalidFunction { name: name.to_string(), reason: reason.to_string() }
+    }
+
+    #[test]
+    fn functions_are_built_from_their_configuration() {
+        let repeat = registry().find_mapper::<String, u64>(&Named::new("repeat").with_config(3u64.to_be_bytes().to_vec()))
+                               .unwrap();
+        let mut emitted = vec![];
+        let mut records = vec![Record { offset: 0, bytes: b"ulla".to_vec() }].into_iter();
+        repeat.map(&mut records, &mut Emitter::new(&mut |key, value| emitted.push((key, value)))).unwrap();
+        assert_eq!(emitted, vec![("ulla".to_string(), 3)]);
+
+        let error = master(Functions::new(Named::new("repeat"), Named::new("sum"))).err();
+        assert_eq!(error, Some(invalid("repeat", "expected a big-endian u64")));
+        let error = registry().find_input_format(&Named::new("fixed_length").with_config(vec![1])).err();
+        assert_eq!(error.map(|e| e.to_string()),
+                   Some("cannot use function fixed_length: expected a non-zero big-endian u64 record length".to_string()));
+    }
+
+    #[test]
+    fn unknown_names_are_rejected() {
+        let repeat = Named::new("repeat").with_config(1u64.to_be_bytes().to_vec());
+        let error = master(Functions::new(repeat.clone(), Named::new("count"))).err();
+        assert_eq!(error, Some(invalid("count", "no reducer is registered under this name")));
+        let error = master(Functions::new(repeat, Named::new("sum")).with_output_format(Named::new("parquet"))).err();
+        assert_eq!(error, Some(invalid("parquet", "no output format is registered under this name")));
+    }
+
+    #[test]
+    fn functions_must_have_the_types_of_the_master() {
+        let error = registry().find_reducer::<String, u64, String>(&Named::new("sum")).err();
+        assert_eq!(error, Some(invalid("sum", "the reducer has different key, value or output types")));
+    }
+}
@@ -0,0 +1,267 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::io;
+use std::iter;
+use std::vec;
+
+use functions::Combiner;
+
+/// Sorts one map output partition by key. The sort is stable, so values for
+/// the same key keep the order they were emitted in.
+pub fn sort_run<K: Ord, V>(pairs: &mut [(K, V)]) {
+    pairs.sort_by(|fst, snd| fst.0.cmp(&snd.0));
+}
+
+/// Collapses each key of a sorted run into the single value returned by the
+/// combiner.
+pub fn combine_run<K: Ord, V>(run: Vec<(K, V)>, combiner: &dyn Combiner<Key = K, Value = V>) -> Vec<(K, V)> {
+    Merge::new(vec![run]).group()
+                         .map(|(key, values)| {
+                             let value = combiner.combine(&key, &mut values.into_iter());
+                             (key, value)
+                         })
+                         .collect()
+}
+
+struct Head<K, V> {
+    key: K,
+    value: V,
+    run: usize
+}
+
+// BinaryHeap is a max-heap, so order heads in reverse to pop the smallest key
+// first; ties go to the earlier run to keep the merge stable.
+impl<K: Ord, V> Ord for Head<K, V> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.key.cmp(&self.key)
+                 .then_with(|| other.run.cmp(&self.run))
+    }
+}
+
+impl<K: Ord, V> PartialOrd for Head<K, V> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K: Ord, V> PartialEq for Head<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<K: Ord, V> Eq for Head<K, V> {}
+
+/// An in-memory sorted run, as merged by `Merge::new`.
+pub type Sorted<K, V> = iter::Map<vec::IntoIter<(K, V)>, fn((K, V)) -> io::Result<(K, V)>>;
+
+/// K-way merge of sorted runs into a single sorted stream of pairs. Runs read
+/// from disk can fail; the merge stops at the first error, which `take_error`
+/// then returns.
+pub struct Merge<K, V, R = Sorted<K, V>> {
+    runs: Vec<R>,
+    heads: BinaryHeap<Head<K, V>>,
+    error: Option<(usize, io::Error)>
+}
+
+impl<K: Ord, V> Merge<K, V> {
+    pub fn new(runs: Vec<Vec<(K, V)>>) -> Self {
+        Merge::streaming(runs.into_iter()
+                             .map(|run| run.into_iter().map(Ok as fn((K, V)) -> io::Result<(K, V)>))
+                             .collect())
+    }
+}
+
+impl<K: Ord, V, R: Iterator<Item = io::Result<(K, V)>>> Merge<K, V, R> {
+    /// Merges runs that are read as the merge goes, holding only the head of
+    /// each run in memory.
+    pub fn streaming(runs: Vec<R>) -> Self {
+        let mut merge = Merge {
+            runs,
+            heads: BinaryHeap::new(),
+            error: None
+        };
+        for run in 0..merge.runs.len() {
+            merge.advance(run);
+        }
+        merge
+    }
+
+    fn advance(&mut self, run: usize) {
+        match self.runs[run].next() {
+            Some(Ok((key, value))) => self.heads.push(Head { key, value, run }),
+            Some(Err(e)) => {
+                self.error.get_or_insert((run, e));
+            }
+            None => {}
+        }
+    }
+
+    /// The error that ended the merge early, if any, and the index of the
+    /// run it came from.
+    pub fn take_error(&mut self) -> Option<(usize, io::Error)> {
+        self.error.take()
+    }
+
+    /// Takes the next pair's value if the pair has `key`.
+    fn next_value(&mut self, key: &K) -> Option<V> {
+        if self.error.is_none() && self.heads.peek().is_some_and(|head| head.key == *key) {
+            self.next().map(|(_, value)| value)
+        } else {
+            None
+        }
+    }
+
+    /// Calls `reduce` once per key, in key order, with an iterator that reads
+    /// the key's values from the runs as it goes, so that only the head of
+    /// each run is held in memory. Values `reduce` leaves unread are skipped.
+    /// Stops at the first error `reduce` returns.
+    pub fn for_each_group<E, F>(&mut self, mut reduce: F) -> Result<(), E>
+        where F: FnMut(&K, &mut dyn Iterator<Item = V>) -> Result<(), E>
+    {
+        while let Some((key, value)) = self.next() {
+            let mut values = Values { key: &key, first: Some(value), merge: self };
+            reduce(&key, &mut values)?;
+            values.for_each(drop);
+        }
+        Ok(())
+    }
+
+    /// Groups the merged stream into one entry per key holding every value
+    /// for that key.
+    pub fn group(self) -> Groups<K, V, R> {
+        Groups { merge: self }
+    }
+}
+
+impl<K: Ord, V, R: Iterator<Item = io::Result<(K, V)>>> Iterator for Merge<K, V, R> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        if self.error.is_some() {
+            return None;
+        }
+        self.heads.pop().map(|head| {
+            self.advance(head.run);
+            (head.key, head.value)
+        })
+    }
+}
+
+pub struct Groups<K, V, R = Sorted<K, V>> {
+    merge: Merge<K, V, R>
+}
+
+impl<K: Ord, V, R: Iterator<Item = io::Result<(K, V)>>> Groups<K, V, R> {
+    /// The error that ended the merge early, if any, and the index of the
+    /// run it came from.
+    pub fn take_error(&mut self) -> Option<(usize, io::Error)> {
+        self.merge.take_error()
+    }
+}
+
+impl<K: Ord, V, R: Iterator<Item = io::Result<(K, V)>>> Iterator for Groups<K, V, R> {
+    type Item = (K, Vec<V>);
+
+    fn next(&mut self) -> Option<(K, Vec<V>)> {
+        self.merge.next().map(|(key, value)| {
+            let mut values = vec![value];
+            while let Some(value) = self.merge.next_value(&key) {
+                values.push(value);
+            }
+            (key, values)
+        })
+    }
+}
+
+/// The values of one key, read from a merge by `for_each_group`.
+struct Values<'a, K: 'a, V: 'a, R: 'a> {
+    key: &'a K,
+    first: Option<V>,
+    merge: &'a mut Merge<K, V, R>
+}
+
+impl<'a, K: Ord, V, R: Iterator<Item = io::Result<(K, V)>>> Iterator for Values<'a, K, V, R> {
+    type Item = V;
+
+    fn next(&mut self) -> Option<V> {
+        self.first.take().or_else(|| self.merge.next_value(self.key))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use functions::Combiner;
+    use super::{ sort_run, combine_run, Merge };
+
+    struct Sum;
+
+    impl Combiner for Sum {
+        type Key = &'static str;
+        type Value = i32;
+
+        fn combine(&self, _key: &&'static str, values: &mut dyn Iterator<Item = i32>) -> i32 {
+            values.sum()
+        }
+    }
+
+    #[test]
+    fn merges_sorted_runs_in_key_order() {
+        let mut first = vec![("tripod", 1), ("cylinder", 2), ("martian", 3)];
+        let mut second = vec![("martian", 4), ("artilleryman", 5)];
+        sort_run(&mut first);
+        sort_run(&mut second);
+
+        let merged = Merge::new(vec![first, second, vec![]]).collect::<Vec<(&str, i32)>>();
+        assert_eq!(merged, vec![("artilleryman", 5),
+                                ("cylinder", 2),
+                                ("martian", 3),
+                                ("martian", 4),
+                                ("tripod", 1)]);
+    }
+
+    #[test]
+    fn groups_every_value_for_a_key() {
+        let runs = vec![vec![("a", 1), ("a", 2), ("c", 3)],
+                        vec![("a", 4), ("b", 5)],
+                        vec![("c", 6)]];
+
+        let groups = Merge::new(runs).group().collect::<Vec<(&str, Vec<i32>)>>();
+        assert_eq!(groups, vec![("a", vec![1, 2, 4]),
+                                ("b", vec![5]),
+                                ("c", vec![3, 6])]);
+    }
+
+    #[test]
+    fn streams_each_keys_values_and_stops_at_a_failed_run() {
+        let runs = vec![vec![("a", 1), ("a", 2), ("c", 3)],
+                        vec![("a", 4), ("b", 5)]];
+        let mut groups = vec![];
+        Merge::new(runs).for_each_group(|key, values| {
+                            // Read only the first value; the rest must be skipped.
+                            groups.push((*key, values.next()));
+                            Ok::<(), ()>(())
+                        })
+                        .unwrap();
+        assert_eq!(groups, vec![("a", Some(1)), ("b", Some(5)), ("c", Some(3))]);
+
+        let failing = vec![Ok(("a", 1)), Err(io::Error::new(io::ErrorKind::InvalidData, "bad block"))];
+        let mut merge = Merge::streaming(vec![vec![Ok(("a", 2)), Ok(("b", 3))].into_iter(), failing.into_iter()]);
+        let mut sums = vec![];
+        merge.for_each_group(|key, values| {
+                 sums.push((*key, values.sum::<i32>()));
+                 Ok::<(), ()>(())
+             })
+             .unwrap();
+        assert_eq!(sums, vec![("a", 3)]);
+        assert_eq!(merge.take_error().map(|(run, e)| (run, e.kind())), Some((1, io::ErrorKind::InvalidData)));
+    }
+
+    #[test]
+    fn combines_each_key_of_a_run() {
+        let run = vec![("a", 1), ("a", 2), ("b", 5), ("c", 3), ("c", 6)];
+
+        assert_eq!(combine_run(run, &Sum), vec![("a", 3), ("b", 5), ("c", 9)]);
+    }
+}
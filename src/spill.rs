@@ -0,0 +1,226 @@
+use std::fs::{ File, remove_file };
+use std::io;
+use std::io::{ BufReader, BufWriter, Write };
+use std::mem;
+use std::path::{ Path, PathBuf };
+
+use compression::Codec;
+use datum::Datum;
+use functions::Combiner;
+use intermediate::{ RunReader, write_run };
+use partitioner::Partitioner;
+use shuffle::{ sort_run, combine_run, Merge };
+
+/// A run merged into a map output: a spill read back from disk, or the pairs
+/// still in memory.
+type Run<K, V> = Box<dyn Iterator<Item = io::Result<(K, V)>>>;
+
+/// Collects a map task's output by partition. Once the buffered pairs take up
+/// more than `budget` bytes, every partition is sorted, combined and spilled
+/// to disk as a sorted run. `finish` merges the spills with what is left in
+/// memory into one run per partition, combining again on the way. A spill
+/// that cannot be written is reported by `finish`, and the pairs pushed after
+/// it are dropped.
+pub struct SpillBuffer<'a, K, V> {
+    partitioner: &'a dyn Partitioner<K>,
+    combiner: Option<&'a dyn Combiner<Key = K, Value = V>>,
+    budget: usize,
+    codec: Codec,
+    spill_name: &'a dyn Fn(usize, usize) -> PathBuf,
+    partitions: Vec<Vec<(K, V)>>,
+    buffered: usize,
+    spills: Vec<Vec<PathBuf>>,
+    error: Option<(PathBuf, io::Error)>
+}
+
+impl<'a, K: Datum + Ord, V: Datum> SpillBuffer<'a, K, V> {
+    /// `spill_name(spill, partition)` names the file a spill writes a
+    /// partition's run to.
+    pub fn new(partitioner: &'a dyn Partitioner<K>,
+               combiner: Option<&'a dyn Combiner<Key = K, Value = V>>,
+               n_reduce: usize,
+               budget: usize,
+               codec: Codec,
+               spill_name: &'a dyn Fn(usize, usize) -> PathBuf
+               ) -> Self
+    {
+        SpillBuffer {
+            partitioner,
+            combiner,
+            budget,
+            codec,
+            spill_name,
+            partitions: (0..n_reduce).map(|_| vec![]).collect(),
+            buffered: 0,
+            spills: vec![],
+            error: None
+        }
+    }
+
+    pub fn push(&mut self, key: K, value: V) {
+        if self.error.is_some() {
+            return;
+        }
+        let partition = self.partitioner.partition(&key, self.partitions.len());
+        self.buffered += mem::size_of::<(K, V)>() + key.encoded_len() + value.encoded_len();
+        self.partitions[partition].push((key, value));
+        if self.buffered > self.budget {
+            self.error = self.spill().err();
+        }
+    }
+
+    /// Takes a partition's buffered pairs, sorted and combined.
+    fn drain(&mut self, partition: usize) -> Vec<(K, V)> {
+        let mut pairs = mem::take(&mut self.partitions[partition]);
+        sort_run(&mut pairs);
+        match self.combiner {
+            Some(combiner) => combine_run(pairs, combiner),
+            None => pairs
+        }
+    }
+
+    fn spill(&mut self) -> Result<(), (PathBuf, io::Error)> {
+        let spill = self.spills.len();
+        self.spills.push(vec![]);
+        for partition in 0..self.partitions.len() {
+            let pairs = self.drain(partition);
+            let path = (self.spill_name)(spill, partition);
+            self.spills[spill].push(path.clone());
+            write_run_file(&path, pairs.into_iter(), self.codec).map_err(|e| (path, e))?;
+        }
+        self.buffered = 0;
+        Ok(())
+    }
+
+    /// Writes each partition's output to `output_name(partition)` and removes
+    /// the spills. Returns the files written, in partition order.
+    pub fn finish(mut self, output_name: &dyn Fn(usize) -> PathBuf) -> Result<Vec<PathBuf>, (PathBuf, io::Error)> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        let mut outputs = vec![];
+        for partition in 0..self.partitions.len() {
+            let pairs = self.drain(partition);
+            let path = output_name(partition);
+            let written = if self.spills.is_empty() {
+                write_run_file(&path, pairs.into_iter(), self.codec)
+            } else {
+                self.merge_spills(partition, pairs, &path)
+            };
+            written.map_err(|e| (path.clone(), e))?;
+            outputs.push(path);
+        }
+        Ok(outputs)
+    }
+
+    fn merge_spills(&self, partition: usize, buffered: Vec<(K, V)>, path: &Path) -> io::Result<()> {
+        let mut runs = vec![];
+        for spill in self.spills.iter() {
+            let spill_path = &spill[partition];
+            let reader = File::open(spill_path).and_then(|f| RunReader::<K, V, _>::new(BufReader::new(f)))
+                                                .map_err(|e| in_spill(spill_path, e))?;
+            runs.push(Box::new(reader) as Run<K, V>);
+        }
+        runs.push(Box::new(buffered.into_iter().map(Ok)));
+
+        let mut merge = Merge::streaming(runs);
+        let error = match self.combiner {
+            Some(combiner) => {
+                let mut groups = merge.group();
+                write_run_file(path,
+                               groups.by_ref().map(|(key, values)| {
+                                   let value = combiner.combine(&key, &mut values.into_iter());
+                                   (key, value)
+                               }),
+                               self.codec)?;
+                groups.take_error()
+            }
+            None => {
+                write_run_file(path, merge.by_ref(), self.codec)?;
+                merge.take_error()
+            }
+        };
+        error.map_or(Ok(()), |(spill, e)| Err(in_spill(&self.spills[spill][partition], e)))
+    }
+}
+
+impl<'a, K, V> Drop for SpillBuffer<'a, K, V> {
+    fn drop(&mut self) {
+        for path in self.spills.iter().flatten() {
+            let _ = remove_file(path);
+        }
+    }
+}
+
+fn write_run_file<K, V, I>(path: &Path, pairs: I, codec: Codec) -> io::Result<()>
+    where K: Datum, V: Datum, I: Iterator<Item = (K, V)>
+{
+    write_run(BufWriter::new(File::create(path)?), pairs, codec)?.flush()
+}
+
+fn in_spill(path: &Path, e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), format!("spill {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::fs::{ File, create_dir_all, read_dir, remove_dir_all };
+    use std::path::PathBuf;
+
+    use compression::Codec;
+    use functions::Combiner;
+    use intermediate::read_run;
+    use partitioner::HashPartitioner;
+    use super::SpillBuffer;
+
+    struct Sum;
+
+    impl Combiner for Sum {
+        type Key = String;
+        type Value = u64;
+
+        fn combine(&self, _key: &String, values: &mut dyn Iterator<Item = u64>) -> u64 {
+            values.sum()
+        }
+    }
+
+    #[test]
+    fn spills_merge_into_the_same_output_as_memory() {
+        let working_directory = PathBuf::from("./test-data/spills_merge_into_the_same_output_as_memory");
+        let _ = remove_dir_all(&working_directory);
+        create_dir_all(&working_directory).unwrap();
+        let spill_name = |spill: usize, partition: usize| working_directory.join(format!("spill.{}.{}", spill, partition));
+        let pairs = (0..5_000u64).map(|i| (format!("martian-{}", i % 300), i))
+                                 .collect::<Vec<(String, u64)>>();
+
+        for combiner in [None, Some(&Sum as &dyn Combiner<Key = String, Value = u64>)] {
+            let mut buffer = SpillBuffer::new(&HashPartitioner, combiner, 3, 4 * 1024, Codec::None, &spill_name);
+            for (key, value) in pairs.iter() {
+                buffer.push(key.clone(), *value);
+            }
+            assert!(buffer.spills.len() > 1);
+            buffer.finish(&|partition| working_directory.join(format!("output.{}", partition))).unwrap();
+
+            let mut expected = BTreeMap::new();
+            for (key, value) in pairs.iter() {
+                expected.entry(key.clone()).or_insert_with(Vec::new).push(*value);
+            }
+            let mut output = BTreeMap::new();
+            for partition in 0..3 {
+                let path = working_directory.join(format!("output.{}", partition));
+                let run = read_run::<String, u64, _>(&mut File::open(path).unwrap()).unwrap();
+                assert!(run.windows(2).all(|w| w[0].0 <= w[1].0));
+                for (key, value) in run {
+                    output.entry(key).or_insert_with(Vec::new).push(value);
+                }
+            }
+            if combiner.is_some() {
+                expected = expected.into_iter().map(|(key, values)| (key, vec![values.iter().sum()])).collect();
+            }
+            assert_eq!(output, expected);
+            assert_eq!(read_dir(&working_directory).unwrap().count(), 3, "spills were not removed");
+        }
+        remove_dir_all(&working_directory).unwrap();
+    }
+}
@@ -0,0 +1,64 @@
+use std::path::{ Path, PathBuf };
+
+/// A byte range of an input file handed to one map task. Which records of
+/// the range the task reads is up to the input's `InputFormat`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputSplit {
+    pub path: PathBuf,
+    pub offset: u64,
+    pub length: u64
+}
+
+impl InputSplit {
+    /// A split covering the whole of `path`.
+    pub fn whole(path: PathBuf) -> Self {
+        InputSplit { path, offset: 0, length: u64::MAX }
+    }
+
+    /// Cuts `path` into splits of `split_size` bytes, or a single split if
+    /// its size cannot be read.
+    pub fn split(path: &Path, split_size: u64) -> Vec<InputSplit> {
+        let size = match path.metadata() {
+            Ok(metadata) => metadata.len(),
+            Err(_) => return vec![InputSplit::whole(path.to_path_buf())]
+        };
+        let split_size = split_size.max(1);
+        let n_splits = size.div_ceil(split_size).max(1);
+        (0..n_splits).map(|i| {
+                         InputSplit {
+                             path: path.to_path_buf(),
+                             offset: i * split_size,
+                             length: split_size
+                         }
+                     })
+                     .collect()
+    }
+
+    /// The offset just past the end of the split.
+    pub fn end(&self) -> u64 {
+        self.offset.saturating_add(self.length)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{ create_dir_all, remove_dir_all, write };
+    use std::path::PathBuf;
+    use super::InputSplit;
+
+    #[test]
+    fn splits_cover_the_whole_file() {
+        let working_directory = PathBuf::from("./test-data/split_covers_the_whole_file");
+        create_dir_all(&working_directory).unwrap();
+        let input = working_directory.join("input");
+        write(&input, "the heat ray\nthe tripods\n").unwrap();
+
+        let splits = InputSplit::split(&input, 10);
+
+        assert_eq!(splits.iter().map(|split| (split.offset, split.end())).collect::<Vec<_>>(),
+                   vec![(0, 10), (10, 20), (20, 30)]);
+        assert_eq!(InputSplit::split(&working_directory.join("missing"), 10),
+                   vec![InputSplit::whole(working_directory.join("missing"))]);
+        remove_dir_all(&working_directory).unwrap();
+    }
+}
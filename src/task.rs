@@ -0,0 +1,17 @@
+use std::fmt;
+
+/// Identifies a map or reduce task by its job id, independent of attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Task {
+    Map(i32),
+    Reduce(i32)
+}
+
+impl fmt::Display for Task {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Task::Map(id) => write!(f, "map task {}", id),
+            Task::Reduce(id) => write!(f, "reduce task {}", id)
+        }
+    }
+}
@@ -1,114 +1,510 @@
+use std::any::Any;
 use std::io::BufReader;
 use std::fs::{ File, OpenOptions };
-use std::io::Write;
-use std::path::PathBuf;
+use std::io;
+use std::iter;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::io::{ BufWriter, Write };
+use std::path::{ Path, PathBuf };
 use std::sync::Arc;
 
 use chan::{ Sender, Receiver };
 
-#[derive(Debug, PartialEq, Eq)]
+use compression::Codec;
+use datum::Datum;
+use error::MapReduceError;
+use functions::{ Mapper, Reducer, Combiner, Emitter };
+use partitioner::Partitioner;
+use shuffle::Merge;
+use spill::SpillBuffer;
+use input::{ InputFormat, InputFormats, Records, TextLines };
+use intermediate::{ RunReader, is_corrupt };
+use output::OutputFormat;
+use split::InputSplit;
+use task::Task;
+
+/// A map or reduce job: the job id, the attempt number and the input split or
+/// files. A map job's skip list is `None` unless bad-record skipping is on, in which
+/// case it holds the offsets of the records to leave out.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Job {
-    Map((i32, PathBuf)),
-    Reduce((i32, Vec<PathBuf>))
+    Map((i32, u32, InputSplit, Option<Vec<u64>>)),
+    Reduce((i32, u32, Vec<PathBuf>))
 }
 
+impl Job {
+    pub fn task(&self) -> Task {
+        match *self {
+            Job::Map((job_id, _, _, _)) => Task::Map(job_id),
+            Job::Reduce((job_id, _, _)) => Task::Reduce(job_id)
+        }
+    }
+
+    pub fn attempt(&self) -> u32 {
+        match *self {
+            Job::Map((_, attempt, _, _)) | Job::Reduce((_, attempt, _)) => attempt
+        }
+    }
+
+    pub fn with_attempt(&self, attempt: u32) -> Job {
+        match *self {
+            Job::Map((job_id, _, ref split, ref skipped)) => Job::Map((job_id, attempt, split.clone(), skipped.clone())),
+            Job::Reduce((job_id, _, ref paths)) => Job::Reduce((job_id, attempt, paths.clone()))
+        }
+    }
+
+    /// Whether this is a map job with records to skip.
+    pub fn skips_records(&self) -> bool {
+        match *self {
+            Job::Map((_, _, _, Some(ref skipped))) => !skipped.is_empty(),
+            _ => false
+        }
+    }
+
+    /// Adds a record to a map job's skip list, if it has one.
+    pub fn skip_record(&mut self, offset: u64) {
+        if let Job::Map((_, _, _, Some(ref mut skipped))) = *self {
+            if !skipped.contains(&offset) {
+                skipped.push(offset);
+                skipped.sort();
+            }
+        }
+    }
+}
+
+/// Reported by a worker for each attempt it runs; every attempt is `Started`
+/// and then either finishes or fails. A finished map attempt lists the file it
+/// wrote for each partition, in partition order, and a finished reduce
+/// attempt its result file. `FunctionFailed` carries the message of
+/// an error returned by the map or reduce function, `RecordFailed` the offset
+/// of the record a map job with a skip list panicked on, `CorruptInput` the map
+/// output a reduce job found corrupt, and `Failed` everything else. Executors
+/// also send a `Heartbeat` for each live worker every `HEARTBEAT_INTERVAL`.
 #[derive(Debug, PartialEq, Eq)]
 pub enum JobResult {
-    MapFinished(i32),
-    ReduceFinished(i32)
+    Started(usize, Task, u32),
+    Heartbeat(usize),
+    MapFinished(i32, u32, Vec<PathBuf>),
+    ReduceFinished(i32, u32, PathBuf),
+    FunctionFailed(Task, u32, String),
+    RecordFailed(Task, u32, u64, MapReduceError),
+    CorruptInput(Task, u32, PathBuf, MapReduceError),
+    Failed(Task, u32, MapReduceError)
+}
+
+pub fn map_output_name(working_directory: &Path, job_id: i32, partition: usize) -> PathBuf {
+    working_directory.join(format!("map.{}.reduce.{}", job_id, partition + 1))
+}
+
+/// Where an attempt writes a map output until the master commits it.
+pub fn map_attempt_name(working_directory: &Path, job_id: i32, attempt: u32, partition: usize) -> PathBuf {
+    working_directory.join(format!("map.{}.reduce.{}.attempt.{}.tmp", job_id, partition + 1, attempt))
+}
+
+/// Where an attempt spills a partition's buffered map output when it runs
+/// out of memory.
+pub fn map_spill_name(working_directory: &Path, job_id: i32, attempt: u32, spill: usize, partition: usize) -> PathBuf {
+    working_directory.join(format!("map.{}.reduce.{}.attempt.{}.spill.{}.tmp", job_id, partition + 1, attempt, spill))
+}
+
+/// Where the records skipped by a map job are written.
+pub fn quarantine_name(working_directory: &Path, job_id: i32) -> PathBuf {
+    working_directory.join(format!("map.{}.quarantine", job_id))
+}
+
+/// Where an attempt writes its skipped records until the master commits them.
+pub fn quarantine_attempt_name(working_directory: &Path, job_id: i32, attempt: u32) -> PathBuf {
+    working_directory.join(format!("map.{}.quarantine.attempt.{}.tmp", job_id, attempt))
+}
+
+/// Where an attempt writes its reduce output until the master commits it.
+pub fn reduce_attempt_name(working_directory: &Path, job_id: i32, attempt: u32) -> PathBuf {
+    working_directory.join(format!("reduce.{}.attempt.{}.tmp", job_id, attempt))
 }
 
-pub struct Worker {
+pub struct Worker<M: Mapper, R: Reducer> {
+    pub id: usize,
     pub working_directory: PathBuf,
-    pub map: Arc<Fn(BufReader<File>) -> Vec<String> + Send + Sync>,
-    pub reduce: Arc<Fn(Vec<BufReader<File>>) -> String + Send + Sync>,
+    pub mapper: Arc<M>,
+    pub reducer: Arc<R>,
+    pub partitioner: Arc<dyn Partitioner<M::Key>>,
+    pub combiner: Option<Arc<dyn Combiner<Key = M::Key, Value = M::Value>>>,
+    pub n_reduce: usize,
+    pub map_buffer_size: usize,
+    pub input_formats: Arc<InputFormats>,
+    pub output_format: Arc<dyn OutputFormat<M::Key, R::Output>>,
+    pub intermediate_codec: Codec,
+    pub output_codec: Codec,
     pub job_queue: Receiver<Job>,
     pub results_queue: Sender<JobResult>
 }
 
-impl Worker {
+impl<M, R> Worker<M, R>
+    where M: Mapper,
+          R: Reducer<Key = M::Key, Value = M::Value>
+{
     pub fn run(&self) {
         for job in self.job_queue.iter() {
-            match job {
-                Job::Map((job_id, path)) => {
-                    let results: Vec<String> = (self.map)(open_file(path));
-                    let names = self.map_result_names(&job_id, results.iter().len());
-                    self.write_map_results(names, results);
-                    self.results_queue.send(JobResult::MapFinished(job_id));
+            let task = job.task();
+            let attempt = job.attempt();
+            self.results_queue.send(JobResult::Started(self.id, task, attempt));
+            let result = panic::catch_unwind(AssertUnwindSafe(|| self.execute(job)))
+                              .unwrap_or_else(|cause| {
+                                  Err(MapReduceError::Panicked { task, attempt, message: panic_message(cause) })
+                              })
+                              .unwrap_or_else(|error| JobResult::Failed(task, attempt, error));
+            self.results_queue.send(result);
+        }
+    }
+
+    fn execute(&self, job: Job) -> Result<JobResult, MapReduceError> {
+        let task = job.task();
+        let attempt = job.attempt();
+        let read_error = |path: &PathBuf, e: io::Error| {
+            MapReduceError::ReadInput { task, attempt, path: path.clone(), reason: e.to_string() }
+        };
+        match job {
+            Job::Map((job_id, attempt, split, Some(skipped))) => {
+                let spill_name = |spill, partition| {
+                    map_spill_name(&self.working_directory, job_id, attempt, spill, partition)
+                };
+                let mut output = self.spill_buffer(&spill_name);
+                if let Err(failure) = self.map_records(job_id, attempt, &split, &skipped, &mut output) {
+                    return Ok(failure);
+                }
+                let outputs = self.write_map_results(job_id, attempt, output)?;
+                Ok(JobResult::MapFinished(job_id, attempt, outputs))
+            }
+            Job::Map((job_id, attempt, split, None)) => {
+                let mut records = self.records(&split).map_err(|e| read_error(&split.path, e))?;
+                let spill_name = |spill, partition| {
+                    map_spill_name(&self.working_directory, job_id, attempt, spill, partition)
+                };
+                let mut output = self.spill_buffer(&spill_name);
+                let mut read_failure = None;
+                let mapped = self.mapper.map(&mut records.by_ref().map_while(|record| {
+                                                 record.map_err(|e| read_failure = Some(e)).ok()
+                                             }),
+                                             &mut Emitter::new(&mut |key, value| output.push(key, value)));
+                if let Some(e) = read_failure {
+                    return Err(read_error(&split.path, e));
                 }
-                Job::Reduce((job_id, paths)) => {
-                    let files = paths.into_iter()
-                                     .map(|path| open_file(path))
-                                     .collect::<Vec<BufReader<File>>>();
-                    let result: String = (self.reduce)(files);
-                    let name = self.reduce_result_name(&job_id);
-                    self.write_reduce_results(name, result);
-                    self.results_queue.send(JobResult::ReduceFinished(job_id));
+                if let Err(e) = mapped {
+                    return Ok(JobResult::FunctionFailed(task, attempt, e.to_string()));
+                }
+                let outputs = self.write_map_results(job_id, attempt, output)?;
+                Ok(JobResult::MapFinished(job_id, attempt, outputs))
+            }
+            Job::Reduce((job_id, attempt, paths)) => {
+                match self.reduce_partition(job_id, attempt, &paths) {
+                    Ok(output) => Ok(JobResult::ReduceFinished(job_id, attempt, output)),
+                    Err(failure) => Ok(failure)
                 }
             }
         }
     }
 
-    fn map_result_names(&self, job_id: &i32, length: usize) -> Vec<PathBuf> {
-        (1..length + 1).map(|i| {
-                       let mut path = self.working_directory.clone();
-                       path.push(format!("map.{}.reduce.{}", job_id, i));
-                       path
-                   })
-                   .collect::<Vec<PathBuf>>()
+    /// Maps the input one record at a time, so that a panic can be pinned on
+    /// the record being mapped. Records in `skipped` are written to the
+    /// attempt's quarantine file instead of being mapped. Any failure is
+    /// returned as the result to report.
+    fn map_records(&self,
+                   job_id: i32,
+                   attempt: u32,
+                   split: &InputSplit,
+                   skipped: &[u64],
+                   output: &mut SpillBuffer<M::Key, M::Value>
+                   ) -> Result<(), JobResult>
+    {
+        let task = Task::Map(job_id);
+        let read_error = |path: &Path, e: io::Error| {
+            let reason = e.to_string();
+            JobResult::Failed(task, attempt, MapReduceError::ReadInput { task, attempt, path: path.to_path_buf(), reason })
+        };
+        let write_error = |path: &Path, e: io::Error| {
+            let reason = e.to_string();
+            JobResult::Failed(task, attempt, MapReduceError::WriteOutput { task, attempt, path: path.to_path_buf(), reason })
+        };
+        let records = self.records(split).map_err(|e| read_error(&split.path, e))?;
+        let quarantine_path = quarantine_attempt_name(&self.working_directory, job_id, attempt);
+        let mut quarantine = File::create(&quarantine_path).map_err(|e| write_error(&quarantine_path, e))?;
+        for record in records {
+            let record = record.map_err(|e| read_error(&split.path, e))?;
+            let offset = record.offset;
+            if skipped.contains(&offset) {
+                quarantine.write_all(format!("{}\t", offset).as_bytes())
+                          .and_then(|_| quarantine.write_all(&record.bytes))
+                          .and_then(|_| quarantine.write_all(b"\n"))
+                          .map_err(|e| write_error(&quarantine_path, e))?;
+                continue;
+            }
+            let mapped = panic::catch_unwind(AssertUnwindSafe(|| {
+                self.mapper.map(&mut iter::once(record), &mut Emitter::new(&mut |key, value| output.push(key, value)))
+            }));
+            // An error returned by the mapper is left to the failure policy;
+            // only a record that brings the mapper down is pinned on its offset.
+            return match mapped {
+                Ok(Ok(())) => continue,
+                Ok(Err(e)) => Err(JobResult::FunctionFailed(task, attempt, e.to_string())),
+                Err(cause) => {
+                    let error = MapReduceError::Panicked { task, attempt, message: panic_message(cause) };
+                    Err(JobResult::RecordFailed(task, attempt, offset, error))
+                }
+            };
+        }
+        Ok(())
     }
 
-    fn write_map_results(&self, names: Vec<PathBuf>, results: Vec<String>) {
-        for (filename, result) in names.iter().zip(results.into_iter()) {
-            let mut f = File::create(filename).unwrap();
-            let _ = f.write_all(&result.as_bytes());
+    /// Opens a split with its input's format, reading it as text lines if
+    /// none was given.
+    fn records(&self, split: &InputSplit) -> io::Result<Records> {
+        match self.input_formats.get(&split.path) {
+            Some(format) => format.records(split),
+            None => TextLines.records(split)
         }
     }
 
-    fn reduce_result_name(&self, job_id: &i32) -> PathBuf {
-        let mut path = self.working_directory.clone();
-        path.push(format!("reduce.{}.result", job_id));
-        path
+    /// A buffer for a map attempt's output, spilling to the files named by
+    /// `spill_name` once it holds more than `map_buffer_size` bytes.
+    fn spill_buffer<'a>(&'a self, spill_name: &'a dyn Fn(usize, usize) -> PathBuf) -> SpillBuffer<'a, M::Key, M::Value> {
+        SpillBuffer::new(&*self.partitioner,
+                         self.combiner.as_deref(),
+                         self.n_reduce,
+                         self.map_buffer_size,
+                         self.intermediate_codec,
+                         spill_name)
     }
 
-    fn write_reduce_results(&self, name: PathBuf, result: String) {
-        let mut f = File::create(name).unwrap();
-        let _ = f.write_all(&result.as_bytes());
+    fn write_map_results(&self,
+                         job_id: i32,
+                         attempt: u32,
+                         output: SpillBuffer<M::Key, M::Value>
+                         ) -> Result<Vec<PathBuf>, MapReduceError>
+    {
+        output.finish(&|partition| map_attempt_name(&self.working_directory, job_id, attempt, partition))
+              .map_err(|(path, e)| write_error(Task::Map(job_id), attempt, path, e))
+    }
+
+    /// Merges the map outputs as they are read and reduces each key as soon
+    /// as its values come up, writing its output straight away. Returns the
+    /// result file, or any failure as the result to report.
+    fn reduce_partition(&self, job_id: i32, attempt: u32, paths: &[PathBuf]) -> Result<PathBuf, JobResult> {
+        let task = Task::Reduce(job_id);
+        let read_failure = |path: &PathBuf, e: io::Error| {
+            let reason = e.to_string();
+            if is_corrupt(&e) {
+                let error = MapReduceError::CorruptIntermediate { task, attempt, path: path.clone(), reason };
+                JobResult::CorruptInput(task, attempt, path.clone(), error)
+            } else {
+                JobResult::Failed(task, attempt, MapReduceError::ReadInput { task, attempt, path: path.clone(), reason })
+            }
+        };
+        let mut merge = merge_map_results::<M::Key, M::Value>(paths).map_err(|(path, e)| read_failure(path, e))?;
+
+        let path = reduce_attempt_name(&self.working_directory, job_id, attempt);
+        let write_failure = |e: io::Error| JobResult::Failed(task, attempt, write_error(task, attempt, path.clone(), e));
+        let f = File::create(&path).map_err(write_failure)?;
+        let mut writer = self.output_codec.encoder(BufWriter::new(f)).map_err(write_failure)?;
+        merge.for_each_group(|key, values| {
+            let output = self.reducer.reduce(key, values)
+                                     .map_err(|e| JobResult::FunctionFailed(task, attempt, e.to_string()))?;
+            self.output_format.write_record(&mut writer, key, &output).map_err(write_failure)
+        })?;
+        if let Some((run, e)) = merge.take_error() {
+            return Err(read_failure(&paths[run], e));
+        }
+        writer.finish()
+              .and_then(|mut writer| writer.flush())
+              .map_err(write_failure)?;
+        Ok(path)
+    }
+}
+
+/// A reduce task's map outputs, merged as they are read.
+type MapOutputs<K, V> = Merge<K, V, RunReader<K, V, BufReader<File>>>;
+
+/// Opens every map output for a merge that reads them a block at a time, or
+/// returns the first file that could not be opened.
+fn merge_map_results<K, V>(paths: &[PathBuf]) -> Result<MapOutputs<K, V>, (&PathBuf, io::Error)>
+    where K: Datum + Ord, V: Datum
+{
+    let runs = paths.iter()
+                    .map(|path| open_file(path).and_then(RunReader::new).map_err(|e| (path, e)))
+                    .collect::<Result<Vec<RunReader<K, V, BufReader<File>>>, _>>()?;
+    Ok(Merge::streaming(runs))
+}
+
+fn write_error(task: Task, attempt: u32, path: PathBuf, e: io::Error) -> MapReduceError {
+    MapReduceError::WriteOutput { task, attempt, path, reason: e.to_string() }
+}
+
+fn panic_message(cause: Box<dyn Any + Send>) -> String {
+    match cause.downcast::<String>() {
+        Ok(message) => *message,
+        Err(cause) => cause.downcast_ref::<&str>()
+                           .map(|message| message.to_string())
+                           .unwrap_or_else(|| "worker panicked".to_string())
     }
 }
 
-fn open_file(path: PathBuf) -> BufReader<File> {
-    let f = OpenOptions::new()
-                        .read(true)
-                        .open(path)
-                        .unwrap();
-    BufReader::new(f)
+fn open_file(path: &Path) -> io::Result<BufReader<File>> {
+    OpenOptions::new().read(true)
+                      .open(path)
+                      .map(BufReader::new)
 }
 
 #[cfg(test)]
 mod test {
-    use std::fs::OpenOptions;
-    use std::io::{ Write, BufRead, BufReader };
-    use std::fs::{ File, remove_file };
-    use std::path::PathBuf;
+    use std::convert::Infallible;
+    use std::io::{ BufRead, BufReader, Read };
+    use std::fs::{ File, OpenOptions, create_dir_all, read_dir, remove_file, remove_dir_all, write };
+    use std::path::{ Path, PathBuf };
     use std::thread;
     use std::sync::Arc;
+    use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
 
     use chan;
-    use chan::{ Sender, Receiver };
 
+    use compression::Codec;
+    use split::InputSplit;
+    use input::{ InputFormats, Record };
+    use output::{ KeyValueText, PlainText };
+    use functions::{ Mapper, Reducer, Emitter, ClosureMapper, ClosureReducer, BoxError };
+    use partitioner::{ Partitioner, BucketPartitioner, HashPartitioner };
+    use error::MapReduceError;
+    use task::Task;
+    use datum::Datum;
+    use intermediate::{ write_run, read_run };
     use super::{ Worker,
                  Job,
-                 JobResult
+                 JobResult,
+                 map_attempt_name,
+                 reduce_attempt_name
                };
 
-    fn map_fn(input: BufReader<File>) -> Vec<String> {
-        vec!["1", "2", "3", "4"].iter().map(|s| s.to_string()).collect()
+    struct Sum;
+
+    impl Mapper for Sum {
+        type Key = String;
+        type Value = u64;
+        type Error = Infallible;
+
+        fn map(&self, records: &mut dyn Iterator<Item = Record>, output: &mut Emitter<String, u64>) -> Result<(), Infallible> {
+            for record in records {
+                for word in String::from_utf8(record.bytes).unwrap().split_whitespace() {
+                    output.emit(word.to_lowercase(), 1);
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl Reducer for Sum {
+        type Key = String;
+        type Value = u64;
+        type Output = u64;
+        type Error = Infallible;
+
+        fn reduce(&self, _key: &String, values: &mut dyn Iterator<Item = u64>) -> Result<u64, Infallible> {
+            Ok(values.sum())
+        }
+    }
+
+    /// Emits a count for many distinct words, then looks for spill files
+    /// before returning.
+    struct SpillWatcher {
+        working_directory: PathBuf,
+        saw_spill: AtomicBool
+    }
+
+    impl Mapper for SpillWatcher {
+        type Key = String;
+        type Value = u64;
+        type Error = Infallible;
+
+        fn map(&self, _records: &mut dyn Iterator<Item = Record>, output: &mut Emitter<String, u64>) -> Result<(), Infallible> {
+            for n in 0..2_000 {
+                output.emit(format!("martian-{}", n), 1);
+            }
+            self.saw_spill.store(spill_files(&self.working_directory) > 0, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    fn spill_files(working_directory: &Path) -> usize {
+        read_dir(working_directory).unwrap()
+                                   .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().contains(".spill."))
+                                   .count()
+    }
+
+    static LIVE_COUNTS: AtomicUsize = AtomicUsize::new(0);
+    static PEAK_LIVE_COUNTS: AtomicUsize = AtomicUsize::new(0);
+
+    /// A count that keeps track of how many counts are in memory at once.
+    struct Tracked(u64);
+
+    impl Tracked {
+        fn new(count: u64) -> Tracked {
+            let live = LIVE_COUNTS.fetch_add(1, Ordering::SeqCst) + 1;
+            PEAK_LIVE_COUNTS.fetch_max(live, Ordering::SeqCst);
+            Tracked(count)
+        }
+    }
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            LIVE_COUNTS.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    impl Datum for Tracked {
+        fn encode(&self, buffer: &mut Vec<u8>) {
+            self.0.encode(buffer)
+        }
+
+        fn decode(bytes: &[u8]) -> Option<Self> {
+            u64::decode(bytes).map(Tracked::new)
+        }
+    }
+
+    struct TrackedSum;
+
+    impl Mapper for TrackedSum {
+        type Key = String;
+        type Value = Tracked;
+        type Error = Infallible;
+
+        fn map(&self, _records: &mut dyn Iterator<Item = Record>, _output: &mut Emitter<String, Tracked>) -> Result<(), Infallible> {
+            Ok(())
+        }
     }
-    fn reduce_fn(input: Vec<BufReader<File>>) -> String {
-        "1234".to_string()
+
+    impl Reducer for TrackedSum {
+        type Key = String;
+        type Value = Tracked;
+        type Output = u64;
+        type Error = Infallible;
+
+        fn reduce(&self, _key: &String, values: &mut dyn Iterator<Item = Tracked>) -> Result<u64, Infallible> {
+            Ok(values.map(|count| count.0).sum())
+        }
     }
 
+    /// The files a map attempt writes, one per partition.
+    fn map_attempt_files(working_directory: &Path, job_id: i32, attempt: u32, n_reduce: usize) -> Vec<PathBuf> {
+        (0..n_reduce).map(|partition| map_attempt_name(working_directory, job_id, attempt, partition)).collect()
+    }
+
+    fn map_fn(_records: &mut dyn Iterator<Item = Record>, output: &mut Emitter<usize, String>) -> Result<(), BoxError> {
+        for (bucket, contents) in ["1", "2", "3", "4"].iter().enumerate() {
+            output.emit(bucket, contents.to_string());
+        }
+        Ok(())
+    }
+    fn reduce_fn(_input: Vec<BufReader<File>>) -> Result<String, BoxError> {
+        Ok("1234".to_string())
+    }
 
     #[test]
     fn worker_maps_input_to_result_files() {
@@ -120,9 +516,18 @@ mod test {
         let (results_send, results_recv) = chan::async();
 
         let worker = Worker {
+            id: 1,
             working_directory: working_directory.clone(),
-            map: Arc::new(map_fn),
-            reduce: Arc::new(reduce_fn),
+            mapper: Arc::new(ClosureMapper::new(Arc::new(map_fn))),
+            reducer: Arc::new(ClosureReducer::new(Arc::new(reduce_fn), working_directory.clone())),
+            partitioner: Arc::new(BucketPartitioner),
+            combiner: None,
+            n_reduce: 4,
+            map_buffer_size: 1024 * 1024,
+            input_formats: Arc::new(InputFormats::new()),
+            output_format: Arc::new(PlainText),
+            intermediate_codec: Codec::None,
+            output_codec: Codec::None,
             job_queue: work_recv,
             results_queue: results_send
         };
@@ -131,36 +536,34 @@ mod test {
             worker.run()
         );
 
-        work_send.send(Job::Map((1, map_file.clone())));
+        work_send.send(Job::Map((1, 1, InputSplit::whole(map_file.clone()), None)));
+        assert_eq!(results_recv.recv(), Some(JobResult::Started(1, Task::Map(1), 1)));
         let done = results_recv.recv();
         drop(work_send);
         drop(results_recv);
 
-        assert_eq!(done, Some(JobResult::MapFinished(1)));
-        let expected_files = vec!["map.1.reduce.1",
-                                  "map.1.reduce.2",
-                                  "map.1.reduce.3",
-                                  "map.1.reduce.4"
+        assert_eq!(done, Some(JobResult::MapFinished(1, 1, map_attempt_files(&working_directory, 1, 1, 4))));
+        let expected_files = vec!["map.1.reduce.1.attempt.1.tmp",
+                                  "map.1.reduce.2.attempt.1.tmp",
+                                  "map.1.reduce.3.attempt.1.tmp",
+                                  "map.1.reduce.4.attempt.1.tmp"
                                  ];
 
         let contents = expected_files.iter()
                                      .flat_map(|name| {
                                         let mut path = working_directory.clone();
                                         path.push(name);
-                                        let contents;
-                                        {
-                                            let f = OpenOptions::new()
+                                        let mut f = OpenOptions::new()
                                                                 .read(true)
                                                                 .open(&path)
                                                                 .unwrap();
-                                            contents = BufReader::new(f).lines()
-                                                            .map(|l| l.unwrap_or("".to_string()))
-                                                            .collect::<Vec<String>>()
-                                        }
-                                        contents
+                                        read_run::<usize, String, _>(&mut f).unwrap()
                                      })
-                                     .collect::<Vec<String>>();
-        assert_eq!(contents, vec!["1", "2", "3", "4"]);
+                                     .collect::<Vec<(usize, String)>>();
+        assert_eq!(contents, vec![(0, "1".to_string()),
+                                  (1, "2".to_string()),
+                                  (2, "3".to_string()),
+                                  (3, "4".to_string())]);
 
         for name in expected_files {
             let mut path = working_directory.clone();
@@ -169,12 +572,63 @@ mod test {
         }
     }
 
+    #[test]
+    fn worker_partitions_map_output_by_key() {
+        let working_directory = PathBuf::from("./test-data/worker_maps_input_to_result_files");
+        let mut map_file = working_directory.clone();
+        map_file.push("input_file");
+
+        let (work_send, work_recv) = chan::async();
+        let (results_send, results_recv) = chan::async();
+        let worker = Worker {
+            id: 1,
+            working_directory: working_directory.clone(),
+            mapper: Arc::new(Sum),
+            reducer: Arc::new(Sum),
+            partitioner: Arc::new(HashPartitioner),
+            combiner: None,
+            n_reduce: 3,
+            map_buffer_size: 1024 * 1024,
+            input_formats: Arc::new(InputFormats::new()),
+            output_format: Arc::new(KeyValueText),
+            intermediate_codec: Codec::None,
+            output_codec: Codec::None,
+            job_queue: work_recv,
+            results_queue: results_send
+        };
+        thread::spawn(move ||
+            worker.run()
+        );
+
+        work_send.send(Job::Map((2, 3, InputSplit::whole(map_file), None)));
+        assert_eq!(results_recv.recv(), Some(JobResult::Started(1, Task::Map(2), 3)));
+        assert_eq!(results_recv.recv(), Some(JobResult::MapFinished(2, 3, map_attempt_files(&working_directory, 2, 3, 3))));
+
+        let mut words = vec![];
+        for partition in 0..3 {
+            let mut path = working_directory.clone();
+            path.push(format!("map.2.reduce.{}.attempt.3.tmp", partition + 1));
+            let pairs = read_run::<String, u64, _>(&mut File::open(&path).unwrap()).unwrap();
+            assert!(pairs.windows(2).all(|w| w[0].0 <= w[1].0));
+            for (word, _) in pairs {
+                assert_eq!(HashPartitioner.partition(&word, 3), partition);
+                words.push(word);
+            }
+            let _ = remove_file(path);
+        }
+        words.sort();
+        assert_eq!(words, vec!["brown", "dog.", "fox", "jumps", "lazy", "over", "quick", "the", "the"]);
+    }
+
     #[test]
     fn worker_reduces_input_to_result_file() {
         let working_directory = PathBuf::from("./test-data/worker_reduces_input_to_test_file");
+        create_dir_all(&working_directory).unwrap();
         let reduce_files = (1..5).map(|i| {
                                      let mut path = working_directory.clone();
                                      path.push(format!("map.{}.reduce.2", i));
+                                     let mut f = File::create(&path).unwrap();
+                                     write_run(&mut f, vec![(1usize, i.to_string())], Codec::None).unwrap();
                                      path
                                  })
                                  .collect::<Vec<PathBuf>>();
@@ -184,9 +638,18 @@ mod test {
         let (results_send, results_recv) = chan::async();
 
         let worker = Worker {
+            id: 1,
             working_directory: working_directory.clone(),
-            map: Arc::new(map_fn),
-            reduce: Arc::new(reduce_fn),
+            mapper: Arc::new(ClosureMapper::new(Arc::new(map_fn))),
+            reducer: Arc::new(ClosureReducer::new(Arc::new(reduce_fn), working_directory.clone())),
+            partitioner: Arc::new(BucketPartitioner),
+            combiner: None,
+            n_reduce: 4,
+            map_buffer_size: 1024 * 1024,
+            input_formats: Arc::new(InputFormats::new()),
+            output_format: Arc::new(PlainText),
+            intermediate_codec: Codec::None,
+            output_codec: Codec::None,
             job_queue: work_recv,
             results_queue: results_send
         };
@@ -195,15 +658,16 @@ mod test {
             worker.run()
         );
 
-        work_send.send(Job::Reduce((2, reduce_files)));
+        work_send.send(Job::Reduce((2, 1, reduce_files)));
+        assert_eq!(results_recv.recv(), Some(JobResult::Started(1, Task::Reduce(2), 1)));
         let done = results_recv.recv();
         drop(work_send);
         drop(results_recv);
 
-        assert_eq!(done, Some(JobResult::ReduceFinished(2)));
+        assert_eq!(done, Some(JobResult::ReduceFinished(2, 1, reduce_attempt_name(&working_directory, 2, 1))));
 
         let mut reduce_file = working_directory.clone();
-        reduce_file.push("reduce.2.result");
+        reduce_file.push("reduce.2.attempt.1.tmp");
         {
             let f = OpenOptions::new()
                                 .read(true)
@@ -216,6 +680,309 @@ mod test {
             assert_eq!(contents, vec!["1234".to_string()]);
         }
 
-        let _ = remove_file(reduce_file);
+        let _ = remove_dir_all(working_directory);
+    }
+
+    #[test]
+    fn worker_groups_values_by_key_across_map_files() {
+        let working_directory = PathBuf::from("./test-data/worker_groups_values_by_key");
+        create_dir_all(&working_directory).unwrap();
+        let map_outputs = [vec![("martian".to_string(), 2u64), ("tripod".to_string(), 1)],
+                               vec![("martian".to_string(), 3u64)]];
+        let reduce_files = map_outputs.iter()
+                                      .enumerate()
+                                      .map(|(i, pairs)| {
+                                          let mut path = working_directory.clone();
+                                          path.push(format!("map.{}.reduce.1", i + 1));
+                                          let mut f = File::create(&path).unwrap();
+                                          write_run(&mut f, pairs.clone(), Codec::None).unwrap();
+                                          path
+                                      })
+                                      .collect::<Vec<PathBuf>>();
+
+        let (work_send, work_recv) = chan::async();
+        let (results_send, results_recv) = chan::async();
+        let worker = Worker {
+            id: 1,
+            working_directory: working_directory.clone(),
+            mapper: Arc::new(Sum),
+            reducer: Arc::new(Sum),
+            partitioner: Arc::new(HashPartitioner),
+            combiner: None,
+            n_reduce: 1,
+            map_buffer_size: 1024 * 1024,
+            input_formats: Arc::new(InputFormats::new()),
+            output_format: Arc::new(KeyValueText),
+            intermediate_codec: Codec::None,
+            output_codec: Codec::None,
+            job_queue: work_recv,
+            results_queue: results_send
+        };
+        thread::spawn(move ||
+            worker.run()
+        );
+
+        work_send.send(Job::Reduce((1, 2, reduce_files)));
+        assert_eq!(results_recv.recv(), Some(JobResult::Started(1, Task::Reduce(1), 2)));
+        assert_eq!(results_recv.recv(), Some(JobResult::ReduceFinished(1, 2, reduce_attempt_name(&working_directory, 1, 2))));
+
+        let mut reduce_file = working_directory.clone();
+        reduce_file.push("reduce.1.attempt.2.tmp");
+        let contents = BufReader::new(File::open(&reduce_file).unwrap())
+                                 .lines()
+                                 .map(|l| l.unwrap())
+                                 .collect::<Vec<String>>();
+        assert_eq!(contents, vec!["martian=5", "tripod=1"]);
+
+        let _ = remove_dir_all(working_directory);
+    }
+
+    #[test]
+    fn worker_reports_errors_returned_by_user_functions() {
+        let working_directory = PathBuf::from("./test-data/worker_reports_function_errors");
+        create_dir_all(&working_directory).unwrap();
+        let input = working_directory.join("input_file");
+        write(&input, "the heat ray\n").unwrap();
+        let (work_send, work_recv) = chan::async();
+        let (results_send, results_recv) = chan::async();
+        let worker = Worker {
+            id: 2,
+            working_directory: working_directory.clone(),
+            mapper: Arc::new(ClosureMapper::new(Arc::new(|_, _| Err("malformed record".into())))),
+            reducer: Arc::new(ClosureReducer::new(Arc::new(reduce_fn), working_directory.clone())),
+            partitioner: Arc::new(BucketPartitioner),
+            combiner: None,
+            n_reduce: 1,
+            map_buffer_size: 1024 * 1024,
+            input_formats: Arc::new(InputFormats::new()),
+            output_format: Arc::new(PlainText),
+            intermediate_codec: Codec::None,
+            output_codec: Codec::None,
+            job_queue: work_recv,
+            results_queue: results_send
+        };
+        thread::spawn(move ||
+            worker.run()
+        );
+
+        work_send.send(Job::Map((4, 1, InputSplit::whole(input.clone()), None)));
+        assert_eq!(results_recv.recv(), Some(JobResult::Started(2, Task::Map(4), 1)));
+        assert_eq!(results_recv.recv(),
+                   Some(JobResult::FunctionFailed(Task::Map(4), 1, "malformed record".to_string())));
+
+        // Skipping bad records leaves errors to the failure policy.
+        work_send.send(Job::Map((4, 2, InputSplit::whole(input), Some(vec![]))));
+        assert_eq!(results_recv.recv(), Some(JobResult::Started(2, Task::Map(4), 2)));
+        assert_eq!(results_recv.recv(),
+                   Some(JobResult::FunctionFailed(Task::Map(4), 2, "malformed record".to_string())));
+
+        let _ = remove_dir_all(working_directory);
+    }
+
+    #[test]
+    fn worker_reports_the_record_it_failed_on() {
+        let working_directory = PathBuf::from("./test-data/worker_reports_failed_records");
+        create_dir_all(&working_directory).unwrap();
+        let input = working_directory.join("input_file");
+        write(&input, b"the heat ray\n\xff\xfe\nthe tripods\n").unwrap();
+        let (work_send, work_recv) = chan::async();
+        let (results_send, results_recv) = chan::async();
+        let worker = Worker {
+            id: 1,
+            working_directory: working_directory.clone(),
+            mapper: Arc::new(Sum),
+            reducer: Arc::new(Sum),
+            partitioner: Arc::new(HashPartitioner),
+            combiner: None,
+            n_reduce: 1,
+            map_buffer_size: 1024 * 1024,
+            input_formats: Arc::new(InputFormats::new()),
+            output_format: Arc::new(KeyValueText),
+            intermediate_codec: Codec::None,
+            output_codec: Codec::None,
+            job_queue: work_recv,
+            results_queue: results_send
+        };
+        thread::spawn(move ||
+            worker.run()
+        );
+
+        // `Sum` panics on the line that is not UTF-8, at offset 13.
+        work_send.send(Job::Map((1, 1, InputSplit::whole(input.clone()), Some(vec![]))));
+        assert_eq!(results_recv.recv(), Some(JobResult::Started(1, Task::Map(1), 1)));
+        match results_recv.recv() {
+            Some(JobResult::RecordFailed(Task::Map(1), 1, 13, MapReduceError::Panicked { .. })) => {}
+            other => panic!("expected a failed record, got {:?}", other)
+        }
+
+        work_send.send(Job::Map((1, 2, InputSplit::whole(input), Some(vec![13]))));
+        assert_eq!(results_recv.recv(), Some(JobResult::Started(1, Task::Map(1), 2)));
+        assert_eq!(results_recv.recv(), Some(JobResult::MapFinished(1, 2, map_attempt_files(&working_directory, 1, 2, 1))));
+
+        let mut quarantine = vec![];
+        File::open(working_directory.join("map.1.quarantine.attempt.2.tmp")).unwrap()
+                                                                            .read_to_end(&mut quarantine)
+                                                                            .unwrap();
+        assert_eq!(quarantine, b"13\t\xff\xfe\n".to_vec());
+        let path = working_directory.join("map.1.reduce.1.attempt.2.tmp");
+        let pairs = read_run::<String, u64, _>(&mut File::open(&path).unwrap()).unwrap();
+        assert_eq!(pairs.len(), 5);
+
+        let _ = remove_dir_all(working_directory);
+    }
+
+    #[test]
+    fn worker_reports_failed_jobs_and_keeps_running() {
+        let working_directory = PathBuf::from("./test-data/worker_reports_panicking_jobs");
+        let (work_send, work_recv) = chan::async();
+        let (results_send, results_recv) = chan::async();
+        let worker = Worker {
+            id: 3,
+            working_directory: working_directory.clone(),
+            mapper: Arc::new(Sum),
+            reducer: Arc::new(Sum),
+            partitioner: Arc::new(HashPartitioner),
+            combiner: None,
+            n_reduce: 1,
+            map_buffer_size: 1024 * 1024,
+            input_formats: Arc::new(InputFormats::new()),
+            output_format: Arc::new(KeyValueText),
+            intermediate_codec: Codec::None,
+            output_codec: Codec::None,
+            job_queue: work_recv,
+            results_queue: results_send
+        };
+        thread::spawn(move ||
+            worker.run()
+        );
+
+        let missing_input = working_directory.join("missing_input");
+        work_send.send(Job::Map((1, 1, InputSplit::whole(missing_input.clone()), None)));
+        assert_eq!(results_recv.recv(), Some(JobResult::Started(3, Task::Map(1), 1)));
+        match results_recv.recv() {
+            Some(JobResult::Failed(Task::Map(1), 1, MapReduceError::ReadInput { task, attempt, path, reason })) => {
+                assert_eq!((task, attempt, path), (Task::Map(1), 1, missing_input));
+                assert!(reason.contains("No such file"));
+            }
+            other => panic!("expected an unreadable input, got {:?}", other)
+        }
+
+        // `Sum` unwraps every line, so input that is not UTF-8 makes it panic.
+        create_dir_all(&working_directory).unwrap();
+        let binary_input = working_directory.join("binary_input");
+        write(&binary_input, [0xff, 0xfe, b'\n']).unwrap();
+        work_send.send(Job::Map((2, 1, InputSplit::whole(binary_input), None)));
+        assert_eq!(results_recv.recv(), Some(JobResult::Started(3, Task::Map(2), 1)));
+        match results_recv.recv() {
+            Some(JobResult::Failed(Task::Map(2), 1, MapReduceError::Panicked { task, attempt, .. })) => {
+                assert_eq!((task, attempt), (Task::Map(2), 1));
+            }
+            other => panic!("expected a panicking map task, got {:?}", other)
+        }
+
+        work_send.send(Job::Reduce((1, 2, vec![])));
+        assert_eq!(results_recv.recv(), Some(JobResult::Started(3, Task::Reduce(1), 2)));
+        assert_eq!(results_recv.recv(), Some(JobResult::ReduceFinished(1, 2, reduce_attempt_name(&working_directory, 1, 2))));
+
+        let _ = remove_dir_all(working_directory);
+    }
+
+    #[test]
+    fn reduce_streams_a_partition_larger_than_its_memory_cap() {
+        // Far fewer counts than the partition holds, but room for a block of
+        // each map output.
+        const MEMORY_CAP: usize = 20_000;
+        let working_directory = PathBuf::from("./test-data/worker_streams_reduce_input");
+        create_dir_all(&working_directory).unwrap();
+        let reduce_files = (1..3).map(|map_id| {
+                                     let path = working_directory.join(format!("map.{}.reduce.1", map_id));
+                                     let pairs = (0..150_000).map(|n| (format!("key-{}", n / 50_000), Tracked::new(1)));
+                                     write_run(File::create(&path).unwrap(), pairs, Codec::None).unwrap();
+                                     path
+                                 })
+                                 .collect::<Vec<PathBuf>>();
+        PEAK_LIVE_COUNTS.store(LIVE_COUNTS.load(Ordering::SeqCst), Ordering::SeqCst);
+
+        let (work_send, work_recv) = chan::async();
+        let (results_send, results_recv) = chan::async();
+        let worker = Worker {
+            id: 1,
+            working_directory: working_directory.clone(),
+            mapper: Arc::new(TrackedSum),
+            reducer: Arc::new(TrackedSum),
+            partitioner: Arc::new(HashPartitioner),
+            combiner: None,
+            n_reduce: 1,
+            map_buffer_size: 1024 * 1024,
+            input_formats: Arc::new(InputFormats::new()),
+            output_format: Arc::new(KeyValueText),
+            intermediate_codec: Codec::None,
+            output_codec: Codec::None,
+            job_queue: work_recv,
+            results_queue: results_send
+        };
+        thread::spawn(move ||
+            worker.run()
+        );
+
+        work_send.send(Job::Reduce((1, 1, reduce_files)));
+        assert_eq!(results_recv.recv(), Some(JobResult::Started(1, Task::Reduce(1), 1)));
+        assert_eq!(results_recv.recv(), Some(JobResult::ReduceFinished(1, 1, reduce_attempt_name(&working_directory, 1, 1))));
+
+        let contents = BufReader::new(File::open(working_directory.join("reduce.1.attempt.1.tmp")).unwrap())
+                                 .lines()
+                                 .map(|l| l.unwrap())
+                                 .collect::<Vec<String>>();
+        assert_eq!(contents, vec!["key-0=100000", "key-1=100000", "key-2=100000"]);
+        let peak = PEAK_LIVE_COUNTS.load(Ordering::SeqCst);
+        assert!(peak < MEMORY_CAP, "{} counts were in memory at once", peak);
+
+        let _ = remove_dir_all(working_directory);
+    }
+
+    #[test]
+    fn emitted_pairs_are_spilled_while_the_mapper_runs() {
+        let working_directory = PathBuf::from("./test-data/worker_spills_emitted_pairs");
+        create_dir_all(&working_directory).unwrap();
+        let input = working_directory.join("input_file");
+        write(&input, "the heat ray\n").unwrap();
+        let mapper = Arc::new(SpillWatcher { working_directory: working_directory.clone(), saw_spill: AtomicBool::new(false) });
+
+        let (work_send, work_recv) = chan::async();
+        let (results_send, results_recv) = chan::async();
+        let worker = Worker {
+            id: 1,
+            working_directory: working_directory.clone(),
+            mapper: mapper.clone(),
+            reducer: Arc::new(Sum),
+            partitioner: Arc::new(HashPartitioner),
+            combiner: None,
+            n_reduce: 2,
+            map_buffer_size: 1024,
+            input_formats: Arc::new(InputFormats::new()),
+            output_format: Arc::new(KeyValueText),
+            intermediate_codec: Codec::None,
+            output_codec: Codec::None,
+            job_queue: work_recv,
+            results_queue: results_send
+        };
+        thread::spawn(move ||
+            worker.run()
+        );
+
+        work_send.send(Job::Map((1, 1, InputSplit::whole(input), None)));
+        assert_eq!(results_recv.recv(), Some(JobResult::Started(1, Task::Map(1), 1)));
+        assert_eq!(results_recv.recv(), Some(JobResult::MapFinished(1, 1, map_attempt_files(&working_directory, 1, 1, 2))));
+
+        assert!(mapper.saw_spill.load(Ordering::SeqCst));
+        assert_eq!(spill_files(&working_directory), 0);
+        let n_pairs = (1..3).map(|partition| {
+                                let path = working_directory.join(format!("map.1.reduce.{}.attempt.1.tmp", partition));
+                                read_run::<String, u64, _>(&mut File::open(path).unwrap()).unwrap().len()
+                            })
+                            .sum::<usize>();
+        assert_eq!(n_pairs, 2_000);
+
+        let _ = remove_dir_all(working_directory);
     }
 }